@@ -0,0 +1,88 @@
+use config::rule::{Action, ProxyRules, Rule};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const RULES: usize = 30_000;
+
+fn generate_rules() -> Vec<Rule> {
+    let mut rules = Vec::with_capacity(RULES + 1);
+    for i in 0..RULES {
+        let action = if i % 2 == 0 {
+            Action::Direct
+        } else {
+            Action::Proxy
+        };
+        let rule = match i % 10 {
+            0 => Rule::Domain(format!("www.domain{}.com", i), action),
+            1 => Rule::DomainKeyword(format!("keyword{}x", i), action),
+            _ => Rule::DomainSuffix(format!("suffix{}.net", i), action),
+        };
+        rules.push(rule);
+    }
+    rules.push(Rule::Match(Action::Probe));
+    rules
+}
+
+/// The linear scan `ProxyRules` used before rules were indexed, matching the same way as the
+/// index: case-insensitively, ignoring the trailing dot, and `DOMAIN-SUFFIX` only on label
+/// boundaries. Generated rules are already lowercase ASCII, so only the domain is normalized.
+fn linear_action_for_domain(rules: &[Rule], domain: &str) -> Option<Action> {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    rules
+        .iter()
+        .filter_map(|rule| match rule {
+            Rule::Domain(d, action) if *d == domain => Some(action.clone()),
+            Rule::DomainSuffix(d, action) if is_subdomain(&domain, d) => Some(action.clone()),
+            Rule::DomainKeyword(d, action) if domain.contains(d.as_str()) => Some(action.clone()),
+            Rule::Match(action) => Some(action.clone()),
+            _ => None,
+        })
+        .next()
+}
+
+fn is_subdomain(domain: &str, suffix: &str) -> bool {
+    domain == suffix
+        || (domain.ends_with(suffix) && domain[..domain.len() - suffix.len()].ends_with('.'))
+}
+
+fn bench_rules(c: &mut Criterion) {
+    let rules = generate_rules();
+    let proxy_rules = ProxyRules::new(rules.clone()).unwrap();
+    let domains = [
+        "www.domain10.com",
+        "a.b.suffix29995.net",
+        "keyword15001x.example.org",
+        "not-matched.example.org",
+    ];
+
+    // Domains which only match on the raw string suffix, or after normalizing, have to give the
+    // same action as well.
+    let equivalent = [
+        "xsuffix29995.net",
+        "A.Suffix29995.NET.",
+        "WWW.DOMAIN10.COM.",
+    ];
+    for domain in domains.iter().chain(&equivalent) {
+        assert_eq!(
+            proxy_rules.action_for_domain(domain, None, None),
+            linear_action_for_domain(&rules, domain)
+        );
+    }
+
+    c.bench_function("linear action_for_domain", |b| {
+        b.iter(|| {
+            for domain in &domains {
+                black_box(linear_action_for_domain(&rules, black_box(domain)));
+            }
+        })
+    });
+    c.bench_function("indexed action_for_domain", |b| {
+        b.iter(|| {
+            for domain in &domains {
+                black_box(proxy_rules.action_for_domain(black_box(domain), None, None));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, bench_rules);
+criterion_main!(benches);
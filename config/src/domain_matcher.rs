@@ -0,0 +1,165 @@
+use crate::rule::Rule;
+use aho_corasick::AhoCorasick;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Index over `DOMAIN`, `DOMAIN-SUFFIX` and `DOMAIN-KEYWORD` rules.
+///
+/// Domains are compared case-insensitively, ignoring the trailing dot, with internationalized
+/// names converted to punycode. `DOMAIN-SUFFIX` only matches at label boundaries, so
+/// `apple.co` matches `apple.co` and `www.apple.co` but not `pineapple.co`.
+///
+/// Every entry remembers the position of its rule, so that when several rules match a domain
+/// the one defined first wins, the same as scanning the rules in order.
+#[derive(Default)]
+pub(crate) struct DomainMatcher {
+    domains: HashMap<String, usize>,
+    suffixes: SuffixNode,
+    keywords: Option<AhoCorasick>,
+    keyword_rules: Vec<usize>,
+}
+
+/// Trie keyed by domain labels from right to left, eg. `www.apple.com` is stored as
+/// `com` -> `apple` -> `www`.
+#[derive(Default)]
+struct SuffixNode {
+    children: HashMap<String, SuffixNode>,
+    rule: Option<usize>,
+}
+
+impl DomainMatcher {
+    pub(crate) fn new(rules: &[Rule]) -> Self {
+        let mut matcher = DomainMatcher::default();
+        let mut keywords = vec![];
+        for (index, rule) in rules.iter().enumerate() {
+            match rule {
+                Rule::Domain(domain, _) => {
+                    matcher
+                        .domains
+                        .entry(normalize_domain(domain).into_owned())
+                        .or_insert(index);
+                }
+                Rule::DomainSuffix(suffix, _) => {
+                    let suffix = normalize_domain(suffix.trim_start_matches('.'));
+                    let node = suffix
+                        .rsplit('.')
+                        .fold(&mut matcher.suffixes, |node, label| {
+                            node.children.entry(label.to_string()).or_default()
+                        });
+                    node.rule.get_or_insert(index);
+                }
+                Rule::DomainKeyword(keyword, _) => {
+                    keywords.push(keyword.to_lowercase());
+                    matcher.keyword_rules.push(index);
+                }
+                _ => {}
+            }
+        }
+        if !keywords.is_empty() {
+            matcher.keywords = Some(AhoCorasick::new(&keywords));
+        }
+        matcher
+    }
+
+    /// Returns the position of the first rule matching `domain`.
+    pub(crate) fn find(&self, domain: &str) -> Option<usize> {
+        let domain = normalize_domain(domain);
+        let domain = domain.as_ref();
+        let exact = self.domains.get(domain).copied();
+
+        let mut suffix = None;
+        let mut node = &self.suffixes;
+        for label in domain.rsplit('.') {
+            node = match node.children.get(label) {
+                Some(n) => n,
+                None => break,
+            };
+            suffix = min_index(suffix, node.rule);
+        }
+
+        let keyword = self.keywords.as_ref().and_then(|ac| {
+            ac.find_overlapping_iter(domain)
+                .map(|m| self.keyword_rules[m.pattern()])
+                .min()
+        });
+
+        min_index(min_index(exact, suffix), keyword)
+    }
+}
+
+/// Lowercase `domain`, strip the trailing dot and convert internationalized labels to punycode.
+///
+/// Most domains are already lowercase ASCII, they are borrowed without allocating.
+fn normalize_domain(domain: &str) -> Cow<'_, str> {
+    let domain = domain.trim_end_matches('.');
+    if !domain.is_ascii() {
+        return Cow::Owned(idna::domain_to_ascii(domain).unwrap_or_else(|_| domain.to_lowercase()));
+    }
+    if domain.bytes().any(|b| b.is_ascii_uppercase()) {
+        Cow::Owned(domain.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(domain)
+    }
+}
+
+fn min_index(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn matcher(rules: &[&str]) -> DomainMatcher {
+        let rules: Vec<Rule> = rules.iter().map(|r| Rule::from_str(r).unwrap()).collect();
+        DomainMatcher::new(&rules)
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let m = matcher(&[
+            "DOMAIN-KEYWORD,google,PROBE",
+            "DOMAIN-SUFFIX,google.com,PROXY",
+            "DOMAIN,www.google.com,DIRECT",
+            "DOMAIN-SUFFIX,apple.com,DIRECT",
+            "DOMAIN,www.apple.com,REJECT",
+            "DOMAIN-SUFFIX,com,PROXY",
+        ]);
+        assert_eq!(m.find("www.google.com"), Some(0));
+        assert_eq!(m.find("www.apple.com"), Some(3));
+        assert_eq!(m.find("apple.com"), Some(3));
+        assert_eq!(m.find("example.com"), Some(5));
+        assert_eq!(m.find("example.org"), None);
+    }
+
+    #[test]
+    fn test_nested_suffix() {
+        let m = matcher(&[
+            "DOMAIN-SUFFIX,cdn.example.com,DIRECT",
+            "DOMAIN-SUFFIX,example.com,PROXY",
+        ]);
+        assert_eq!(m.find("a.cdn.example.com"), Some(0));
+        assert_eq!(m.find("www.example.com"), Some(1));
+    }
+
+    #[test]
+    fn test_overlapping_keywords() {
+        let m = matcher(&["DOMAIN-KEYWORD,ogle,DIRECT", "DOMAIN-KEYWORD,goo,PROXY"]);
+        assert_eq!(m.find("google.com"), Some(0));
+        assert_eq!(m.find("goo.gl"), Some(1));
+    }
+
+    #[test]
+    fn test_normalize_domain() {
+        assert!(match normalize_domain("www.apple.com.") {
+            Cow::Borrowed("www.apple.com") => true,
+            _ => false,
+        });
+        assert_eq!(normalize_domain("WWW.Apple.com"), "www.apple.com");
+        assert_eq!(normalize_domain("例子.测试"), "xn--fsqu00a.xn--0zwm56d");
+    }
+}
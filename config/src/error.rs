@@ -0,0 +1,93 @@
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+/// Errors raised while loading a config file
+#[derive(Debug)]
+pub enum Error {
+    /// Config file or a file referenced by it can not be read
+    Io(PathBuf, io::Error),
+    /// Invalid yaml, or a field fails to deserialize. The message carries the line and column.
+    Yaml(serde_yaml::Error),
+    /// Invalid SIP008 json file
+    Json(PathBuf, serde_json::Error),
+    /// GeoIP database can not be loaded
+    GeoIp(PathBuf, maxminddb::MaxMindDBError),
+    /// Rule provider with the name can not be loaded
+    RuleProvider(String, Box<Error>),
+    /// Invalid rule, eg. `DOMAIN-SUFFIX,DIRECT`
+    InvalidRule { rule: String, reason: String },
+    /// Empty action
+    InvalidAction(String),
+    /// Rule action is neither a builtin action nor a server or proxy group name
+    UnknownProxy(String),
+    /// Invalid proxy group, eg. it contains an unknown server
+    InvalidProxyGroup { name: String, reason: String },
+    /// Invalid cidr, eg. `10.0.0.0`
+    InvalidCidr(String),
+    /// Invalid IPv6 options, eg. `tun_ipv6` is not in `tun_ipv6_cidr`
+    InvalidIpv6(String),
+    /// Invalid duration, eg. `10m`
+    InvalidDuration(String),
+    /// Invalid url, eg. `https://www.gstatic.com`
+    InvalidUrl(String),
+    /// Invalid `ss://` url
+    InvalidServerUrl { url: String, reason: String },
+    /// Invalid server, eg. its method is unknown
+    InvalidServer { name: String, reason: String },
+    /// Buffer size option too small, eg. `relay_buffer_size: 0`
+    InvalidBufferSize { name: String, reason: String },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::Yaml(e) => write!(f, "{}", e),
+            Error::Json(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::GeoIp(path, e) => write!(f, "{}: {:?}", path.display(), e),
+            Error::RuleProvider(name, e) => write!(f, "rule provider {}: {}", name, e),
+            Error::InvalidRule { rule, reason } => write!(f, "invalid rule `{}`: {}", rule, reason),
+            Error::InvalidAction(action) => write!(
+                f,
+                "invalid action `{}`, expected REJECT, DIRECT, PROXY, PROBE or a proxy name",
+                action
+            ),
+            Error::UnknownProxy(name) => write!(
+                f,
+                "unknown action `{}`, expected REJECT, DIRECT, PROXY, PROBE, a server name or a proxy group name",
+                name
+            ),
+            Error::InvalidProxyGroup { name, reason } => {
+                write!(f, "invalid proxy group `{}`: {}", name, reason)
+            }
+            Error::InvalidCidr(cidr) => {
+                write!(f, "invalid cidr `{}`, expected 10.0.0.0/16 or fd00::/64", cidr)
+            }
+            Error::InvalidIpv6(reason) => write!(f, "invalid ipv6 options: {}", reason),
+            Error::InvalidDuration(duration) => {
+                write!(f, "invalid duration `{}`, expected 10s or 10ms", duration)
+            }
+            Error::InvalidUrl(url) => {
+                write!(f, "invalid url `{}`, expected http://host[:port]/path", url)
+            }
+            Error::InvalidServerUrl { url, reason } => {
+                write!(f, "invalid server url `{}`: {}", url, reason)
+            }
+            Error::InvalidServer { name, reason } => {
+                write!(f, "invalid server `{}`: {}", name, reason)
+            }
+            Error::InvalidBufferSize { name, reason } => {
+                write!(f, "invalid `{}`: {}", name, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(e: serde_yaml::Error) -> Self {
+        Error::Yaml(e)
+    }
+}
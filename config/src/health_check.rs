@@ -0,0 +1,189 @@
+use crate::{duration, Address, Error};
+use serde::{Deserialize, Deserializer};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Request sent through every server in `server_configs` to measure its latency and
+/// whether it is alive.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthCheck {
+    /// Url requested through the server, only `http://` is supported
+    #[serde(deserialize_with = "deserialize_url", default = "default_url")]
+    pub url: HttpUrl,
+    /// How often every server is checked
+    #[serde(with = "duration", default = "default_interval")]
+    pub interval: Duration,
+    /// A check fails when there is no response in time
+    #[serde(with = "duration", default = "default_timeout")]
+    pub timeout: Duration,
+}
+
+/// Plain http url, eg. `http://www.gstatic.com/generate_204`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HttpUrl {
+    /// Domain or IP, IPv6 addresses are kept without brackets
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl HttpUrl {
+    pub fn address(&self) -> Address {
+        match self.host.parse::<IpAddr>() {
+            Ok(ip) => Address::SocketAddress(SocketAddr::new(ip, self.port)),
+            Err(_) => Address::DomainNameAddress(self.host.clone(), self.port),
+        }
+    }
+
+    /// `HEAD` request for the url
+    pub fn request(&self) -> Vec<u8> {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        format!(
+            "HEAD {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            self.path, host
+        )
+        .into_bytes()
+    }
+}
+
+impl FromStr for HttpUrl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidUrl(s.to_string());
+        if !s.starts_with("http://") {
+            return Err(invalid());
+        }
+        let rest = &s["http://".len()..];
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = if authority.starts_with('[') {
+            // IPv6 literal, eg. `[::1]:8080`
+            let end = authority.find(']').ok_or_else(invalid)?;
+            let host = &authority[1..end];
+            host.parse::<Ipv6Addr>().map_err(|_| invalid())?;
+            let port = match &authority[end + 1..] {
+                "" => 80,
+                port if port.starts_with(':') => port[1..].parse().map_err(|_| invalid())?,
+                _ => return Err(invalid()),
+            };
+            (host, port)
+        } else {
+            // IPv6 hosts without brackets can't be told apart from the port
+            if authority.matches(':').count() > 1 {
+                return Err(invalid());
+            }
+            match authority.rfind(':') {
+                Some(i) => (
+                    &authority[..i],
+                    authority[i + 1..].parse().map_err(|_| invalid())?,
+                ),
+                None => (authority, 80),
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(HttpUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        HealthCheck {
+            url: default_url(),
+            interval: default_interval(),
+            timeout: default_timeout(),
+        }
+    }
+}
+
+fn deserialize_url<'de, D>(deserializer: D) -> Result<HttpUrl, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    HttpUrl::from_str(&s).map_err(serde::de::Error::custom)
+}
+
+fn default_url() -> HttpUrl {
+    HttpUrl::from_str("http://www.gstatic.com/generate_204").expect("valid url")
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(300)
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            HttpUrl::from_str("http://www.gstatic.com/generate_204").unwrap(),
+            HttpUrl {
+                host: "www.gstatic.com".to_string(),
+                port: 80,
+                path: "/generate_204".to_string(),
+            }
+        );
+        let url = HttpUrl::from_str("http://1.1.1.1:8080").unwrap();
+        assert_eq!(url.path, "/");
+        assert_eq!(
+            url.address(),
+            Address::SocketAddress("1.1.1.1:8080".parse().unwrap())
+        );
+        assert!(HttpUrl::from_str("https://www.gstatic.com").is_err());
+        assert!(HttpUrl::from_str("http://:80/").is_err());
+        assert!(HttpUrl::from_str("http://example.com:http/").is_err());
+    }
+
+    #[test]
+    fn test_parse_ipv6_url() {
+        let url = HttpUrl::from_str("http://[::1]:8080/generate_204").unwrap();
+        assert_eq!(url.host, "::1");
+        assert_eq!(url.path, "/generate_204");
+        assert_eq!(
+            url.address(),
+            Address::SocketAddress("[::1]:8080".parse().unwrap())
+        );
+        assert_eq!(
+            url.request(),
+            b"HEAD /generate_204 HTTP/1.1\r\nHost: [::1]\r\nConnection: close\r\n\r\n".to_vec()
+        );
+        let url = HttpUrl::from_str("http://[fd00::1]").unwrap();
+        assert_eq!(url.port, 80);
+        assert!(HttpUrl::from_str("http://[::1/").is_err());
+        assert!(HttpUrl::from_str("http://[example.com]:80/").is_err());
+        assert!(HttpUrl::from_str("http://[::1]8080/").is_err());
+        assert!(HttpUrl::from_str("http://::1:8080/").is_err());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let check: HealthCheck = serde_yaml::from_str("url: http://example.com/ping").unwrap();
+        assert_eq!(check.url.host, "example.com");
+        assert_eq!(check.interval, Duration::from_secs(300));
+        assert_eq!(check.timeout, Duration::from_secs(5));
+        assert_eq!(
+            check.url.request(),
+            b"HEAD /ping HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n".to_vec()
+        );
+    }
+}
@@ -1,19 +1,34 @@
+mod domain_matcher;
+mod error;
+mod health_check;
+mod proxy_group;
 pub mod rule;
+mod rule_provider;
 mod server_config;
-mod socks5;
-pub use server_config::{ServerAddr, ServerConfig};
+mod sip008;
+pub mod socks5;
+pub use error::Error;
+pub use health_check::{HealthCheck, HttpUrl};
+pub use proxy_group::{ProxyGroup, ProxyGroupType};
+pub use rule_provider::{Behavior, RuleProvider};
+pub use server_config::{ServerAddr, ServerConfig, ServerType};
 pub use socks5::Address;
 
-use rule::ProxyRules;
+use crypto::CipherCategory;
+use rule::{Action, ProxyRules};
 use serde::Deserialize;
-use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+use smoltcp::wire::{Ipv4Address, Ipv4Cidr, Ipv6Address, Ipv6Cidr};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
+    /// Servers, each one is either a map or a `ss://` url.
+    #[serde(default, with = "server_configs")]
     pub server_configs: Arc<Vec<ServerConfig>>,
     pub dns_start_ip: Ipv4Addr,
     pub dns_server: SocketAddr,
@@ -34,10 +49,51 @@ pub struct Config {
     #[serde(with = "duration")]
     pub direct_write_timeout: Duration,
     pub max_connect_errors: usize,
+    /// Path to a MaxMind country database (mmdb) used by `GEOIP` rules.
+    #[serde(default)]
+    pub geo_ip: Option<PathBuf>,
+    /// Rule files referenced by `RULE-SET` rules.
+    #[serde(default)]
+    pub rule_providers: HashMap<String, RuleProvider>,
+    /// Groups of servers that rules can proxy through by name.
+    #[serde(default)]
+    pub proxy_groups: Vec<ProxyGroup>,
+    /// How servers are checked, the results are used by proxy groups.
+    #[serde(default)]
+    pub health_check: HealthCheck,
+    /// SIP008 json file with more servers, appended to `server_configs`.
+    #[serde(default)]
+    pub server_configs_file: Option<PathBuf>,
+    /// Accept socks5 connections on this address and dispatch them by `rules`.
+    #[serde(default)]
+    pub socks5_listen: Option<SocketAddr>,
+    /// Accept http proxy connections on this address and dispatch them by `rules`.
+    #[serde(default)]
+    pub http_listen: Option<SocketAddr>,
+    /// First fake IPv6 address returned in AAAA answers. IPv6 is disabled if not set.
+    #[serde(default)]
+    pub dns_start_ipv6: Option<Ipv6Addr>,
+    #[serde(default)]
+    pub tun_ipv6: Option<Ipv6Addr>,
+    #[serde(default, with = "ipv6_cidr")]
+    pub tun_ipv6_cidr: Option<Ipv6Cidr>,
+    /// How long connections in flight are given to finish on shutdown.
+    #[serde(with = "duration", default = "default_drain_timeout")]
+    pub drain_timeout: Duration,
+    /// Receive buffer size of each tcp connection in the tun netstack, in bytes.
+    #[serde(default = "default_tun_tcp_buffer_size")]
+    pub tun_tcp_rx_buffer_size: usize,
+    /// Send buffer size of each tcp connection in the tun netstack, in bytes.
+    #[serde(default = "default_tun_tcp_buffer_size")]
+    pub tun_tcp_tx_buffer_size: usize,
+    /// Size of the buffers copying data between relayed tcp connections, in bytes.
+    #[serde(default = "default_relay_buffer_size")]
+    pub relay_buffer_size: usize,
 }
 
 mod ipv4_cidr {
     use crate::parse_cidr;
+    use serde::de::Error;
     use serde::{Deserialize, Deserializer};
     use smoltcp::wire::Ipv4Cidr;
 
@@ -46,7 +102,7 @@ mod ipv4_cidr {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        Ok(parse_cidr(s))
+        parse_cidr(&s).map_err(Error::custom)
     }
 }
 
@@ -55,7 +111,7 @@ mod duration {
     use serde::{Deserialize, Deserializer};
     use std::time::Duration;
 
-    pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    pub fn parse_duration(s: &str) -> Result<Duration, crate::Error> {
         let mut num = Vec::with_capacity(100);
         let mut chars = Vec::with_capacity(100);
         for c in s.chars() {
@@ -65,11 +121,15 @@ mod duration {
                 chars.push(c);
             }
         }
-        let n: u64 = num.into_iter().collect::<String>().parse().unwrap();
+        let n: u64 = num
+            .into_iter()
+            .collect::<String>()
+            .parse()
+            .map_err(|_| crate::Error::InvalidDuration(s.to_string()))?;
         match chars.into_iter().collect::<String>().as_str() {
             "s" => Ok(Duration::from_secs(n)),
             "ms" => Ok(Duration::from_millis(n)),
-            _ => Err(format!("invalid value: {}, expected 10s or 10ms", &s)),
+            _ => Err(crate::Error::InvalidDuration(s.to_string())),
         }
     }
 
@@ -80,10 +140,55 @@ mod duration {
         let s: String = String::deserialize(deserializer)?;
         parse_duration(&s).map_err(Error::custom)
     }
+
+    pub mod option {
+        use super::parse_duration;
+        use serde::de::Error;
+        use serde::{Deserialize, Deserializer};
+        use std::time::Duration;
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let s: Option<String> = Option::deserialize(deserializer)?;
+            s.map(|s| parse_duration(&s))
+                .transpose()
+                .map_err(Error::custom)
+        }
+    }
+}
+
+mod server_configs {
+    use crate::ServerConfig;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer};
+    use serde_yaml::Value;
+    use std::sync::Arc;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Arc<Vec<ServerConfig>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let values: Vec<Value> = Vec::deserialize(deserializer)?;
+        let servers = values
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| {
+                match value {
+                    Value::String(url) => ServerConfig::from_url(&url).map_err(|e| e.to_string()),
+                    value => serde_yaml::from_value(value).map_err(|e| e.to_string()),
+                }
+                .map_err(|e| Error::custom(format!("server_configs[{}]: {}", i, e)))
+            })
+            .collect::<Result<Vec<ServerConfig>, D::Error>>()?;
+        Ok(Arc::new(servers))
+    }
 }
 
 mod rules {
     use crate::rule::{ProxyRules, Rule};
+    use serde::de::Error;
     use serde::{Deserialize, Deserializer};
     use std::str::FromStr;
 
@@ -92,35 +197,245 @@ mod rules {
         D: Deserializer<'de>,
     {
         let rules: Vec<String> = Vec::deserialize(deserializer)?;
-        let rs: Vec<Rule> = rules
-            .into_iter()
-            .map(|s| Rule::from_str(&s).unwrap())
-            .collect();
-        Ok(ProxyRules::new(rs))
+        let rs = rules
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                Rule::from_str(s).map_err(|e| Error::custom(format!("rules[{}]: {}", i, e)))
+            })
+            .collect::<Result<Vec<Rule>, D::Error>>()?;
+        Ok(ProxyRules::unexpanded(rs))
+    }
+}
+
+mod ipv6_cidr {
+    use crate::parse_cidr6;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer};
+    use smoltcp::wire::Ipv6Cidr;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Ipv6Cidr>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| parse_cidr6(&s))
+            .transpose()
+            .map_err(Error::custom)
+    }
+}
+
+fn default_drain_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_tun_tcp_buffer_size() -> usize {
+    64 * 1024
+}
+
+fn default_relay_buffer_size() -> usize {
+    10240
+}
+
+/// A tcp buffer of the netstack holds at least one full segment of a 1500 bytes MTU.
+const MIN_TUN_TCP_BUFFER_SIZE: usize = 1500;
+/// Smaller relay buffers take a read and a write for every few bytes.
+const MIN_RELAY_BUFFER_SIZE: usize = 1024;
+
+fn parse_cidr(s: &str) -> Result<Ipv4Cidr, Error> {
+    let invalid = || Error::InvalidCidr(s.to_string());
+    let segments = s.splitn(2, '/').collect::<Vec<&str>>();
+    if segments.len() != 2 {
+        return Err(invalid());
+    }
+    let addr: Ipv4Addr = segments[0].parse().map_err(|_| invalid())?;
+    let prefix: u8 = segments[1].parse().map_err(|_| invalid())?;
+    if prefix > 32 {
+        return Err(invalid());
     }
+    Ok(Ipv4Cidr::new(Ipv4Address::from(addr), prefix))
 }
 
-fn parse_cidr(s: String) -> Ipv4Cidr {
+fn parse_cidr6(s: &str) -> Result<Ipv6Cidr, Error> {
+    let invalid = || Error::InvalidCidr(s.to_string());
     let segments = s.splitn(2, '/').collect::<Vec<&str>>();
-    let addr = segments[0];
-    let len = segments[1];
-    let addr: Ipv4Addr = addr.parse().unwrap();
-    let prefix = len.parse().unwrap();
-    Ipv4Cidr::new(Ipv4Address::from(addr), prefix)
+    if segments.len() != 2 {
+        return Err(invalid());
+    }
+    let addr: Ipv6Addr = segments[0].parse().map_err(|_| invalid())?;
+    let prefix: u8 = segments[1].parse().map_err(|_| invalid())?;
+    if prefix > 128 {
+        return Err(invalid());
+    }
+    Ok(Ipv6Cidr::new(Ipv6Address::from(addr), prefix))
 }
 
 impl Config {
-    pub fn from_config_file(path: &str) -> Self {
-        let file = File::open(&path).unwrap();
-        let conf: Config = serde_yaml::from_reader(&file).unwrap();
-        conf
+    pub fn from_config_file(path: &str) -> Result<Self, Error> {
+        let file = File::open(&path).map_err(|e| Error::Io(PathBuf::from(path), e))?;
+        let mut conf: Config = serde_yaml::from_reader(&file)?;
+        let base_dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        if let Some(path) = &conf.server_configs_file {
+            let mut servers = (*conf.server_configs).clone();
+            servers.extend(sip008::load_servers(&base_dir.join(path))?);
+            conf.server_configs = Arc::new(servers);
+        }
+        let rules =
+            rule_provider::expand_rule_sets(conf.rules.rules(), &conf.rule_providers, base_dir)?;
+        conf.rules = ProxyRules::new(rules)?;
+        if let Some(path) = &conf.geo_ip {
+            let db = maxminddb::Reader::open_readfile(path)
+                .map_err(|e| Error::GeoIp(path.clone(), e))?;
+            conf.rules.set_geo_ip_db(db);
+        }
+        conf.validate_proxies()?;
+        conf.validate_ipv6()?;
+        conf.validate_buffer_sizes()?;
+        Ok(conf)
+    }
+
+    /// Check that IPv6 options are either all set or all unset, and the addresses are in
+    /// `tun_ipv6_cidr`.
+    fn validate_ipv6(&self) -> Result<(), Error> {
+        match (self.dns_start_ipv6, self.tun_ipv6, self.tun_ipv6_cidr) {
+            (None, None, None) => Ok(()),
+            (Some(dns_start_ip), Some(tun_ip), Some(cidr)) => {
+                for ip in &[dns_start_ip, tun_ip] {
+                    if !cidr.contains_addr(&Ipv6Address::from(*ip)) {
+                        return Err(Error::InvalidIpv6(format!("{} is not in {}", ip, cidr)));
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(Error::InvalidIpv6(
+                "dns_start_ipv6, tun_ipv6 and tun_ipv6_cidr must be set together".to_string(),
+            )),
+        }
+    }
+
+    /// Check that buffer sizes are large enough to move data at all.
+    fn validate_buffer_sizes(&self) -> Result<(), Error> {
+        let sizes = [
+            (
+                "tun_tcp_rx_buffer_size",
+                self.tun_tcp_rx_buffer_size,
+                MIN_TUN_TCP_BUFFER_SIZE,
+            ),
+            (
+                "tun_tcp_tx_buffer_size",
+                self.tun_tcp_tx_buffer_size,
+                MIN_TUN_TCP_BUFFER_SIZE,
+            ),
+            (
+                "relay_buffer_size",
+                self.relay_buffer_size,
+                MIN_RELAY_BUFFER_SIZE,
+            ),
+        ];
+        for &(name, size, min) in &sizes {
+            if size < min {
+                return Err(Error::InvalidBufferSize {
+                    name: name.to_string(),
+                    reason: format!("{} bytes is less than the minimum {} bytes", size, min),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that server names are unique, groups only contain known servers and rules only
+    /// refer to known servers or groups.
+    fn validate_proxies(&self) -> Result<(), Error> {
+        let mut servers = HashSet::new();
+        for server in self.server_configs.iter() {
+            let invalid = |reason: &str| Error::InvalidServer {
+                name: server.name().to_string(),
+                reason: reason.to_string(),
+            };
+            if !servers.insert(server.name()) {
+                return Err(invalid("defined more than once"));
+            }
+            if Action::is_builtin(server.name()) {
+                return Err(invalid("the name is reserved for a builtin action"));
+            }
+            if server.server_type() == ServerType::Shadowsocks && server.password().is_empty() {
+                return Err(invalid("password is required by shadowsocks servers"));
+            }
+            let method = server.method();
+            if server.server_type() == ServerType::Shadowsocks
+                && method.category() == CipherCategory::Aead2022
+                && server.key().len() != method.key_size()
+            {
+                return Err(invalid(&format!(
+                    "password of {} must be a base64 encoded {} bytes key",
+                    method,
+                    method.key_size()
+                )));
+            }
+            if server.server_type() != ServerType::Shadowsocks && server.plugin().is_some() {
+                return Err(invalid("plugin is only supported by shadowsocks servers"));
+            }
+        }
+        let mut groups = HashSet::new();
+        for group in &self.proxy_groups {
+            let invalid = |reason: String| Error::InvalidProxyGroup {
+                name: group.name.clone(),
+                reason,
+            };
+            if servers.contains(group.name.as_str()) {
+                return Err(invalid("a server has the same name".to_string()));
+            }
+            if !groups.insert(group.name.as_str()) {
+                return Err(invalid("defined more than once".to_string()));
+            }
+            if Action::is_builtin(&group.name) {
+                return Err(invalid(
+                    "the name is reserved for a builtin action".to_string(),
+                ));
+            }
+            if group.proxies.is_empty() {
+                return Err(invalid("no proxies".to_string()));
+            }
+            if let Some(proxy) = group.proxies.iter().find(|p| !servers.contains(p.as_str())) {
+                return Err(invalid(format!(
+                    "{} is not defined in server_configs",
+                    proxy
+                )));
+            }
+        }
+        for rule in self.rules.rules() {
+            if let Action::ProxyTo(name) = rule.action() {
+                if !servers.contains(name.as_str()) && !groups.contains(name.as_str()) {
+                    return Err(Error::UnknownProxy(name.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the proxy group with the name
+    pub fn proxy_group(&self, name: &str) -> Option<&ProxyGroup> {
+        self.proxy_groups.iter().find(|g| g.name == name)
+    }
+
+    /// How often the server is checked by `health_check`. A server in proxy groups that pick
+    /// servers by health uses the shortest `interval` of those groups.
+    pub fn health_check_interval(&self, server_name: &str) -> Duration {
+        self.proxy_groups
+            .iter()
+            .filter(|g| g.need_health_check() && g.proxies.iter().any(|p| p == server_name))
+            .map(|g| g.interval.unwrap_or(self.health_check.interval))
+            .min()
+            .unwrap_or(self.health_check.interval)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::duration::parse_duration;
-    use crate::Config;
+    use crate::{parse_cidr6, Config, Error, MIN_RELAY_BUFFER_SIZE, MIN_TUN_TCP_BUFFER_SIZE};
+    use crypto::CipherType;
+    use std::sync::Arc;
     use std::time::Duration;
 
     #[test]
@@ -185,6 +500,10 @@ rules:
             read_timeout: 30s,
             write_timeout: 30s,
             idle_connections: 10,
+            plugin: None,
+            plugin_opts: None,
+            server_type: Shadowsocks,
+            username: None,
         },
         ServerConfig {
             name: "server2",
@@ -199,6 +518,10 @@ rules:
             read_timeout: 30s,
             write_timeout: 30s,
             idle_connections: 10,
+            plugin: None,
+            plugin_opts: None,
+            server_type: Shadowsocks,
+            username: None,
         },
     ],
     dns_start_ip: 10.0.0.10,
@@ -264,13 +587,267 @@ rules:
     direct_read_timeout: 1s,
     direct_write_timeout: 1s,
     max_connect_errors: 20,
+    geo_ip: None,
+    rule_providers: {},
+    proxy_groups: [],
+    health_check: HealthCheck {
+        url: HttpUrl {
+            host: "www.gstatic.com",
+            port: 80,
+            path: "/generate_204",
+        },
+        interval: 300s,
+        timeout: 5s,
+    },
+    server_configs_file: None,
+    socks5_listen: None,
+    http_listen: None,
+    dns_start_ipv6: None,
+    tun_ipv6: None,
+    tun_ipv6_cidr: None,
+    drain_timeout: 10s,
+    tun_tcp_rx_buffer_size: 65536,
+    tun_tcp_tx_buffer_size: 65536,
+    relay_buffer_size: 10240,
 }"#
         )
     }
 
+    #[test]
+    fn test_deserialize_server_urls() {
+        let content = r#"
+dns_start_ip: 10.0.0.10
+dns_server: 223.5.5.5:53
+tun_name: utun4
+tun_ip: 10.0.0.1
+tun_cidr: 10.0.0.0/16
+dns_listen: 0.0.0.0:53
+gateway_mode: true
+probe_timeout: 10ms
+direct_connect_timeout: 1s
+direct_read_timeout: 1s
+direct_write_timeout: 1s
+max_connect_errors: 20
+server_configs:
+  - name: server1
+    addr: 192.168.2.3:234
+    method: chacha20-ietf
+    password: password
+    connect_timeout: 5s
+    read_timeout: 30s
+    write_timeout: 30s
+    idle_connections: 10
+  - 'ss://Y2hhY2hhMjAtaWV0ZjpwYXNzd29yZA@192.168.2.4:234#server2'
+server_configs_file: servers.json
+rules:
+  - 'MATCH,DIRECT'
+        "#;
+
+        let conf: Config = serde_yaml::from_str(&content).unwrap();
+        assert_eq!(conf.server_configs.len(), 2);
+        let server = &conf.server_configs[1];
+        assert_eq!(server.name(), "server2");
+        assert_eq!(server.addr().to_string(), "192.168.2.4:234");
+        assert_eq!(server.method(), CipherType::ChaCha20Ietf);
+        assert_eq!(server.password(), "password");
+        assert_eq!(
+            conf.server_configs_file,
+            Some(std::path::PathBuf::from("servers.json"))
+        );
+    }
+
     #[test]
     fn test_parse_duration() {
-        assert_eq!(parse_duration("10s"), Ok(Duration::from_secs(10)));
-        assert_eq!(parse_duration("8ms"), Ok(Duration::from_millis(8)));
+        assert_eq!(parse_duration("10s").unwrap(), Duration::from_secs(10));
+        assert_eq!(parse_duration("8ms").unwrap(), Duration::from_millis(8));
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("10m").is_err());
+    }
+
+    #[test]
+    fn test_invalid_rule() {
+        let content = r#"
+dns_start_ip: 10.0.0.10
+dns_server: 223.5.5.5:53
+tun_name: utun4
+tun_ip: 10.0.0.1
+tun_cidr: 10.0.0.0/16
+dns_listen: 0.0.0.0:53
+gateway_mode: true
+probe_timeout: 10ms
+direct_connect_timeout: 1s
+direct_read_timeout: 1s
+direct_write_timeout: 1s
+max_connect_errors: 20
+server_configs: []
+rules:
+  - 'DOMAIN,audio-ssl.itunes.apple.com,DIRECT'
+  - 'IP-CIDR,10.0.0.0,DIRECT'
+  - 'MATCH,PROBE'
+        "#;
+
+        let err = serde_yaml::from_str::<Config>(&content).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("rules[1]"), "{}", msg);
+        assert!(msg.contains("IP-CIDR,10.0.0.0,DIRECT"), "{}", msg);
+    }
+
+    fn config_with_proxies(groups: &str, rules: &str) -> Config {
+        let content = format!(
+            r#"
+dns_start_ip: 10.0.0.10
+dns_server: 223.5.5.5:53
+tun_name: utun4
+tun_ip: 10.0.0.1
+tun_cidr: 10.0.0.0/16
+dns_listen: 0.0.0.0:53
+gateway_mode: true
+probe_timeout: 10ms
+direct_connect_timeout: 1s
+direct_read_timeout: 1s
+direct_write_timeout: 1s
+max_connect_errors: 20
+server_configs:
+  - name: server1
+    addr: 192.168.2.3:234
+    method: chacha20-ietf
+    password: password
+    connect_timeout: 5s
+    read_timeout: 30s
+    write_timeout: 30s
+    idle_connections: 10
+proxy_groups:
+{}
+rules:
+{}
+"#,
+            groups, rules
+        );
+        serde_yaml::from_str(&content).unwrap()
+    }
+
+    #[test]
+    fn test_validate_proxies() {
+        let group = "  - { name: auto, type: url-test, proxies: [server1] }";
+        let conf = config_with_proxies(group, "  - 'DOMAIN,a.com,auto'\n  - 'MATCH,server1'");
+        assert!(conf.validate_proxies().is_ok());
+        assert_eq!(conf.proxy_group("auto").unwrap().proxies, vec!["server1"]);
+
+        let conf = config_with_proxies(group, "  - 'DOMAIN,a.com,PASS'");
+        match conf.validate_proxies() {
+            Err(Error::UnknownProxy(name)) => assert_eq!(name, "PASS"),
+            r => panic!("{:?}", r),
+        }
+
+        let group = "  - { name: auto, type: fallback, proxies: [server1, server3] }";
+        let conf = config_with_proxies(group, "  - 'MATCH,auto'");
+        match conf.validate_proxies() {
+            Err(Error::InvalidProxyGroup { name, .. }) => assert_eq!(name, "auto"),
+            r => panic!("{:?}", r),
+        }
+
+        let group = "  - { name: server1, type: select, proxies: [server1] }";
+        let conf = config_with_proxies(group, "  - 'MATCH,DIRECT'");
+        assert!(conf.validate_proxies().is_err());
+
+        let mut conf = config_with_proxies("  []", "  - 'MATCH,DIRECT'");
+        let mut server = conf.server_configs[0].clone();
+        server.set_method(
+            CipherType::Aead2022Blake3Aes128Gcm,
+            "AAECAwQFBgcICQoLDA0ODw==".to_string(),
+        );
+        conf.server_configs = Arc::new(vec![server.clone()]);
+        assert!(conf.validate_proxies().is_ok());
+        server.set_method(
+            CipherType::Aead2022Blake3Aes256Gcm,
+            server.password().to_string(),
+        );
+        conf.server_configs = Arc::new(vec![server]);
+        match conf.validate_proxies() {
+            Err(Error::InvalidServer { name, .. }) => assert_eq!(name, "server1"),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_health_check_interval() {
+        let groups = "  - { name: auto, type: url-test, proxies: [server1], interval: 60s }
+  - { name: fallback, type: fallback, proxies: [server1, server2] }
+  - { name: manual, type: select, proxies: [server2], interval: 10s }";
+        let mut conf = config_with_proxies(groups, "  - 'MATCH,auto'");
+        assert_eq!(
+            conf.health_check_interval("server1"),
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            conf.health_check_interval("server2"),
+            Duration::from_secs(300)
+        );
+        conf.health_check.interval = Duration::from_secs(30);
+        assert_eq!(
+            conf.health_check_interval("server1"),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            conf.health_check_interval("server3"),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[test]
+    fn test_reserved_proxy_names() {
+        let group = "  - { name: direct, type: select, proxies: [server1] }";
+        let conf = config_with_proxies(group, "  - 'MATCH,DIRECT'");
+        match conf.validate_proxies() {
+            Err(Error::InvalidProxyGroup { name, .. }) => assert_eq!(name, "direct"),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_validate_ipv6() {
+        let mut conf = config_with_proxies("  []", "  - 'MATCH,DIRECT'");
+        assert!(conf.validate_ipv6().is_ok());
+
+        conf.dns_start_ipv6 = Some("fd00::10".parse().unwrap());
+        match conf.validate_ipv6() {
+            Err(Error::InvalidIpv6(_)) => {}
+            r => panic!("{:?}", r),
+        }
+
+        conf.tun_ipv6 = Some("fd00::1".parse().unwrap());
+        conf.tun_ipv6_cidr = Some(parse_cidr6("fd00::/64").unwrap());
+        assert!(conf.validate_ipv6().is_ok());
+
+        conf.tun_ipv6 = Some("fd01::1".parse().unwrap());
+        assert!(conf.validate_ipv6().is_err());
+
+        assert!(parse_cidr6("fd00::").is_err());
+        assert!(parse_cidr6("fd00::/129").is_err());
+    }
+
+    #[test]
+    fn test_validate_buffer_sizes() {
+        let mut conf = config_with_proxies("  []", "  - 'MATCH,DIRECT'");
+        assert!(conf.validate_buffer_sizes().is_ok());
+
+        conf.relay_buffer_size = 0;
+        match conf.validate_buffer_sizes() {
+            Err(Error::InvalidBufferSize { name, .. }) => assert_eq!(name, "relay_buffer_size"),
+            r => panic!("{:?}", r),
+        }
+
+        conf.relay_buffer_size = MIN_RELAY_BUFFER_SIZE;
+        conf.tun_tcp_rx_buffer_size = MIN_TUN_TCP_BUFFER_SIZE;
+        conf.tun_tcp_tx_buffer_size = MIN_TUN_TCP_BUFFER_SIZE;
+        assert!(conf.validate_buffer_sizes().is_ok());
+
+        conf.tun_tcp_tx_buffer_size = MIN_TUN_TCP_BUFFER_SIZE - 1;
+        match conf.validate_buffer_sizes() {
+            Err(Error::InvalidBufferSize { name, .. }) => {
+                assert_eq!(name, "tun_tcp_tx_buffer_size")
+            }
+            r => panic!("{:?}", r),
+        }
     }
 }
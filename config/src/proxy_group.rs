@@ -0,0 +1,63 @@
+use crate::duration;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// A named group of servers, referenced by rule actions like `DOMAIN-SUFFIX,netflix.com,<name>`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProxyGroup {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub group_type: ProxyGroupType,
+    /// Names of servers in `server_configs`
+    pub proxies: Vec<String>,
+    /// How often `proxies` are checked, `health_check.interval` by default
+    #[serde(with = "duration::option", default)]
+    pub interval: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProxyGroupType {
+    /// Always use the first proxy
+    Select,
+    /// Use the proxy with the lowest latency measured by `health_check`
+    UrlTest,
+    /// Use the first proxy that is alive
+    Fallback,
+    /// Spread destination hosts over the alive proxies, the same host always uses the same proxy
+    LoadBalance,
+}
+
+impl ProxyGroup {
+    /// Whether the group needs the health of its proxies to pick one
+    pub fn need_health_check(&self) -> bool {
+        self.group_type != ProxyGroupType::Select
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let content = r#"
+name: auto
+type: url-test
+proxies:
+  - server1
+  - server2
+interval: 60s
+"#;
+        let group: ProxyGroup = serde_yaml::from_str(content).unwrap();
+        assert_eq!(group.name, "auto");
+        assert_eq!(group.group_type, ProxyGroupType::UrlTest);
+        assert_eq!(group.proxies, vec!["server1", "server2"]);
+        assert_eq!(group.interval, Some(Duration::from_secs(60)));
+
+        let group: ProxyGroup =
+            serde_yaml::from_str("name: lb\ntype: load-balance\nproxies: [server1]").unwrap();
+        assert_eq!(group.group_type, ProxyGroupType::LoadBalance);
+        assert_eq!(group.interval, None);
+    }
+}
@@ -1,6 +1,9 @@
-use crate::parse_cidr;
+use crate::domain_matcher::DomainMatcher;
+use crate::{parse_cidr, Error};
+use maxminddb::{geoip2, Reader};
 use smoltcp::wire::Ipv4Cidr;
-use std::net::Ipv4Addr;
+use std::fmt::{self, Debug, Formatter};
+use std::net::IpAddr;
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -10,62 +13,248 @@ pub enum Rule {
     DomainSuffix(String, Action),
     DomainKeyword(String, Action),
     IpCidr(Ipv4Cidr, Action),
+    /// `IP-CIDR` with the `no-resolve` option, only loaded from rule providers. It matches IPs
+    /// but never makes a domain resolve.
+    IpCidrNoResolve(Ipv4Cidr, Action),
+    GeoIp(String, Action),
+    ProcessName(String, Action),
+    Uid(u32, Action),
+    /// Rules loaded from a rule provider, replaced by these rules when the config is loaded
+    RuleSet(String, Action),
     Match(Action),
 }
 
-#[derive(Eq, PartialEq, Copy, Clone, Debug, Hash)]
+#[derive(Eq, PartialEq, Clone, Debug, Hash)]
 pub enum Action {
     Reject,
     Direct,
     Proxy,
     Probe,
+    /// Proxy through the server or proxy group with the name
+    ProxyTo(String),
 }
 
-#[derive(Debug, Clone)]
+/// Process owning the local socket of a connection, used by `PROCESS-NAME` and `UID` rules
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Process {
+    pub name: String,
+    pub uid: u32,
+}
+
+#[derive(Clone)]
 pub struct ProxyRules {
     rules: Arc<Vec<Rule>>,
+    domain_matcher: Arc<DomainMatcher>,
+    /// Positions of rules not handled by `domain_matcher`
+    other_rules: Arc<Vec<usize>>,
+    geo_ip_db: Option<Arc<Reader<Vec<u8>>>>,
 }
 
 impl ProxyRules {
-    pub fn new(rules: Vec<Rule>) -> Self {
+    /// Index `rules` for matching. `RULE-SET` rules have to be expanded before.
+    pub fn new(rules: Vec<Rule>) -> Result<Self, Error> {
+        let mut other_rules = vec![];
+        for (index, rule) in rules.iter().enumerate() {
+            match rule {
+                Rule::Domain(..) | Rule::DomainSuffix(..) | Rule::DomainKeyword(..) => {}
+                Rule::RuleSet(name, _) => {
+                    return Err(Error::InvalidRule {
+                        rule: format!("RULE-SET,{}", name),
+                        reason: "rule sets are only expanded when loading a config file"
+                            .to_string(),
+                    })
+                }
+                Rule::Match(_) => {
+                    // Rules after `MATCH` are never reached.
+                    other_rules.push(index);
+                    break;
+                }
+                _ => other_rules.push(index),
+            }
+        }
+        Ok(Self {
+            domain_matcher: Arc::new(DomainMatcher::new(&rules)),
+            rules: Arc::new(rules),
+            other_rules: Arc::new(other_rules),
+            geo_ip_db: None,
+        })
+    }
+
+    /// Rules as written in the config file, before `RULE-SET` rules are expanded. Nothing is
+    /// indexed, so no rule matches until they are expanded and passed to `ProxyRules::new`.
+    pub(crate) fn unexpanded(rules: Vec<Rule>) -> Self {
         Self {
             rules: Arc::new(rules),
+            domain_matcher: Arc::new(DomainMatcher::default()),
+            other_rules: Arc::new(vec![]),
+            geo_ip_db: None,
         }
     }
 
-    pub fn action_for_domain(&self, domain: &str) -> Option<Action> {
-        self.rules
+    /// Set the MaxMind database used by `GEOIP` rules. Without it `GEOIP` rules never match.
+    pub fn set_geo_ip_db(&mut self, db: Reader<Vec<u8>>) {
+        self.geo_ip_db = Some(Arc::new(db));
+    }
+
+    /// Match `domain` against the domain rules, or nothing for an IP literal when `domain` is
+    /// `None`. The result answers every question about the connection without matching again.
+    pub fn match_domain(&self, domain: Option<&str>) -> RuleMatch<'_> {
+        RuleMatch {
+            rules: self,
+            domain_match: domain.and_then(|d| self.domain_matcher.find(d)),
+        }
+    }
+
+    /// Find the action for a domain. `ip` is the resolved address of the domain, it is
+    /// used by `IP-CIDR` and `GEOIP` rules. `process` is the owner of the connection, it is
+    /// used by `PROCESS-NAME` and `UID` rules. When either is `None`, its rules are skipped.
+    pub fn action_for_domain(
+        &self,
+        domain: &str,
+        ip: Option<IpAddr>,
+        process: Option<&Process>,
+    ) -> Option<Action> {
+        self.match_domain(Some(domain)).action(ip, process)
+    }
+
+    /// Find the action for an IP literal. Domain rules never match an IP, so all other
+    /// rules take part, in the order they are defined.
+    pub fn action_for_ip(&self, ip: IpAddr, process: Option<&Process>) -> Option<Action> {
+        self.match_domain(None).action(Some(ip), process)
+    }
+
+    /// Whether an IP based rule may be reached before any domain rule matches `domain`.
+    /// Only then the domain needs to be resolved to find its action.
+    pub fn need_resolve(&self, domain: &str) -> bool {
+        self.match_domain(Some(domain)).need_resolve()
+    }
+
+    /// Whether a process based rule may be reached for `domain`, or for an IP literal when
+    /// `domain` is `None`. Only then the owner of the connection needs to be looked up.
+    pub fn need_process(&self, domain: Option<&str>) -> bool {
+        self.match_domain(domain).need_process()
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn default_action(&self) -> Action {
+        Action::Direct
+    }
+
+    /// Rules not handled by `domain_matcher`, defined before the matched domain rule
+    fn other_rules_before<'a>(
+        &'a self,
+        domain_match: Option<usize>,
+    ) -> impl Iterator<Item = &'a Rule> + 'a {
+        let end = domain_match.unwrap_or_else(|| self.rules.len());
+        self.other_rules
             .iter()
-            .filter_map(|rule| match rule {
-                Rule::Domain(d, action) if d == domain => Some(*action),
-                Rule::DomainSuffix(d, action) if domain.ends_with(d) => Some(*action),
-                Rule::DomainKeyword(d, action) if domain.contains(d) => Some(*action),
-                Rule::Match(action) => Some(*action),
+            .take_while(move |index| **index < end)
+            .map(move |index| &self.rules[*index])
+    }
+
+    fn action_for_other_rule(
+        &self,
+        rule: &Rule,
+        ip: Option<IpAddr>,
+        process: Option<&Process>,
+    ) -> Option<Action> {
+        match rule {
+            Rule::Match(action) => Some(action.clone()),
+            Rule::ProcessName(name, action) => {
+                process.filter(|p| &p.name == name).map(|_| action.clone())
+            }
+            Rule::Uid(uid, action) => process.filter(|p| p.uid == *uid).map(|_| action.clone()),
+            _ => ip.and_then(|ip| self.action_for_ip_rule(rule, ip)),
+        }
+    }
+
+    fn action_for_ip_rule(&self, rule: &Rule, ip: IpAddr) -> Option<Action> {
+        match (rule, ip) {
+            (Rule::IpCidr(cidr, action), IpAddr::V4(ip))
+            | (Rule::IpCidrNoResolve(cidr, action), IpAddr::V4(ip))
+                if cidr.contains_addr(&ip.into()) =>
+            {
+                Some(action.clone())
+            }
+            (Rule::GeoIp(country, action), ip) => match self.country_of(ip) {
+                Some(c) if c.eq_ignore_ascii_case(country) => Some(action.clone()),
                 _ => None,
-            })
-            .take(1)
+            },
+            _ => None,
+        }
+    }
+
+    fn country_of(&self, ip: IpAddr) -> Option<String> {
+        let db = self.geo_ip_db.as_ref()?;
+        let country: geoip2::Country = db.lookup(ip).ok()?;
+        country.country?.iso_code
+    }
+}
+
+/// Domain rules matched for one connection, see `ProxyRules::match_domain`
+pub struct RuleMatch<'a> {
+    rules: &'a ProxyRules,
+    domain_match: Option<usize>,
+}
+
+impl<'a> RuleMatch<'a> {
+    /// Find the action, the same as `ProxyRules::action_for_domain`
+    pub fn action(&self, ip: Option<IpAddr>, process: Option<&Process>) -> Option<Action> {
+        let rules = self.rules;
+        rules
+            .other_rules_before(self.domain_match)
+            .filter_map(|rule| rules.action_for_other_rule(rule, ip, process))
             .next()
+            .or_else(|| {
+                self.domain_match
+                    .map(|index| rules.rules[index].action().clone())
+            })
     }
 
-    #[allow(dead_code)]
-    pub fn action_for_ip(&self, ip: Ipv4Addr) -> Option<Action> {
+    /// Whether an IP based rule may be reached before the matched domain rule
+    pub fn need_resolve(&self) -> bool {
         self.rules
-            .iter()
-            .filter_map(|rule| match rule {
-                Rule::IpCidr(cidr, action) if cidr.contains_addr(&ip.into()) => Some(*action),
-                _ => None,
+            .other_rules_before(self.domain_match)
+            .any(|rule| match rule {
+                Rule::IpCidr(..) | Rule::GeoIp(..) => true,
+                _ => false,
             })
-            .take(1)
-            .next()
     }
 
-    pub fn default_action(&self) -> Action {
-        Action::Direct
+    /// Whether a process based rule may be reached before the matched domain rule
+    pub fn need_process(&self) -> bool {
+        self.rules
+            .other_rules_before(self.domain_match)
+            .any(|rule| match rule {
+                Rule::ProcessName(..) | Rule::Uid(..) => true,
+                _ => false,
+            })
+    }
+}
+
+impl Debug for ProxyRules {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("ProxyRules")
+            .field("rules", &self.rules)
+            .finish()
+    }
+}
+
+impl Action {
+    /// Whether `name` is a builtin action, ignoring case. Such names are reserved, servers and
+    /// proxy groups can't use them.
+    pub fn is_builtin(name: &str) -> bool {
+        ["REJECT", "DIRECT", "PROXY", "PROBE"]
+            .iter()
+            .any(|action| action.eq_ignore_ascii_case(name))
     }
 }
 
 impl FromStr for Action {
-    type Err = ();
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s {
@@ -73,36 +262,443 @@ impl FromStr for Action {
             "DIRECT" => Action::Direct,
             "PROXY" => Action::Proxy,
             "PROBE" => Action::Probe,
-            _ => unreachable!(),
+            // Builtin actions in another case, eg. `direct`, are typos rather than proxy names.
+            // Other names are checked against servers and proxy groups when the config is loaded.
+            name if name.is_empty() || Action::is_builtin(name) => {
+                return Err(Error::InvalidAction(s.to_string()))
+            }
+            name => Action::ProxyTo(name.to_string()),
         })
     }
 }
 
 impl FromStr for Rule {
-    type Err = ();
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| Error::InvalidRule {
+            rule: s.to_string(),
+            reason,
+        };
         let segments = s.splitn(3, ',').collect::<Vec<_>>();
         let (rule, criteria, action) = match segments.len() {
             2 => (segments[0], "", segments[1]),
             3 => (segments[0], segments[1], segments[2]),
-            _ => unreachable!(),
+            _ => {
+                return Err(invalid(
+                    "expected TYPE,CRITERIA,ACTION or MATCH,ACTION".to_string(),
+                ))
+            }
         };
+        if rule != "MATCH" && criteria.is_empty() {
+            return Err(invalid(format!("missing criteria for {}", rule)));
+        }
+        let action = Action::from_str(action).map_err(|e| invalid(e.to_string()))?;
 
+        match rule {
+            "MATCH" => Ok(Rule::Match(action)),
+            "RULE-SET" => Ok(Rule::RuleSet(criteria.to_string(), action)),
+            _ => Rule::with_criteria(rule, criteria, action).map_err(invalid),
+        }
+    }
+}
+
+impl Rule {
+    pub fn action(&self) -> &Action {
+        match self {
+            Rule::Domain(_, action)
+            | Rule::DomainSuffix(_, action)
+            | Rule::DomainKeyword(_, action)
+            | Rule::IpCidr(_, action)
+            | Rule::IpCidrNoResolve(_, action)
+            | Rule::GeoIp(_, action)
+            | Rule::ProcessName(_, action)
+            | Rule::Uid(_, action)
+            | Rule::RuleSet(_, action)
+            | Rule::Match(action) => action,
+        }
+    }
+
+    /// Build a rule matching `criteria`, eg. `DOMAIN-SUFFIX` and `google.com`
+    pub(crate) fn with_criteria(
+        rule: &str,
+        criteria: &str,
+        action: Action,
+    ) -> Result<Rule, String> {
         Ok(match rule {
-            "DOMAIN" => Rule::Domain(criteria.to_string(), Action::from_str(action).unwrap()),
-            "DOMAIN-SUFFIX" => {
-                Rule::DomainSuffix(criteria.to_string(), Action::from_str(action).unwrap())
-            }
-            "DOMAIN-KEYWORD" => {
-                Rule::DomainKeyword(criteria.to_string(), Action::from_str(action).unwrap())
-            }
-            "IP-CIDR" => Rule::IpCidr(
-                parse_cidr(criteria.to_string()),
-                Action::from_str(action).unwrap(),
+            "DOMAIN" => Rule::Domain(criteria.to_string(), action),
+            "DOMAIN-SUFFIX" => Rule::DomainSuffix(criteria.to_string(), action),
+            "DOMAIN-KEYWORD" => Rule::DomainKeyword(criteria.to_string(), action),
+            "IP-CIDR" => Rule::IpCidr(parse_cidr(criteria).map_err(|e| e.to_string())?, action),
+            "GEOIP" => Rule::GeoIp(criteria.to_ascii_uppercase(), action),
+            "PROCESS-NAME" => Rule::ProcessName(criteria.to_string(), action),
+            "UID" => Rule::Uid(
+                criteria
+                    .parse()
+                    .map_err(|_| format!("invalid uid {}", criteria))?,
+                action,
             ),
-            "MATCH" => Rule::Match(Action::from_str(action).unwrap()),
-            _ => unreachable!(),
+            _ => return Err(format!("unknown rule type {}", rule)),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[&str]) -> ProxyRules {
+        ProxyRules::new(rules.iter().map(|r| Rule::from_str(r).unwrap()).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_action_for_ip() {
+        let rules = rules(&[
+            "DOMAIN-SUFFIX,example.com,PROXY",
+            "IP-CIDR,192.168.0.0/16,DIRECT",
+            "IP-CIDR,10.0.0.0/8,REJECT",
+            "IP-CIDR,10.1.0.0/16,DIRECT",
+            "MATCH,PROXY",
+        ]);
+        assert_eq!(
+            rules.action_for_ip("192.168.1.1".parse().unwrap(), None),
+            Some(Action::Direct)
+        );
+        assert_eq!(
+            rules.action_for_ip("10.1.1.1".parse().unwrap(), None),
+            Some(Action::Reject)
+        );
+        assert_eq!(
+            rules.action_for_ip("8.8.8.8".parse().unwrap(), None),
+            Some(Action::Proxy)
+        );
+        assert_eq!(
+            rules.action_for_ip("::1".parse().unwrap(), None),
+            Some(Action::Proxy)
+        );
+    }
+
+    #[test]
+    fn test_match_before_ip_cidr() {
+        let rules = rules(&["MATCH,PROBE", "IP-CIDR,192.168.0.0/16,DIRECT"]);
+        assert_eq!(
+            rules.action_for_ip("192.168.1.1".parse().unwrap(), None),
+            Some(Action::Probe)
+        );
+    }
+
+    #[test]
+    fn test_ip_cidr_skipped_for_domain() {
+        let rules = rules(&["IP-CIDR,192.168.0.0/16,DIRECT", "MATCH,PROXY"]);
+        assert_eq!(
+            rules.action_for_domain("192.168.1.1", None, None),
+            Some(Action::Proxy)
+        );
+        assert_eq!(
+            rules.action_for_domain("example.com", None, None),
+            Some(Action::Proxy)
+        );
+        assert_eq!(
+            rules.action_for_domain("example.com", Some("192.168.1.1".parse().unwrap()), None),
+            Some(Action::Direct)
+        );
+    }
+
+    #[test]
+    fn test_parse_geo_ip() {
+        assert_eq!(
+            Rule::from_str("GEOIP,cn,DIRECT").unwrap(),
+            Rule::GeoIp("CN".to_string(), Action::Direct)
+        );
+    }
+
+    #[test]
+    fn test_parse_proxy_to() {
+        assert_eq!(
+            Rule::from_str("DOMAIN-SUFFIX,netflix.com,server2").unwrap(),
+            Rule::DomainSuffix(
+                "netflix.com".to_string(),
+                Action::ProxyTo("server2".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_action() {
+        for action in &["", "direct", "Proxy", "reject", "probe"] {
+            match Action::from_str(action) {
+                Err(Error::InvalidAction(a)) => assert_eq!(&a, action),
+                other => panic!("{}: {:?}", action, other),
+            }
+        }
+        match Rule::from_str("DOMAIN,example.com,direct") {
+            Err(Error::InvalidRule { reason, .. }) => {
+                assert!(reason.contains("direct"), "{}", reason)
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_rule_set() {
+        assert_eq!(
+            Rule::from_str("RULE-SET,reject,REJECT").unwrap(),
+            Rule::RuleSet("reject".to_string(), Action::Reject)
+        );
+    }
+
+    #[test]
+    fn test_unexpanded_rule_set() {
+        let rules = vec![
+            Rule::from_str("RULE-SET,reject,REJECT").unwrap(),
+            Rule::from_str("MATCH,PROXY").unwrap(),
+        ];
+        match ProxyRules::new(rules) {
+            Err(Error::InvalidRule { rule, .. }) => assert_eq!(rule, "RULE-SET,reject"),
+            _ => panic!("unexpanded RULE-SET must be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid_rule() {
+        for rule in &[
+            "MATCH",
+            "DOMAIN,DIRECT",
+            "DOMAIN,example.com,",
+            "IP-CIDR,10.0.0.0,DIRECT",
+            "IP-CIDR,10.0.0.0/40,DIRECT",
+            "IP-CIDR,10.0.0.256/8,DIRECT",
+            "PORT,80,DIRECT",
+        ] {
+            match Rule::from_str(rule) {
+                Err(Error::InvalidRule { rule: r, .. }) => assert_eq!(&r, rule),
+                other => panic!("{}: {:?}", rule, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_geo_ip_without_db() {
+        let rules = rules(&["GEOIP,CN,DIRECT", "MATCH,PROXY"]);
+        assert_eq!(
+            rules.action_for_ip("114.114.114.114".parse().unwrap(), None),
+            Some(Action::Proxy)
+        );
+    }
+
+    #[test]
+    fn test_need_resolve() {
+        let ip_rules = rules(&[
+            "DOMAIN-SUFFIX,example.com,PROXY",
+            "GEOIP,CN,DIRECT",
+            "MATCH,PROXY",
+        ]);
+        assert!(!ip_rules.need_resolve("www.example.com"));
+        assert!(ip_rules.need_resolve("www.example.cn"));
+        let no_ip_rules = rules(&["DOMAIN-SUFFIX,example.com,PROXY", "MATCH,PROXY"]);
+        assert!(!no_ip_rules.need_resolve("www.example.cn"));
+    }
+
+    #[test]
+    fn test_ip_cidr_no_resolve() {
+        let rules = ProxyRules::new(vec![
+            Rule::IpCidrNoResolve(parse_cidr("10.0.0.0/8").unwrap(), Action::Direct),
+            Rule::Match(Action::Proxy),
+        ])
+        .unwrap();
+        assert!(!rules.need_resolve("www.example.com"));
+        assert_eq!(
+            rules.action_for_ip("10.0.0.1".parse().unwrap(), None),
+            Some(Action::Direct)
+        );
+        assert_eq!(
+            rules.action_for_domain("www.example.com", None, None),
+            Some(Action::Proxy)
+        );
+    }
+
+    #[test]
+    fn test_domain_suffix_label_boundary() {
+        let rules = rules(&["DOMAIN-SUFFIX,apple.co,DIRECT", "MATCH,PROXY"]);
+        for domain in &["apple.co", "www.apple.co", "a.b.apple.co"] {
+            assert_eq!(
+                rules.action_for_domain(domain, None, None),
+                Some(Action::Direct),
+                "{}",
+                domain
+            );
+        }
+        for domain in &[
+            "notapple.co",
+            "pineapple.co",
+            "apple.com",
+            "apple.co.uk",
+            "co",
+        ] {
+            assert_eq!(
+                rules.action_for_domain(domain, None, None),
+                Some(Action::Proxy),
+                "{}",
+                domain
+            );
+        }
+    }
+
+    #[test]
+    fn test_domain_trailing_dot() {
+        let rules = rules(&[
+            "DOMAIN,example.com.,REJECT",
+            "DOMAIN-SUFFIX,apple.co.,DIRECT",
+            "MATCH,PROXY",
+        ]);
+        assert_eq!(
+            rules.action_for_domain("example.com", None, None),
+            Some(Action::Reject)
+        );
+        assert_eq!(
+            rules.action_for_domain("example.com.", None, None),
+            Some(Action::Reject)
+        );
+        assert_eq!(
+            rules.action_for_domain("www.apple.co.", None, None),
+            Some(Action::Direct)
+        );
+        assert_eq!(
+            rules.action_for_domain("pineapple.co.", None, None),
+            Some(Action::Proxy)
+        );
+    }
+
+    #[test]
+    fn test_domain_case_insensitive() {
+        let rules = rules(&[
+            "DOMAIN,Example.COM,REJECT",
+            "DOMAIN-SUFFIX,Apple.Co,DIRECT",
+            "DOMAIN-KEYWORD,GOOGLE,PROBE",
+            "MATCH,PROXY",
+        ]);
+        assert_eq!(
+            rules.action_for_domain("EXAMPLE.com", None, None),
+            Some(Action::Reject)
+        );
+        assert_eq!(
+            rules.action_for_domain("WWW.APPLE.CO", None, None),
+            Some(Action::Direct)
+        );
+        assert_eq!(
+            rules.action_for_domain("www.Google.com", None, None),
+            Some(Action::Probe)
+        );
+    }
+
+    #[test]
+    fn test_domain_idn() {
+        let rules = rules(&[
+            "DOMAIN-SUFFIX,例子.测试,DIRECT",
+            "DOMAIN,xn--fiqs8s.xn--0zwm56d,REJECT",
+            "MATCH,PROXY",
+        ]);
+        assert_eq!(
+            rules.action_for_domain("www.xn--fsqu00a.xn--0zwm56d", None, None),
+            Some(Action::Direct)
+        );
+        assert_eq!(
+            rules.action_for_domain("www.例子.测试", None, None),
+            Some(Action::Direct)
+        );
+        assert_eq!(
+            rules.action_for_domain("中国.测试", None, None),
+            Some(Action::Reject)
+        );
+        assert_eq!(
+            rules.action_for_domain("xn--fsqu00a.xn--0zwm56d.example", None, None),
+            Some(Action::Proxy)
+        );
+    }
+
+    #[test]
+    fn test_process_rules() {
+        let rules = rules(&[
+            "DOMAIN-SUFFIX,example.com,REJECT",
+            "PROCESS-NAME,curl,PROXY",
+            "UID,1001,DIRECT",
+            "MATCH,PROBE",
+        ]);
+        let curl = Process {
+            name: "curl".to_string(),
+            uid: 1001,
+        };
+        let wget = Process {
+            name: "wget".to_string(),
+            uid: 1001,
+        };
+        let root = Process {
+            name: "wget".to_string(),
+            uid: 0,
+        };
+        assert_eq!(
+            rules.action_for_domain("www.example.com", None, Some(&curl)),
+            Some(Action::Reject)
+        );
+        assert_eq!(
+            rules.action_for_domain("www.google.com", None, Some(&curl)),
+            Some(Action::Proxy)
+        );
+        assert_eq!(
+            rules.action_for_domain("www.google.com", None, Some(&wget)),
+            Some(Action::Direct)
+        );
+        assert_eq!(
+            rules.action_for_domain("www.google.com", None, Some(&root)),
+            Some(Action::Probe)
+        );
+        assert_eq!(
+            rules.action_for_ip("8.8.8.8".parse().unwrap(), Some(&curl)),
+            Some(Action::Proxy)
+        );
+        assert_eq!(
+            rules.action_for_ip("8.8.8.8".parse().unwrap(), None),
+            Some(Action::Probe)
+        );
+        assert!(!rules.need_process(Some("www.example.com")));
+        assert!(rules.need_process(Some("www.google.com")));
+        assert!(rules.need_process(None));
+    }
+
+    #[test]
+    fn test_match_domain() {
+        let rules = rules(&[
+            "DOMAIN-SUFFIX,example.com,REJECT",
+            "PROCESS-NAME,curl,PROXY",
+            "GEOIP,CN,DIRECT",
+            "DOMAIN-SUFFIX,google.com,DIRECT",
+            "MATCH,PROBE",
+        ]);
+        let matched = rules.match_domain(Some("www.example.com"));
+        assert!(!matched.need_process());
+        assert!(!matched.need_resolve());
+        assert_eq!(matched.action(None, None), Some(Action::Reject));
+
+        let matched = rules.match_domain(Some("www.google.com"));
+        assert!(matched.need_process());
+        assert!(matched.need_resolve());
+        assert_eq!(matched.action(None, None), Some(Action::Direct));
+
+        let matched = rules.match_domain(None);
+        assert!(matched.need_process());
+        assert_eq!(matched.action(None, None), Some(Action::Probe));
+    }
+
+    #[test]
+    fn test_parse_process_rules() {
+        assert_eq!(
+            Rule::from_str("PROCESS-NAME,curl,PROXY").unwrap(),
+            Rule::ProcessName("curl".to_string(), Action::Proxy)
+        );
+        assert_eq!(
+            Rule::from_str("UID,1001,DIRECT").unwrap(),
+            Rule::Uid(1001, Action::Direct)
+        );
+        assert!(Rule::from_str("UID,root,DIRECT").is_err());
+    }
+}
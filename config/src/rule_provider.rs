@@ -0,0 +1,242 @@
+use crate::rule::{Action, Rule};
+use crate::{parse_cidr, Error};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// A list of rules kept in a separate file and referenced by `RULE-SET,<name>,<ACTION>`.
+///
+/// The file is either a Clash rule provider with a `payload:` list, or a plain text file with
+/// one entry per line. Empty lines and lines starting with `#` are ignored.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleProvider {
+    /// How entries are interpreted
+    #[serde(default)]
+    pub behavior: Behavior,
+    /// Path to the rule file, relative to the config file
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Behavior {
+    /// `google.com` matches the domain, `+.google.com` matches the domain and its subdomains
+    Domain,
+    /// `10.0.0.0/8`
+    Ipcidr,
+    /// Rules without action, eg. `DOMAIN-SUFFIX,google.com`
+    Classical,
+}
+
+impl Default for Behavior {
+    fn default() -> Self {
+        Behavior::Classical
+    }
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    payload: Vec<String>,
+}
+
+impl RuleProvider {
+    /// Read the rule file and turn every entry into a rule with `action`.
+    pub fn load(&self, base_dir: &Path, action: Action) -> Result<Vec<Rule>, Error> {
+        let path = base_dir.join(&self.path);
+        let content = std::fs::read_to_string(&path).map_err(|e| Error::Io(path.clone(), e))?;
+        parse_rules(&content, self.behavior, action)
+    }
+}
+
+/// Replace every `RULE-SET` rule with the rules of its provider, keeping the rule order.
+pub(crate) fn expand_rule_sets(
+    rules: &[Rule],
+    providers: &HashMap<String, RuleProvider>,
+    base_dir: &Path,
+) -> Result<Vec<Rule>, Error> {
+    let mut expanded = Vec::with_capacity(rules.len());
+    for rule in rules {
+        match rule {
+            Rule::RuleSet(name, action) => {
+                let provider = providers.get(name).ok_or_else(|| Error::InvalidRule {
+                    rule: format!("RULE-SET,{}", name),
+                    reason: format!("rule provider {} is not defined in rule_providers", name),
+                })?;
+                let rules = provider
+                    .load(base_dir, action.clone())
+                    .map_err(|e| Error::RuleProvider(name.clone(), Box::new(e)))?;
+                expanded.extend(rules);
+            }
+            rule => expanded.push(rule.clone()),
+        }
+    }
+    Ok(expanded)
+}
+
+fn parse_rules(content: &str, behavior: Behavior, action: Action) -> Result<Vec<Rule>, Error> {
+    let entries = if content.lines().any(|l| l.trim_end() == "payload:") {
+        serde_yaml::from_str::<Payload>(content)?.payload
+    } else {
+        content
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| l.to_string())
+            .collect()
+    };
+
+    let mut rules = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let entry = entry.trim();
+        let invalid = |reason: String| Error::InvalidRule {
+            rule: entry.to_string(),
+            reason,
+        };
+        let rule = match behavior {
+            Behavior::Domain => {
+                if entry.contains('*') {
+                    return Err(invalid("wildcard domains are not supported".to_string()));
+                }
+                if entry.starts_with("+.") || entry.starts_with('.') {
+                    Rule::DomainSuffix(
+                        entry
+                            .trim_start_matches('+')
+                            .trim_start_matches('.')
+                            .to_string(),
+                        action.clone(),
+                    )
+                } else {
+                    Rule::Domain(entry.to_string(), action.clone())
+                }
+            }
+            Behavior::Ipcidr => {
+                if entry.contains(':') {
+                    warn!(cidr = entry, "IPv6 cidr is not supported, skip it");
+                    continue;
+                }
+                Rule::IpCidr(
+                    parse_cidr(entry).map_err(|e| invalid(e.to_string()))?,
+                    action.clone(),
+                )
+            }
+            Behavior::Classical => {
+                let segments = entry.split(',').collect::<Vec<_>>();
+                if segments.len() < 2 {
+                    return Err(invalid("expected TYPE,CRITERIA".to_string()));
+                }
+                if segments[0] == "IP-CIDR6" {
+                    warn!(cidr = segments[1], "IPv6 cidr is not supported, skip it");
+                    continue;
+                }
+                let rule = Rule::with_criteria(segments[0], segments[1], action.clone())
+                    .map_err(invalid)?;
+                match (rule, &segments[2..]) {
+                    (rule, []) => rule,
+                    (Rule::IpCidr(cidr, action), ["no-resolve"]) => {
+                        Rule::IpCidrNoResolve(cidr, action)
+                    }
+                    (_, options) => {
+                        return Err(invalid(format!(
+                            "unsupported options {}",
+                            options.join(",")
+                        )))
+                    }
+                }
+            }
+        };
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_payload() {
+        let content = r#"
+payload:
+  - 'DOMAIN-SUFFIX,google.com'
+  - 'DOMAIN-KEYWORD,youtube'
+  - 'IP-CIDR,91.108.4.0/22,no-resolve'
+  - 'IP-CIDR6,2001:b28:f23d::/48,no-resolve'
+"#;
+        let rules = parse_rules(content, Behavior::Classical, Action::Proxy).unwrap();
+        assert_eq!(
+            rules,
+            vec![
+                Rule::DomainSuffix("google.com".to_string(), Action::Proxy),
+                Rule::DomainKeyword("youtube".to_string(), Action::Proxy),
+                Rule::IpCidrNoResolve(parse_cidr("91.108.4.0/22").unwrap(), Action::Proxy),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_domain_list() {
+        let content = r#"
+# ads
++.doubleclick.net
+ad.example.com
+"#;
+        let rules = parse_rules(content, Behavior::Domain, Action::Reject).unwrap();
+        assert_eq!(
+            rules,
+            vec![
+                Rule::DomainSuffix("doubleclick.net".to_string(), Action::Reject),
+                Rule::Domain("ad.example.com".to_string(), Action::Reject),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ipcidr_payload() {
+        let content = r#"
+payload:
+  - '10.0.0.0/8'
+  - 'fc00::/7'
+"#;
+        let rules = parse_rules(content, Behavior::Ipcidr, Action::Direct).unwrap();
+        assert_eq!(
+            rules,
+            vec![Rule::IpCidr(
+                parse_cidr("10.0.0.0/8").unwrap(),
+                Action::Direct
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_entry() {
+        assert!(parse_rules("MATCH", Behavior::Classical, Action::Direct).is_err());
+        assert!(parse_rules("RULE-SET,other", Behavior::Classical, Action::Direct).is_err());
+        assert!(parse_rules("*.example.com", Behavior::Domain, Action::Direct).is_err());
+        assert!(parse_rules(
+            "IP-CIDR,10.0.0.0/8,resolve",
+            Behavior::Classical,
+            Action::Direct
+        )
+        .is_err());
+        assert!(parse_rules(
+            "DOMAIN,example.com,no-resolve",
+            Behavior::Classical,
+            Action::Direct
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_expand_rule_sets() {
+        let rules = vec![
+            Rule::Domain("example.com".to_string(), Action::Direct),
+            Rule::RuleSet("unknown".to_string(), Action::Proxy),
+        ];
+        let err = expand_rule_sets(&rules, &HashMap::new(), Path::new("")).unwrap_err();
+        match err {
+            Error::InvalidRule { .. } => {}
+            e => panic!("{:?}", e),
+        }
+    }
+}
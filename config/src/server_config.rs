@@ -13,7 +13,7 @@ use serde::Deserialize;
 use tracing::trace;
 
 /// Server address
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub enum ServerAddr {
     /// IP Address
     SocketAddr(SocketAddr),
@@ -81,18 +81,36 @@ impl Display for ServerAddr {
     }
 }
 
+/// Protocol spoken by a server
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerType {
+    Shadowsocks,
+    Socks5,
+    /// HTTP proxy supporting `CONNECT`
+    Http,
+}
+
+impl Default for ServerType {
+    fn default() -> Self {
+        ServerType::Shadowsocks
+    }
+}
+
 /// Configuration for a server
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct ServerConfig {
     /// Server name
     name: String,
     /// Server address
     #[serde(with = "server_addr")]
     addr: ServerAddr,
-    /// Encryption password (key)
+    /// Encryption password (key) of shadowsocks servers, or the password of socks5 and http
+    /// servers
+    #[serde(default)]
     password: String,
-    /// Encryption type (method)
-    #[serde(with = "cipher_type")]
+    /// Encryption type (method), only used by shadowsocks servers
+    #[serde(with = "cipher_type", default = "default_method")]
     method: CipherType,
     /// Connection timeout
     #[serde(with = "duration")]
@@ -105,6 +123,52 @@ pub struct ServerConfig {
     write_timeout: Duration,
     /// Max idle connections
     idle_connections: usize,
+    /// SIP003 plugin executable, eg. `obfs-local`
+    #[serde(default)]
+    plugin: Option<String>,
+    /// Options passed to the plugin in `SS_PLUGIN_OPTIONS`, eg. `obfs=http;obfs-host=example.com`
+    #[serde(default)]
+    plugin_opts: Option<String>,
+    /// Protocol of the server
+    #[serde(rename = "type", default)]
+    server_type: ServerType,
+    /// User name of socks5 and http servers, no authentication if not set
+    #[serde(default)]
+    username: Option<String>,
+}
+
+fn default_method() -> CipherType {
+    CipherType::Plain
+}
+
+/// Decode base64 with or without padding, in either the standard or url safe alphabet
+fn decode_base64(s: &str) -> Option<String> {
+    let s = s.trim_end_matches('=').replace('+', "-").replace('/', "_");
+    let bytes = base64::decode_config(&s, base64::URL_SAFE_NO_PAD).ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
 }
 
 mod cipher_type {
@@ -162,14 +226,85 @@ impl ServerConfig {
             read_timeout,
             write_timeout,
             idle_connections,
+            plugin: None,
+            plugin_opts: None,
+            server_type: ServerType::Shadowsocks,
+            username: None,
         }
     }
 
-    /// Create a basic config
-    pub fn basic(addr: SocketAddr, password: String, method: CipherType) -> ServerConfig {
+    /// Create a config from a SIP002 url, eg. `ss://YWVzLTI1Ni1nY206cGFzcw@example.com:8388#name`.
+    /// The legacy `ss://<base64 of method:password@host:port>#name` form is also accepted.
+    /// Timeouts and idle connections are set to the defaults of `with_defaults`.
+    pub fn from_url(url: &str) -> Result<ServerConfig, crate::Error> {
+        let invalid = |reason: &str| crate::Error::InvalidServerUrl {
+            url: url.to_string(),
+            reason: reason.to_string(),
+        };
+        if !url.starts_with("ss://") {
+            return Err(invalid("expected ss://"));
+        }
+        let rest = &url["ss://".len()..];
+        let (rest, tag) = match rest.find('#') {
+            Some(i) => (&rest[..i], percent_decode(&rest[i + 1..])),
+            None => (rest, String::new()),
+        };
+        let (rest, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => (rest, ""),
+        };
+        let rest = rest.trim_end_matches('/');
+        let (user_info, host) = match rest.rfind('@') {
+            // AEAD ciphers may use plain `method:password` instead of base64
+            Some(i) if rest[..i].contains(':') => {
+                (percent_decode(&rest[..i]), rest[i + 1..].to_string())
+            }
+            Some(i) => (
+                decode_base64(&rest[..i]).ok_or_else(|| invalid("invalid base64 user info"))?,
+                rest[i + 1..].to_string(),
+            ),
+            None => {
+                let decoded = decode_base64(rest).ok_or_else(|| invalid("invalid base64"))?;
+                let i = decoded.rfind('@').ok_or_else(|| invalid("missing host"))?;
+                (decoded[..i].to_string(), decoded[i + 1..].to_string())
+            }
+        };
+        let i = user_info
+            .find(':')
+            .ok_or_else(|| invalid("expected method:password"))?;
+        let method = CipherType::from_str(&user_info[..i])
+            .map_err(|_| invalid(&format!("unknown method {}", &user_info[..i])))?;
+        let password = user_info[i + 1..].to_string();
+        let addr = ServerAddr::from_str(&host).map_err(|_| invalid("expected host:port"))?;
+        let name = if tag.is_empty() { host } else { tag };
+
+        let mut config = ServerConfig::with_defaults(name, addr, password, method);
+        for pair in query.split('&') {
+            let mut kv = pair.splitn(2, '=');
+            if let (Some("plugin"), Some(value)) = (kv.next(), kv.next()) {
+                let value = percent_decode(value);
+                let mut parts = value.splitn(2, ';');
+                let plugin = parts.next().unwrap_or("").to_string();
+                let opts = parts.next().map(|o| o.to_string());
+                if !plugin.is_empty() {
+                    config.set_plugin(plugin, opts);
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Create a config with 30s timeouts and 10 idle connections, used for servers from
+    /// `ss://` urls and SIP008 files that don't set them.
+    pub fn with_defaults(
+        name: String,
+        addr: ServerAddr,
+        password: String,
+        method: CipherType,
+    ) -> ServerConfig {
         ServerConfig::new(
-            addr.to_string(),
-            ServerAddr::SocketAddr(addr),
+            name,
+            addr,
             password,
             method,
             Duration::from_secs(30),
@@ -179,6 +314,16 @@ impl ServerConfig {
         )
     }
 
+    /// Create a basic config
+    pub fn basic(addr: SocketAddr, password: String, method: CipherType) -> ServerConfig {
+        ServerConfig::with_defaults(
+            addr.to_string(),
+            ServerAddr::SocketAddr(addr),
+            password,
+            method,
+        )
+    }
+
     /// Set encryption method
     pub fn set_method(&mut self, t: CipherType, pwd: String) {
         self.password = pwd;
@@ -234,4 +379,128 @@ impl ServerConfig {
     pub fn idle_connections(&self) -> usize {
         self.idle_connections
     }
+
+    /// Set SIP003 plugin and its options
+    pub fn set_plugin(&mut self, plugin: String, plugin_opts: Option<String>) {
+        self.plugin = Some(plugin);
+        self.plugin_opts = plugin_opts;
+    }
+
+    /// Get SIP003 plugin
+    pub fn plugin(&self) -> Option<&str> {
+        self.plugin.as_ref().map(|p| p.as_str())
+    }
+
+    /// Get SIP003 plugin options
+    pub fn plugin_opts(&self) -> Option<&str> {
+        self.plugin_opts.as_ref().map(|p| p.as_str())
+    }
+
+    /// Set server type
+    pub fn set_server_type(&mut self, server_type: ServerType) {
+        self.server_type = server_type;
+    }
+
+    /// Get server type
+    pub fn server_type(&self) -> ServerType {
+        self.server_type
+    }
+
+    /// Set user name of socks5 and http servers
+    pub fn set_username(&mut self, username: String) {
+        self.username = Some(username);
+    }
+
+    /// Get user name of socks5 and http servers
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_ref().map(|u| u.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_server_type() {
+        let config: ServerConfig = serde_yaml::from_str(
+            r#"
+name: socks5-server
+type: socks5
+addr: 127.0.0.1:1080
+username: user
+password: pass
+connect_timeout: 5s
+read_timeout: 30s
+write_timeout: 30s
+idle_connections: 0
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.server_type(), ServerType::Socks5);
+        assert_eq!(config.username(), Some("user"));
+        assert_eq!(config.password(), "pass");
+        assert_eq!(config.method(), CipherType::Plain);
+
+        let config: ServerConfig = serde_yaml::from_str(
+            r#"
+name: http-server
+type: http
+addr: example.com:3128
+connect_timeout: 5s
+read_timeout: 30s
+write_timeout: 30s
+idle_connections: 0
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.server_type(), ServerType::Http);
+        assert_eq!(config.username(), None);
+        assert_eq!(config.password(), "");
+    }
+
+    #[test]
+    fn test_from_url() {
+        let config = ServerConfig::from_url(
+            "ss://YWVzLTI1Ni1nY206cGFzcw@192.168.100.1:8888/?plugin=obfs-local%3Bobfs%3Dhttp#Example%20Server",
+        )
+        .unwrap();
+        assert_eq!(config.name(), "Example Server");
+        assert_eq!(
+            config.method(),
+            CipherType::from_str("aes-256-gcm").unwrap()
+        );
+        assert_eq!(config.password(), "pass");
+        assert_eq!(config.addr().to_string(), "192.168.100.1:8888");
+        assert_eq!(config.plugin(), Some("obfs-local"));
+        assert_eq!(config.plugin_opts(), Some("obfs=http"));
+
+        let config = ServerConfig::from_url("ss://cmM0LW1kNTpwYXNzd2Q=@example.com:8888").unwrap();
+        assert_eq!(config.name(), "example.com:8888");
+        assert_eq!(config.password(), "passwd");
+        assert_eq!(config.plugin(), None);
+
+        let config =
+            ServerConfig::from_url("ss://chacha20-ietf-poly1305:p%40ss@example.com:8888").unwrap();
+        assert_eq!(config.password(), "p@ss");
+    }
+
+    #[test]
+    fn test_from_legacy_url() {
+        let config = ServerConfig::from_url(
+            "ss://Y2hhY2hhMjAtaWV0Zi1wb2x5MTMwNTpwQHNzQDE5Mi4xNjguMTAwLjE6ODg4OA==#name",
+        )
+        .unwrap();
+        assert_eq!(config.name(), "name");
+        assert_eq!(config.password(), "p@ss");
+        assert_eq!(config.addr().to_string(), "192.168.100.1:8888");
+    }
+
+    #[test]
+    fn test_from_invalid_url() {
+        assert!(ServerConfig::from_url("http://example.com").is_err());
+        assert!(ServerConfig::from_url("ss://not-base64!@example.com:8888").is_err());
+        assert!(ServerConfig::from_url("ss://YWVzLTI1Ni1nY206cGFzcw@example.com").is_err());
+        assert!(ServerConfig::from_url("ss://dW5rbm93bjpwYXNz@example.com:8888").is_err());
+    }
 }
@@ -0,0 +1,130 @@
+use crate::{Error, ServerAddr, ServerConfig};
+use crypto::CipherType;
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+
+/// SIP008 online configuration document
+#[derive(Deserialize)]
+struct Document {
+    servers: Vec<Server>,
+}
+
+#[derive(Deserialize)]
+struct Server {
+    #[serde(default)]
+    remarks: Option<String>,
+    server: String,
+    server_port: u16,
+    password: String,
+    method: String,
+    #[serde(default)]
+    plugin: Option<String>,
+    #[serde(default)]
+    plugin_opts: Option<String>,
+}
+
+/// Load servers from a SIP008 json file. Timeouts and idle connections are set to the
+/// defaults of `ServerConfig::with_defaults`.
+pub(crate) fn load_servers(path: &Path) -> Result<Vec<ServerConfig>, Error> {
+    let content = std::fs::read_to_string(path).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+    parse_servers(&content, path)
+}
+
+fn parse_servers(content: &str, path: &Path) -> Result<Vec<ServerConfig>, Error> {
+    let document: Document =
+        serde_json::from_str(content).map_err(|e| Error::Json(path.to_path_buf(), e))?;
+    document
+        .servers
+        .into_iter()
+        .map(|server| {
+            // `server` is a bare host, IPv6 addresses have no brackets
+            let addr = match server.server.parse::<IpAddr>() {
+                Ok(ip) => ServerAddr::SocketAddr(SocketAddr::new(ip, server.server_port)),
+                Err(_) => ServerAddr::DomainName(server.server.clone(), server.server_port),
+            };
+            let name = server.remarks.unwrap_or_else(|| addr.to_string());
+            let invalid = |reason: String| Error::InvalidServer {
+                name: name.clone(),
+                reason,
+            };
+            if server.server.is_empty() {
+                return Err(invalid("empty server address".to_string()));
+            }
+            let method = CipherType::from_str(&server.method)
+                .map_err(|_| invalid(format!("unknown method {}", server.method)))?;
+            let mut config =
+                ServerConfig::with_defaults(name.clone(), addr, server.password, method);
+            if let Some(plugin) = server.plugin.filter(|p| !p.is_empty()) {
+                config.set_plugin(plugin, server.plugin_opts);
+            }
+            Ok(config)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_servers() {
+        let content = r#"{
+    "version": 1,
+    "servers": [
+        {
+            "id": "27b8a625-4f4b-4428-9f0f-8a2317db7c79",
+            "remarks": "Name of the server",
+            "server": "example.com",
+            "server_port": 8388,
+            "password": "example",
+            "method": "chacha20-ietf-poly1305",
+            "plugin": "xxx",
+            "plugin_opts": "xxxxx"
+        },
+        {
+            "server": "192.168.100.1",
+            "server_port": 8389,
+            "password": "example",
+            "method": "aes-256-gcm",
+            "plugin": ""
+        },
+        {
+            "server": "2001:db8::1",
+            "server_port": 8390,
+            "password": "example",
+            "method": "aes-256-gcm"
+        }
+    ],
+    "bytes_used": 274877906944,
+    "bytes_remaining": 824633720832
+}"#;
+        let servers = parse_servers(content, Path::new("servers.json")).unwrap();
+        assert_eq!(servers.len(), 3);
+        assert_eq!(servers[0].name(), "Name of the server");
+        assert_eq!(servers[0].addr().to_string(), "example.com:8388");
+        assert_eq!(servers[0].plugin(), Some("xxx"));
+        assert_eq!(servers[0].plugin_opts(), Some("xxxxx"));
+        assert_eq!(servers[1].name(), "192.168.100.1:8389");
+        assert_eq!(servers[1].plugin(), None);
+        assert_eq!(servers[2].name(), "[2001:db8::1]:8390");
+        assert_eq!(
+            servers[2].addr(),
+            &ServerAddr::SocketAddr("[2001:db8::1]:8390".parse().unwrap())
+        );
+        assert_eq!(servers[2].connect_timeout(), Duration::from_secs(30));
+        assert_eq!(servers[2].idle_connections(), 10);
+    }
+
+    #[test]
+    fn test_parse_invalid_servers() {
+        assert!(parse_servers("{}", Path::new("servers.json")).is_err());
+        let content = r#"{"servers": [{"server": "example.com", "server_port": 8388, "password": "p", "method": "unknown"}]}"#;
+        match parse_servers(content, Path::new("servers.json")) {
+            Err(Error::InvalidServer { name, .. }) => assert_eq!(name, "example.com:8388"),
+            r => panic!("{:?}", r.map(|_| ())),
+        }
+    }
+}
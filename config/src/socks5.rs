@@ -7,7 +7,7 @@ use std::{fmt, io, vec};
 
 #[allow(dead_code)]
 #[rustfmt::skip]
-mod consts {
+pub mod consts {
     pub const SOCKS5_VERSION:                          u8 = 0x05;
 
     pub const SOCKS5_AUTH_METHOD_NONE:                 u8 = 0x00;
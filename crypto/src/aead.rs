@@ -1,13 +1,17 @@
 //! Aead Ciphers
 
-use super::cipher::{CipherCategory, CipherResult, CipherType};
+use super::cipher::{CipherCategory, CipherResult, CipherType, Error};
 
+#[cfg(feature = "pure-rust")]
+use super::pure_rust::RustAeadCipher;
 use super::ring::RingAeadCipher;
 #[cfg(feature = "miscreant")]
 use super::siv::MiscreantCipher;
 #[cfg(feature = "sodium")]
 use super::sodium::SodiumAeadCipher;
 
+use aes::cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::{Aes128, Aes256};
 use bytes::{Bytes, BytesMut};
 use ring::{digest::SHA1, hkdf, hmac::SigningKey};
 
@@ -41,15 +45,22 @@ pub type BoxAeadEncryptor = Box<dyn AeadEncryptor + Send + 'static>;
 
 /// Generate a specific AEAD cipher encryptor
 pub fn new_aead_encryptor(t: CipherType, key: &[u8], nonce: &[u8]) -> BoxAeadEncryptor {
-    assert!(t.category() == CipherCategory::Aead);
+    assert!(t.category() != CipherCategory::Stream);
 
     match t {
-        CipherType::Aes128Gcm | CipherType::Aes256Gcm | CipherType::ChaCha20IetfPoly1305 => {
+        CipherType::Aes128Gcm
+        | CipherType::Aes256Gcm
+        | CipherType::ChaCha20IetfPoly1305
+        | CipherType::Aead2022Blake3Aes128Gcm
+        | CipherType::Aead2022Blake3Aes256Gcm
+        | CipherType::Aead2022Blake3ChaCha20Poly1305 => {
             Box::new(RingAeadCipher::new(t, key, nonce, true))
         }
 
         #[cfg(feature = "sodium")]
         CipherType::XChaCha20IetfPoly1305 => Box::new(SodiumAeadCipher::new(t, key, nonce)),
+        #[cfg(all(feature = "pure-rust", not(feature = "sodium")))]
+        CipherType::XChaCha20IetfPoly1305 => Box::new(RustAeadCipher::new(t, key, nonce)),
 
         #[cfg(feature = "miscreant")]
         CipherType::Aes128PmacSiv | CipherType::Aes256PmacSiv => {
@@ -62,15 +73,22 @@ pub fn new_aead_encryptor(t: CipherType, key: &[u8], nonce: &[u8]) -> BoxAeadEnc
 
 /// Generate a specific AEAD cipher decryptor
 pub fn new_aead_decryptor(t: CipherType, key: &[u8], nonce: &[u8]) -> BoxAeadDecryptor {
-    assert!(t.category() == CipherCategory::Aead);
+    assert!(t.category() != CipherCategory::Stream);
 
     match t {
-        CipherType::Aes128Gcm | CipherType::Aes256Gcm | CipherType::ChaCha20IetfPoly1305 => {
+        CipherType::Aes128Gcm
+        | CipherType::Aes256Gcm
+        | CipherType::ChaCha20IetfPoly1305
+        | CipherType::Aead2022Blake3Aes128Gcm
+        | CipherType::Aead2022Blake3Aes256Gcm
+        | CipherType::Aead2022Blake3ChaCha20Poly1305 => {
             Box::new(RingAeadCipher::new(t, key, nonce, false))
         }
 
         #[cfg(feature = "sodium")]
         CipherType::XChaCha20IetfPoly1305 => Box::new(SodiumAeadCipher::new(t, key, nonce)),
+        #[cfg(all(feature = "pure-rust", not(feature = "sodium")))]
+        CipherType::XChaCha20IetfPoly1305 => Box::new(RustAeadCipher::new(t, key, nonce)),
 
         #[cfg(feature = "miscreant")]
         CipherType::Aes128PmacSiv | CipherType::Aes256PmacSiv => {
@@ -81,7 +99,86 @@ pub fn new_aead_decryptor(t: CipherType, key: &[u8], nonce: &[u8]) -> BoxAeadDec
     }
 }
 
+/// Generate an AEAD cipher encryptor which uses `key` as is, without deriving a session subkey,
+/// and counts the nonce from `nonce` instead of zero.
+///
+/// SIP022 UDP packets are sealed this way, each with its own nonce. Packets of
+/// `2022-blake3-chacha20-poly1305` are sealed with XChaCha20-Poly1305, which requires the
+/// `sodium` or the `pure-rust` feature.
+pub fn new_aead_encryptor_with_nonce(
+    t: CipherType,
+    key: &[u8],
+    nonce: &[u8],
+) -> CipherResult<BoxAeadEncryptor> {
+    match t {
+        CipherType::Aes128Gcm
+        | CipherType::Aes256Gcm
+        | CipherType::ChaCha20IetfPoly1305
+        | CipherType::Aead2022Blake3Aes128Gcm
+        | CipherType::Aead2022Blake3Aes256Gcm => {
+            Ok(Box::new(RingAeadCipher::with_nonce(t, key, nonce, true)))
+        }
+
+        #[cfg(feature = "sodium")]
+        CipherType::XChaCha20IetfPoly1305 | CipherType::Aead2022Blake3ChaCha20Poly1305 => {
+            Ok(Box::new(SodiumAeadCipher::with_nonce(
+                CipherType::XChaCha20IetfPoly1305,
+                key,
+                nonce,
+            )))
+        }
+        #[cfg(all(feature = "pure-rust", not(feature = "sodium")))]
+        CipherType::XChaCha20IetfPoly1305 | CipherType::Aead2022Blake3ChaCha20Poly1305 => {
+            Ok(Box::new(RustAeadCipher::with_nonce(
+                CipherType::XChaCha20IetfPoly1305,
+                key,
+                nonce,
+            )))
+        }
+
+        _ => Err(Error::UnknownCipherType),
+    }
+}
+
+/// Generate an AEAD cipher decryptor which uses `key` as is, without deriving a session subkey,
+/// and counts the nonce from `nonce` instead of zero. See `new_aead_encryptor_with_nonce`.
+pub fn new_aead_decryptor_with_nonce(
+    t: CipherType,
+    key: &[u8],
+    nonce: &[u8],
+) -> CipherResult<BoxAeadDecryptor> {
+    match t {
+        CipherType::Aes128Gcm
+        | CipherType::Aes256Gcm
+        | CipherType::ChaCha20IetfPoly1305
+        | CipherType::Aead2022Blake3Aes128Gcm
+        | CipherType::Aead2022Blake3Aes256Gcm => {
+            Ok(Box::new(RingAeadCipher::with_nonce(t, key, nonce, false)))
+        }
+
+        #[cfg(feature = "sodium")]
+        CipherType::XChaCha20IetfPoly1305 | CipherType::Aead2022Blake3ChaCha20Poly1305 => {
+            Ok(Box::new(SodiumAeadCipher::with_nonce(
+                CipherType::XChaCha20IetfPoly1305,
+                key,
+                nonce,
+            )))
+        }
+        #[cfg(all(feature = "pure-rust", not(feature = "sodium")))]
+        CipherType::XChaCha20IetfPoly1305 | CipherType::Aead2022Blake3ChaCha20Poly1305 => {
+            Ok(Box::new(RustAeadCipher::with_nonce(
+                CipherType::XChaCha20IetfPoly1305,
+                key,
+                nonce,
+            )))
+        }
+
+        _ => Err(Error::UnknownCipherType),
+    }
+}
+
 const SUBKEY_INFO: &[u8] = b"ss-subkey";
+const SUBKEY_CONTEXT_2022: &str = "shadowsocks 2022 session subkey";
 
 /// Make Session key
 ///
@@ -111,7 +208,18 @@ const SUBKEY_INFO: &[u8] = b"ss-subkey";
 /// 4. For each chunk, encrypt and authenticate payload using SK with a counting nonce
 ///    (starting from 0 and increment by 1 after each use)
 /// 5. Send encrypted chunk
+///
+/// ## Session key (SIP022)
+///
+/// AEAD 2022 ciphers derive the subkey with BLAKE3 instead:
+///
+/// ```plain
+/// session_subkey := blake3::derive_key(context: "shadowsocks 2022 session subkey", key_material: key + salt)
+/// ```
 pub fn make_skey(t: CipherType, key: &[u8], salt: &[u8]) -> Bytes {
+    if t.category() == CipherCategory::Aead2022 {
+        return make_skey_2022(key, salt);
+    }
     assert!(t.category() == CipherCategory::Aead);
 
     let salt = SigningKey::new(&SHA1, salt);
@@ -126,6 +234,38 @@ pub fn make_skey(t: CipherType, key: &[u8], salt: &[u8]) -> Bytes {
     skey.freeze()
 }
 
+fn make_skey_2022(key: &[u8], salt: &[u8]) -> Bytes {
+    let mut hasher = blake3::Hasher::new_derive_key(SUBKEY_CONTEXT_2022);
+    hasher.update(key);
+    hasher.update(salt);
+
+    let mut skey = vec![0; key.len()];
+    hasher.finalize_xof().fill(&mut skey);
+    Bytes::from(skey)
+}
+
+/// Encrypt one 16 bytes block in place with AES-128 or AES-256, picked by the length of `key`
+///
+/// SIP022 encrypts the separate header of UDP packets this way with the pre-shared key.
+pub fn aes_encrypt_block(key: &[u8], block: &mut [u8]) {
+    let block = GenericArray::from_mut_slice(block);
+    match key.len() {
+        16 => Aes128::new(GenericArray::from_slice(key)).encrypt_block(block),
+        32 => Aes256::new(GenericArray::from_slice(key)).encrypt_block(block),
+        n => panic!("invalid aes key length {}", n),
+    }
+}
+
+/// Decrypt one 16 bytes block in place with AES-128 or AES-256, picked by the length of `key`
+pub fn aes_decrypt_block(key: &[u8], block: &mut [u8]) {
+    let block = GenericArray::from_mut_slice(block);
+    match key.len() {
+        16 => Aes128::new(GenericArray::from_slice(key)).decrypt_block(block),
+        32 => Aes256::new(GenericArray::from_slice(key)).decrypt_block(block),
+        n => panic!("invalid aes key length {}", n),
+    }
+}
+
 /// Increase nonce by 1
 ///
 /// AEAD ciphers requires to increase nonce after encrypt/decrypt every chunk
@@ -150,3 +290,43 @@ pub fn increase_nonce(nonce: &mut [u8]) {
         prev >>= 8;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aes_block() {
+        // FIPS-197 appendix C.1
+        let key = (0..16).collect::<Vec<u8>>();
+        let plain = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+            0xee, 0xff,
+        ];
+        let mut block = plain;
+        aes_encrypt_block(&key, &mut block);
+        assert_eq!(
+            block,
+            [
+                0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+                0xc5, 0x5a
+            ]
+        );
+        aes_decrypt_block(&key, &mut block);
+        assert_eq!(block, plain);
+    }
+
+    #[test]
+    fn test_make_skey_2022() {
+        let t = CipherType::Aead2022Blake3Aes128Gcm;
+        let key = [1; 16];
+        let skey = make_skey(t, &key, &[2; 16]);
+        assert_eq!(skey.len(), 16);
+        assert_ne!(skey, make_skey(t, &key, &[3; 16]));
+        // The 16 bytes subkey is a prefix of the 32 bytes one.
+        let mut hasher = blake3::Hasher::new_derive_key(SUBKEY_CONTEXT_2022);
+        hasher.update(&key);
+        hasher.update(&[2; 16]);
+        assert_eq!(&skey[..], &hasher.finalize().as_bytes()[..16]);
+    }
+}
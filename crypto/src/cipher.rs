@@ -11,7 +11,7 @@ use super::digest::{self, Digest, DigestType};
 use bytes::{BufMut, Bytes, BytesMut};
 #[cfg(feature = "camellia-cfb")]
 use openssl::nid::Nid;
-#[cfg(feature = "openssl")]
+#[cfg(feature = "camellia-cfb")]
 use openssl::symm;
 use rand::{self, RngCore};
 use ring::aead::{AES_128_GCM, AES_256_GCM, CHACHA20_POLY1305};
@@ -141,13 +141,13 @@ const CIPHER_RC4_MD5: &str = "rc4-md5";
 
 const CIPHER_TABLE: &str = "table";
 
-#[cfg(feature = "sodium")]
+#[cfg(any(feature = "sodium", feature = "pure-rust"))]
 const CIPHER_CHACHA20: &str = "chacha20";
-#[cfg(feature = "sodium")]
+#[cfg(any(feature = "sodium", feature = "pure-rust"))]
 const CIPHER_SALSA20: &str = "salsa20";
-#[cfg(feature = "sodium")]
+#[cfg(any(feature = "sodium", feature = "pure-rust"))]
 const CIPHER_XSALSA20: &str = "xsalsa20";
-#[cfg(feature = "sodium")]
+#[cfg(any(feature = "sodium", feature = "pure-rust"))]
 const CIPHER_CHACHA20_IETF: &str = "chacha20-ietf";
 
 #[cfg(feature = "miscreant")]
@@ -160,11 +160,15 @@ const CIPHER_PLAIN: &str = "plain";
 const CIPHER_AES_128_GCM: &str = "aes-128-gcm";
 const CIPHER_AES_256_GCM: &str = "aes-256-gcm";
 const CIPHER_CHACHA20_IETF_POLY1305: &str = "chacha20-ietf-poly1305";
-#[cfg(feature = "sodium")]
+#[cfg(any(feature = "sodium", feature = "pure-rust"))]
 const CIPHER_XCHACHA20_IETF_POLY1305: &str = "xchacha20-ietf-poly1305";
 
+const CIPHER_AEAD_2022_BLAKE3_AES_128_GCM: &str = "2022-blake3-aes-128-gcm";
+const CIPHER_AEAD_2022_BLAKE3_AES_256_GCM: &str = "2022-blake3-aes-256-gcm";
+const CIPHER_AEAD_2022_BLAKE3_CHACHA20_POLY1305: &str = "2022-blake3-chacha20-poly1305";
+
 /// ShadowSocks cipher type
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
 pub enum CipherType {
     Table,
     Plain,
@@ -233,26 +237,30 @@ pub enum CipherType {
     #[cfg(feature = "rc4")]
     Rc4Md5,
 
-    #[cfg(feature = "sodium")]
+    #[cfg(any(feature = "sodium", feature = "pure-rust"))]
     ChaCha20,
-    #[cfg(feature = "sodium")]
+    #[cfg(any(feature = "sodium", feature = "pure-rust"))]
     Salsa20,
-    #[cfg(feature = "sodium")]
+    #[cfg(any(feature = "sodium", feature = "pure-rust"))]
     XSalsa20,
-    #[cfg(feature = "sodium")]
+    #[cfg(any(feature = "sodium", feature = "pure-rust"))]
     ChaCha20Ietf,
 
     Aes128Gcm,
     Aes256Gcm,
 
     ChaCha20IetfPoly1305,
-    #[cfg(feature = "sodium")]
+    #[cfg(any(feature = "sodium", feature = "pure-rust"))]
     XChaCha20IetfPoly1305,
 
     #[cfg(feature = "miscreant")]
     Aes128PmacSiv,
     #[cfg(feature = "miscreant")]
     Aes256PmacSiv,
+
+    Aead2022Blake3Aes128Gcm,
+    Aead2022Blake3Aes256Gcm,
+    Aead2022Blake3ChaCha20Poly1305,
 }
 
 /// Category of ciphers
@@ -262,6 +270,9 @@ pub enum CipherCategory {
     Stream,
     /// AEAD ciphers is used in modern ShadowSocks protocol, which sends data in separate packets
     Aead,
+    /// AEAD ciphers of ShadowSocks 2022 (SIP022), which use a base64 encoded key instead of a
+    /// password and derive session subkeys with BLAKE3
+    Aead2022,
 }
 
 impl CipherType {
@@ -271,36 +282,27 @@ impl CipherType {
             CipherType::Table | CipherType::Plain => 0,
 
             #[cfg(feature = "aes-cfb")]
-            CipherType::Aes128Cfb1 => symm::Cipher::aes_128_cfb1().key_len(),
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes128Cfb8 => symm::Cipher::aes_128_cfb8().key_len(),
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes128Cfb | CipherType::Aes128Cfb128 => {
-                symm::Cipher::aes_128_cfb128().key_len()
-            }
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes192Cfb1 => symm::Cipher::aes_192_cfb1().key_len(),
+            CipherType::Aes128Cfb
+            | CipherType::Aes128Cfb1
+            | CipherType::Aes128Cfb8
+            | CipherType::Aes128Cfb128 => 16,
             #[cfg(feature = "aes-cfb")]
-            CipherType::Aes192Cfb8 => symm::Cipher::aes_192_cfb8().key_len(),
+            CipherType::Aes192Cfb
+            | CipherType::Aes192Cfb1
+            | CipherType::Aes192Cfb8
+            | CipherType::Aes192Cfb128 => 24,
             #[cfg(feature = "aes-cfb")]
-            CipherType::Aes192Cfb | CipherType::Aes192Cfb128 => {
-                symm::Cipher::aes_192_cfb128().key_len()
-            }
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes256Cfb1 => symm::Cipher::aes_256_cfb1().key_len(),
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes256Cfb8 => symm::Cipher::aes_256_cfb8().key_len(),
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes256Cfb | CipherType::Aes256Cfb128 => {
-                symm::Cipher::aes_256_cfb128().key_len()
-            }
+            CipherType::Aes256Cfb
+            | CipherType::Aes256Cfb1
+            | CipherType::Aes256Cfb8
+            | CipherType::Aes256Cfb128 => 32,
 
             #[cfg(feature = "aes-ctr")]
-            CipherType::Aes128Ctr => symm::Cipher::aes_128_ctr().key_len(),
+            CipherType::Aes128Ctr => 16,
             #[cfg(feature = "aes-ctr")]
-            CipherType::Aes192Ctr => symm::Cipher::aes_192_ctr().key_len(),
+            CipherType::Aes192Ctr => 24,
             #[cfg(feature = "aes-ctr")]
-            CipherType::Aes256Ctr => symm::Cipher::aes_256_ctr().key_len(),
+            CipherType::Aes256Ctr => 32,
 
             #[cfg(feature = "camellia-cfb")]
             CipherType::Camellia128Cfb => symm::Cipher::from_nid(Nid::CAMELLIA_128_CFB128)
@@ -352,9 +354,9 @@ impl CipherType {
                 .key_len(),
 
             #[cfg(feature = "rc4")]
-            CipherType::Rc4 | CipherType::Rc4Md5 => symm::Cipher::rc4().key_len(),
+            CipherType::Rc4 | CipherType::Rc4Md5 => 16,
 
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CipherType::ChaCha20
             | CipherType::Salsa20
             | CipherType::XSalsa20
@@ -365,13 +367,17 @@ impl CipherType {
 
             CipherType::ChaCha20IetfPoly1305 => CHACHA20_POLY1305.key_len(),
 
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CipherType::XChaCha20IetfPoly1305 => 32,
 
             #[cfg(feature = "miscreant")]
             CipherType::Aes128PmacSiv => 32,
             #[cfg(feature = "miscreant")]
             CipherType::Aes256PmacSiv => 64,
+
+            CipherType::Aead2022Blake3Aes128Gcm => AES_128_GCM.key_len(),
+            CipherType::Aead2022Blake3Aes256Gcm => AES_256_GCM.key_len(),
+            CipherType::Aead2022Blake3ChaCha20Poly1305 => CHACHA20_POLY1305.key_len(),
         }
     }
 
@@ -407,8 +413,16 @@ impl CipherType {
     }
 
     /// Extends key to match the required key length
+    ///
+    /// Keys of AEAD 2022 ciphers are base64 decoded instead. The result is empty if `key` is not
+    /// valid base64, callers should check its length against `key_size`.
     pub fn bytes_to_key(self, key: &[u8]) -> Bytes {
-        self.classic_bytes_to_key(key)
+        match self.category() {
+            CipherCategory::Aead2022 => base64::decode(key)
+                .map(Bytes::from)
+                .unwrap_or_else(|_| Bytes::new()),
+            _ => self.classic_bytes_to_key(key),
+        }
     }
 
     /// Symmetric crypto initialize vector size
@@ -417,54 +431,21 @@ impl CipherType {
             CipherType::Table | CipherType::Plain => 0,
 
             #[cfg(feature = "aes-cfb")]
-            CipherType::Aes128Cfb1 => symm::Cipher::aes_128_cfb1()
-                .iv_len()
-                .expect("iv_len should not be None"),
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes128Cfb8 => symm::Cipher::aes_128_cfb8()
-                .iv_len()
-                .expect("iv_len should not be None"),
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes128Cfb | CipherType::Aes128Cfb128 => symm::Cipher::aes_128_cfb128()
-                .iv_len()
-                .expect("iv_len should not be None"),
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes192Cfb1 => symm::Cipher::aes_192_cfb1()
-                .iv_len()
-                .expect("iv_len should not be None"),
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes192Cfb8 => symm::Cipher::aes_192_cfb8()
-                .iv_len()
-                .expect("iv_len should not be None"),
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes192Cfb | CipherType::Aes192Cfb128 => symm::Cipher::aes_192_cfb128()
-                .iv_len()
-                .expect("iv_len should not be None"),
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes256Cfb1 => symm::Cipher::aes_256_cfb1()
-                .iv_len()
-                .expect("iv_len should not be None"),
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes256Cfb8 => symm::Cipher::aes_256_cfb8()
-                .iv_len()
-                .expect("iv_len should not be None"),
-            #[cfg(feature = "aes-cfb")]
-            CipherType::Aes256Cfb | CipherType::Aes256Cfb128 => symm::Cipher::aes_256_cfb128()
-                .iv_len()
-                .expect("iv_len should not be None"),
+            CipherType::Aes128Cfb
+            | CipherType::Aes128Cfb1
+            | CipherType::Aes128Cfb8
+            | CipherType::Aes128Cfb128
+            | CipherType::Aes192Cfb
+            | CipherType::Aes192Cfb1
+            | CipherType::Aes192Cfb8
+            | CipherType::Aes192Cfb128
+            | CipherType::Aes256Cfb
+            | CipherType::Aes256Cfb1
+            | CipherType::Aes256Cfb8
+            | CipherType::Aes256Cfb128 => 16,
 
             #[cfg(feature = "aes-ctr")]
-            CipherType::Aes128Ctr => symm::Cipher::aes_128_ctr()
-                .iv_len()
-                .expect("iv_len should not be None"),
-            #[cfg(feature = "aes-ctr")]
-            CipherType::Aes192Ctr => symm::Cipher::aes_192_ctr()
-                .iv_len()
-                .expect("iv_len should not be None"),
-            #[cfg(feature = "aes-ctr")]
-            CipherType::Aes256Ctr => symm::Cipher::aes_256_ctr()
-                .iv_len()
-                .expect("iv_len should not be None"),
+            CipherType::Aes128Ctr | CipherType::Aes192Ctr | CipherType::Aes256Ctr => 16,
 
             #[cfg(feature = "camellia-cfb")]
             CipherType::Camellia128Cfb => symm::Cipher::from_nid(Nid::CAMELLIA_128_CFB128)
@@ -528,29 +509,31 @@ impl CipherType {
                 .expect("iv_len should not be None"),
 
             #[cfg(feature = "rc4")]
-            CipherType::Rc4 => symm::Cipher::rc4()
-                .iv_len()
-                .expect("iv_len should not be None"),
+            CipherType::Rc4 => 0,
             #[cfg(feature = "rc4")]
             CipherType::Rc4Md5 => 16,
 
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CipherType::ChaCha20 | CipherType::Salsa20 => 8,
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CipherType::XSalsa20 => 24,
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CipherType::ChaCha20Ietf => 12,
 
             CipherType::Aes128Gcm => AES_128_GCM.nonce_len(),
             CipherType::Aes256Gcm => AES_256_GCM.nonce_len(),
             CipherType::ChaCha20IetfPoly1305 => CHACHA20_POLY1305.nonce_len(),
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CipherType::XChaCha20IetfPoly1305 => 24,
 
             #[cfg(feature = "miscreant")]
             CipherType::Aes128PmacSiv => 8,
             #[cfg(feature = "miscreant")]
             CipherType::Aes256PmacSiv => 8,
+
+            CipherType::Aead2022Blake3Aes128Gcm => AES_128_GCM.nonce_len(),
+            CipherType::Aead2022Blake3Aes256Gcm => AES_256_GCM.nonce_len(),
+            CipherType::Aead2022Blake3ChaCha20Poly1305 => CHACHA20_POLY1305.nonce_len(),
         }
     }
 
@@ -578,37 +561,45 @@ impl CipherType {
                 CipherCategory::Aead
             }
 
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CipherType::XChaCha20IetfPoly1305 => CipherCategory::Aead,
 
             #[cfg(feature = "miscreant")]
             CipherType::Aes128PmacSiv | CipherType::Aes256PmacSiv => CipherCategory::Aead,
 
+            CipherType::Aead2022Blake3Aes128Gcm
+            | CipherType::Aead2022Blake3Aes256Gcm
+            | CipherType::Aead2022Blake3ChaCha20Poly1305 => CipherCategory::Aead2022,
+
             _ => CipherCategory::Stream,
         }
     }
 
     /// Get tag size for AEAD Ciphers
     pub fn tag_size(self) -> usize {
-        assert!(self.category() == CipherCategory::Aead);
+        assert!(self.category() != CipherCategory::Stream);
 
         match self {
             CipherType::Aes128Gcm => AES_128_GCM.tag_len(),
             CipherType::Aes256Gcm => AES_256_GCM.tag_len(),
             CipherType::ChaCha20IetfPoly1305 => CHACHA20_POLY1305.tag_len(),
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CipherType::XChaCha20IetfPoly1305 => 16,
 
             #[cfg(feature = "miscreant")]
             CipherType::Aes128PmacSiv | CipherType::Aes256PmacSiv => 16,
 
+            CipherType::Aead2022Blake3Aes128Gcm => AES_128_GCM.tag_len(),
+            CipherType::Aead2022Blake3Aes256Gcm => AES_256_GCM.tag_len(),
+            CipherType::Aead2022Blake3ChaCha20Poly1305 => CHACHA20_POLY1305.tag_len(),
+
             _ => panic!("Only support AEAD ciphers, found {:?}", self),
         }
     }
 
     /// Get nonce size for AEAD ciphers
     pub fn salt_size(self) -> usize {
-        assert!(self.category() == CipherCategory::Aead);
+        assert!(self.category() != CipherCategory::Stream);
         self.key_size()
     }
 
@@ -689,20 +680,20 @@ impl FromStr for CipherType {
             #[cfg(feature = "rc4")]
             CIPHER_RC4_MD5 => Ok(CipherType::Rc4Md5),
 
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CIPHER_CHACHA20 => Ok(CipherType::ChaCha20),
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CIPHER_SALSA20 => Ok(CipherType::Salsa20),
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CIPHER_XSALSA20 => Ok(CipherType::XSalsa20),
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CIPHER_CHACHA20_IETF => Ok(CipherType::ChaCha20Ietf),
 
             CIPHER_AES_128_GCM => Ok(CipherType::Aes128Gcm),
             CIPHER_AES_256_GCM => Ok(CipherType::Aes256Gcm),
 
             CIPHER_CHACHA20_IETF_POLY1305 => Ok(CipherType::ChaCha20IetfPoly1305),
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CIPHER_XCHACHA20_IETF_POLY1305 => Ok(CipherType::XChaCha20IetfPoly1305),
 
             #[cfg(feature = "miscreant")]
@@ -710,6 +701,12 @@ impl FromStr for CipherType {
             #[cfg(feature = "miscreant")]
             CIPHER_AES_256_PMAC_SIV => Ok(CipherType::Aes256PmacSiv),
 
+            CIPHER_AEAD_2022_BLAKE3_AES_128_GCM => Ok(CipherType::Aead2022Blake3Aes128Gcm),
+            CIPHER_AEAD_2022_BLAKE3_AES_256_GCM => Ok(CipherType::Aead2022Blake3Aes256Gcm),
+            CIPHER_AEAD_2022_BLAKE3_CHACHA20_POLY1305 => {
+                Ok(CipherType::Aead2022Blake3ChaCha20Poly1305)
+            }
+
             _ => Err(Error::UnknownCipherType),
         }
     }
@@ -784,25 +781,35 @@ impl Display for CipherType {
             #[cfg(feature = "rc4")]
             CipherType::Rc4Md5 => write!(f, "{}", CIPHER_RC4_MD5),
 
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CipherType::ChaCha20 => write!(f, "{}", CIPHER_CHACHA20),
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CipherType::Salsa20 => write!(f, "{}", CIPHER_SALSA20),
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CipherType::XSalsa20 => write!(f, "{}", CIPHER_XSALSA20),
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CipherType::ChaCha20Ietf => write!(f, "{}", CIPHER_CHACHA20_IETF),
 
             CipherType::Aes128Gcm => write!(f, "{}", CIPHER_AES_128_GCM),
             CipherType::Aes256Gcm => write!(f, "{}", CIPHER_AES_256_GCM),
             CipherType::ChaCha20IetfPoly1305 => write!(f, "{}", CIPHER_CHACHA20_IETF_POLY1305),
-            #[cfg(feature = "sodium")]
+            #[cfg(any(feature = "sodium", feature = "pure-rust"))]
             CipherType::XChaCha20IetfPoly1305 => write!(f, "{}", CIPHER_XCHACHA20_IETF_POLY1305),
 
             #[cfg(feature = "miscreant")]
             CipherType::Aes128PmacSiv => write!(f, "{}", CIPHER_AES_128_PMAC_SIV),
             #[cfg(feature = "miscreant")]
             CipherType::Aes256PmacSiv => write!(f, "{}", CIPHER_AES_256_PMAC_SIV),
+
+            CipherType::Aead2022Blake3Aes128Gcm => {
+                write!(f, "{}", CIPHER_AEAD_2022_BLAKE3_AES_128_GCM)
+            }
+            CipherType::Aead2022Blake3Aes256Gcm => {
+                write!(f, "{}", CIPHER_AEAD_2022_BLAKE3_AES_256_GCM)
+            }
+            CipherType::Aead2022Blake3ChaCha20Poly1305 => {
+                write!(f, "{}", CIPHER_AEAD_2022_BLAKE3_CHACHA20_POLY1305)
+            }
         }
     }
 }
@@ -842,6 +849,15 @@ mod test_cipher {
         assert!(message.as_bytes() == &decrypted_msg[..]);
     }
 
+    #[test]
+    fn test_aead_2022_key() {
+        let ty = CipherType::Aead2022Blake3Aes128Gcm;
+        assert_eq!("2022-blake3-aes-128-gcm".parse::<CipherType>().unwrap(), ty);
+        let key = ty.bytes_to_key(b"AAECAwQFBgcICQoLDA0ODw==");
+        assert_eq!(&key[..], &(0..16).collect::<Vec<u8>>()[..]);
+        assert!(ty.bytes_to_key(b"not base64!").is_empty());
+    }
+
     #[cfg(feature = "rc4")]
     #[test]
     fn test_rc4_md5_key_iv() {
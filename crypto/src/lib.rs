@@ -2,7 +2,8 @@
 
 pub use self::{
     aead::{
-        new_aead_decryptor, new_aead_encryptor, AeadDecryptor, AeadEncryptor, BoxAeadDecryptor,
+        new_aead_decryptor, new_aead_decryptor_with_nonce, new_aead_encryptor,
+        new_aead_encryptor_with_nonce, AeadDecryptor, AeadEncryptor, BoxAeadDecryptor,
         BoxAeadEncryptor,
     },
     cipher::{CipherCategory, CipherResult, CipherType},
@@ -17,6 +18,8 @@ pub mod digest;
 pub mod dummy;
 #[cfg(feature = "openssl")]
 pub mod openssl;
+#[cfg(feature = "pure-rust")]
+pub mod pure_rust;
 #[cfg(feature = "rc4")]
 pub mod rc4_md5;
 pub mod ring;
@@ -26,6 +29,14 @@ pub mod siv;
 pub mod sodium;
 pub mod stream;
 pub mod table;
+#[cfg(test)]
+mod test_vectors;
+
+#[cfg(all(
+    any(feature = "rc4", feature = "aes-cfb", feature = "aes-ctr"),
+    not(any(feature = "openssl", feature = "pure-rust"))
+))]
+compile_error!("rc4, aes-cfb and aes-ctr require the openssl or the pure-rust feature");
 
 /// Crypto mode, encrypt or decrypt
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
@@ -31,18 +31,24 @@ impl OpenSSLCrypto {
             #[cfg(feature = "aes-cfb")]
             CipherType::Aes128Cfb1 => symm::Cipher::aes_128_cfb1(),
             #[cfg(feature = "aes-cfb")]
+            CipherType::Aes128Cfb8 => symm::Cipher::aes_128_cfb8(),
+            #[cfg(feature = "aes-cfb")]
             CipherType::Aes128Cfb128 => symm::Cipher::aes_128_cfb128(),
             #[cfg(feature = "aes-cfb")]
             CipherType::Aes192Cfb => symm::Cipher::aes_192_cfb128(),
             #[cfg(feature = "aes-cfb")]
             CipherType::Aes192Cfb1 => symm::Cipher::aes_192_cfb1(),
             #[cfg(feature = "aes-cfb")]
+            CipherType::Aes192Cfb8 => symm::Cipher::aes_192_cfb8(),
+            #[cfg(feature = "aes-cfb")]
             CipherType::Aes192Cfb128 => symm::Cipher::aes_192_cfb128(),
             #[cfg(feature = "aes-cfb")]
             CipherType::Aes256Cfb => symm::Cipher::aes_256_cfb128(),
             #[cfg(feature = "aes-cfb")]
             CipherType::Aes256Cfb1 => symm::Cipher::aes_256_cfb1(),
             #[cfg(feature = "aes-cfb")]
+            CipherType::Aes256Cfb8 => symm::Cipher::aes_256_cfb8(),
+            #[cfg(feature = "aes-cfb")]
             CipherType::Aes256Cfb128 => symm::Cipher::aes_256_cfb128(),
 
             #[cfg(feature = "aes-ctr")]
@@ -0,0 +1,481 @@
+//! Ciphers implemented with RustCrypto crates
+//!
+//! They replace ciphers of OpenSSL and libsodium when the `openssl` or `sodium` feature is
+//! disabled, so seeker can be built without linking either library.
+
+use std::ptr;
+
+#[cfg(feature = "aes-cfb")]
+use aes::cipher::{
+    consts::U16, BlockCipher, BlockDecryptMut, BlockEncrypt, BlockEncryptMut, BlockSizeUser,
+};
+use aes::cipher::{
+    generic_array::GenericArray, KeyInit, KeyIvInit, StreamCipher as RustCryptoStreamCipher,
+};
+#[cfg(any(feature = "aes-cfb", feature = "aes-ctr"))]
+use aes::{Aes128, Aes192, Aes256};
+use bytes::{BufMut, BytesMut};
+use chacha20::{ChaCha20, ChaCha20Legacy};
+use chacha20poly1305::{aead::AeadInPlace, XChaCha20Poly1305};
+#[cfg(feature = "aes-ctr")]
+use ctr::Ctr128BE;
+use salsa20::{Salsa20, XSalsa20};
+
+use crate::{
+    aead::{increase_nonce, make_skey},
+    cipher::Error,
+    AeadDecryptor, AeadEncryptor, CipherResult, CipherType, CryptoMode, StreamCipher,
+};
+
+type Process = Box<dyn FnMut(&mut [u8]) + Send>;
+
+/// Stream cipher implemented in pure rust
+pub struct RustStreamCipher {
+    process: Process,
+}
+
+impl RustStreamCipher {
+    /// Creates an instance
+    #[cfg_attr(not(feature = "aes-cfb"), allow(unused_variables))]
+    pub fn new(t: CipherType, key: &[u8], iv: &[u8], mode: CryptoMode) -> RustStreamCipher {
+        let process = match t {
+            CipherType::ChaCha20 => new_process::<ChaCha20Legacy>(key, iv),
+            CipherType::ChaCha20Ietf => new_process::<ChaCha20>(key, iv),
+            CipherType::Salsa20 => new_process::<Salsa20>(key, iv),
+            CipherType::XSalsa20 => new_process::<XSalsa20>(key, iv),
+
+            #[cfg(feature = "aes-cfb")]
+            CipherType::Aes128Cfb | CipherType::Aes128Cfb128 => {
+                cfb_process::<Aes128>(key, iv, mode)
+            }
+            #[cfg(feature = "aes-cfb")]
+            CipherType::Aes192Cfb | CipherType::Aes192Cfb128 => {
+                cfb_process::<Aes192>(key, iv, mode)
+            }
+            #[cfg(feature = "aes-cfb")]
+            CipherType::Aes256Cfb | CipherType::Aes256Cfb128 => {
+                cfb_process::<Aes256>(key, iv, mode)
+            }
+            #[cfg(feature = "aes-cfb")]
+            CipherType::Aes128Cfb8 => cfb8_process::<Aes128>(key, iv, mode),
+            #[cfg(feature = "aes-cfb")]
+            CipherType::Aes192Cfb8 => cfb8_process::<Aes192>(key, iv, mode),
+            #[cfg(feature = "aes-cfb")]
+            CipherType::Aes256Cfb8 => cfb8_process::<Aes256>(key, iv, mode),
+            #[cfg(feature = "aes-cfb")]
+            CipherType::Aes128Cfb1 => Cfb1::<Aes128>::new_process(key, iv, mode),
+            #[cfg(feature = "aes-cfb")]
+            CipherType::Aes192Cfb1 => Cfb1::<Aes192>::new_process(key, iv, mode),
+            #[cfg(feature = "aes-cfb")]
+            CipherType::Aes256Cfb1 => Cfb1::<Aes256>::new_process(key, iv, mode),
+
+            #[cfg(feature = "aes-ctr")]
+            CipherType::Aes128Ctr => new_process::<Ctr128BE<Aes128>>(key, iv),
+            #[cfg(feature = "aes-ctr")]
+            CipherType::Aes192Ctr => new_process::<Ctr128BE<Aes192>>(key, iv),
+            #[cfg(feature = "aes-ctr")]
+            CipherType::Aes256Ctr => new_process::<Ctr128BE<Aes256>>(key, iv),
+
+            #[cfg(feature = "rc4")]
+            CipherType::Rc4 => {
+                let mut rc4 = Rc4::new(key);
+                Box::new(move |data: &mut [u8]| rc4.apply_keystream(data))
+            }
+
+            _ => panic!("pure rust cipher does not support {:?} cipher", t),
+        };
+
+        RustStreamCipher { process }
+    }
+}
+
+/// Stream ciphers which XOR the data with their key stream, the same for both directions
+fn new_process<C>(key: &[u8], iv: &[u8]) -> Process
+where
+    C: KeyIvInit + RustCryptoStreamCipher + Send + 'static,
+{
+    let mut cipher = C::new_from_slices(key, iv).expect("invalid key or iv length");
+    Box::new(move |data: &mut [u8]| cipher.apply_keystream(data))
+}
+
+/// AES in 128 bit CFB mode
+#[cfg(feature = "aes-cfb")]
+fn cfb_process<C>(key: &[u8], iv: &[u8], mode: CryptoMode) -> Process
+where
+    C: BlockEncryptMut + BlockCipher + KeyInit + Send + 'static,
+{
+    match mode {
+        CryptoMode::Encrypt => {
+            let mut cfb = cfb_mode::BufEncryptor::<C>::new_from_slices(key, iv)
+                .expect("invalid key or iv length");
+            Box::new(move |data: &mut [u8]| cfb.encrypt(data))
+        }
+        CryptoMode::Decrypt => {
+            let mut cfb = cfb_mode::BufDecryptor::<C>::new_from_slices(key, iv)
+                .expect("invalid key or iv length");
+            Box::new(move |data: &mut [u8]| cfb.decrypt(data))
+        }
+    }
+}
+
+/// AES in 8 bit CFB mode, whose blocks are single bytes
+#[cfg(feature = "aes-cfb")]
+fn cfb8_process<C>(key: &[u8], iv: &[u8], mode: CryptoMode) -> Process
+where
+    C: BlockEncryptMut + BlockCipher + KeyInit + Send + 'static,
+{
+    match mode {
+        CryptoMode::Encrypt => {
+            let mut cfb =
+                cfb8::Encryptor::<C>::new_from_slices(key, iv).expect("invalid key or iv length");
+            Box::new(move |data: &mut [u8]| {
+                for byte in data.chunks_mut(1) {
+                    cfb.encrypt_block_mut(byte.into());
+                }
+            })
+        }
+        CryptoMode::Decrypt => {
+            let mut cfb =
+                cfb8::Decryptor::<C>::new_from_slices(key, iv).expect("invalid key or iv length");
+            Box::new(move |data: &mut [u8]| {
+                for byte in data.chunks_mut(1) {
+                    cfb.decrypt_block_mut(byte.into());
+                }
+            })
+        }
+    }
+}
+
+impl StreamCipher for RustStreamCipher {
+    fn update(&mut self, data: &[u8], out: &mut dyn BufMut) -> CipherResult<()> {
+        let mut buf = data.to_vec();
+        (self.process)(&mut buf);
+        out.put_slice(&buf);
+        Ok(())
+    }
+
+    fn finalize(&mut self, _: &mut dyn BufMut) -> CipherResult<()> {
+        Ok(())
+    }
+
+    fn buffer_size(&self, data: &[u8]) -> usize {
+        data.len()
+    }
+}
+
+/// AES in 1 bit CFB mode, which RustCrypto doesn't provide
+///
+/// Every bit of data takes one block encryption of the shift register.
+#[cfg(feature = "aes-cfb")]
+struct Cfb1<C> {
+    cipher: C,
+    register: GenericArray<u8, U16>,
+    mode: CryptoMode,
+}
+
+#[cfg(feature = "aes-cfb")]
+impl<C: BlockEncrypt + BlockSizeUser<BlockSize = U16> + KeyInit + Send + 'static> Cfb1<C> {
+    fn new_process(key: &[u8], iv: &[u8], mode: CryptoMode) -> Process {
+        let mut cfb = Cfb1 {
+            cipher: C::new_from_slice(key).expect("invalid key length"),
+            register: GenericArray::clone_from_slice(iv),
+            mode,
+        };
+        Box::new(move |data: &mut [u8]| cfb.process(data))
+    }
+
+    fn process(&mut self, data: &mut [u8]) {
+        for byte in data {
+            let mut output = 0;
+            for i in (0..8).rev() {
+                let mut block = self.register.clone();
+                self.cipher.encrypt_block(&mut block);
+                let input_bit = (*byte >> i) & 1;
+                let output_bit = input_bit ^ (block[0] >> 7);
+                output |= output_bit << i;
+
+                let cipher_bit = match self.mode {
+                    CryptoMode::Encrypt => output_bit,
+                    CryptoMode::Decrypt => input_bit,
+                };
+                for j in 0..15 {
+                    self.register[j] = (self.register[j] << 1) | (self.register[j + 1] >> 7);
+                }
+                self.register[15] = (self.register[15] << 1) | cipher_bit;
+            }
+            *byte = output;
+        }
+    }
+}
+
+/// RC4, which RustCrypto doesn't provide
+#[cfg(feature = "rc4")]
+struct Rc4 {
+    state: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+#[cfg(feature = "rc4")]
+impl Rc4 {
+    fn new(key: &[u8]) -> Rc4 {
+        assert!(!key.is_empty() && key.len() <= 256);
+        let mut state = [0; 256];
+        for (i, s) in state.iter_mut().enumerate() {
+            *s = i as u8;
+        }
+        let mut j: u8 = 0;
+        for (i, &k) in (0..256).zip(key.iter().cycle()) {
+            j = j.wrapping_add(state[i]).wrapping_add(k);
+            state.swap(i, j as usize);
+        }
+        Rc4 { state, i: 0, j: 0 }
+    }
+
+    fn apply_keystream(&mut self, data: &mut [u8]) {
+        for byte in data {
+            self.i = self.i.wrapping_add(1);
+            self.j = self.j.wrapping_add(self.state[self.i as usize]);
+            self.state.swap(self.i as usize, self.j as usize);
+            let k = self.state
+                [self.state[self.i as usize].wrapping_add(self.state[self.j as usize]) as usize];
+            *byte ^= k;
+        }
+    }
+}
+
+/// XChaCha20-Poly1305 implemented in pure rust
+pub struct RustAeadCipher {
+    cipher: XChaCha20Poly1305,
+    nonce: BytesMut,
+}
+
+const XCHACHA20_POLY1305_TAG_SIZE: usize = 16;
+
+impl RustAeadCipher {
+    pub fn new(t: CipherType, key: &[u8], salt: &[u8]) -> RustAeadCipher {
+        let nonce_size = t.iv_size();
+        let mut nonce = BytesMut::with_capacity(nonce_size);
+        unsafe {
+            nonce.set_len(nonce_size);
+            ptr::write_bytes(nonce.as_mut_ptr(), 0, nonce_size);
+        }
+
+        let skey = make_skey(t, key, salt);
+        RustAeadCipher::with_nonce(t, &skey, &nonce)
+    }
+
+    /// Initialize with `key` as the subkey and `nonce` as the first nonce
+    pub fn with_nonce(t: CipherType, key: &[u8], nonce: &[u8]) -> RustAeadCipher {
+        match t {
+            CipherType::XChaCha20IetfPoly1305 => {}
+            _ => panic!("pure rust cipher does not support {:?} cipher", t),
+        }
+
+        RustAeadCipher {
+            cipher: XChaCha20Poly1305::new(GenericArray::from_slice(key)),
+            nonce: BytesMut::from(nonce),
+        }
+    }
+}
+
+impl AeadEncryptor for RustAeadCipher {
+    fn encrypt(&mut self, input: &[u8], output: &mut [u8]) {
+        assert_eq!(output.len(), input.len() + XCHACHA20_POLY1305_TAG_SIZE);
+
+        let (data, tag) = output.split_at_mut(input.len());
+        data.copy_from_slice(input);
+        let nonce = GenericArray::from_slice(&self.nonce);
+        let computed_tag = self
+            .cipher
+            .encrypt_in_place_detached(nonce, &[], data)
+            .expect("xchacha20-poly1305 encrypt failed");
+        tag.copy_from_slice(&computed_tag);
+
+        increase_nonce(&mut self.nonce);
+    }
+}
+
+impl AeadDecryptor for RustAeadCipher {
+    fn decrypt(&mut self, input: &[u8], output: &mut [u8]) -> CipherResult<()> {
+        if input.len() < XCHACHA20_POLY1305_TAG_SIZE
+            || output.len() != input.len() - XCHACHA20_POLY1305_TAG_SIZE
+        {
+            return Err(Error::AeadDecryptFailed);
+        }
+
+        let (data, tag) = input.split_at(output.len());
+        output.copy_from_slice(data);
+        let nonce = GenericArray::from_slice(&self.nonce);
+        self.cipher
+            .decrypt_in_place_detached(nonce, &[], output, GenericArray::from_slice(tag))
+            .map_err(|_| Error::AeadDecryptFailed)?;
+
+        increase_nonce(&mut self.nonce);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encrypt in chunks of different sizes, which must not change the result
+    fn update_in_chunks(cipher: &mut dyn StreamCipher, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut rest = data;
+        let mut size = 1;
+        while !rest.is_empty() {
+            let (chunk, remaining) = rest.split_at(size.min(rest.len()));
+            cipher.update(chunk, &mut out).unwrap();
+            rest = remaining;
+            size = size * 3 + 1;
+        }
+        cipher.finalize(&mut out).unwrap();
+        out
+    }
+
+    #[cfg(feature = "rc4")]
+    #[test]
+    fn test_rc4() {
+        // RFC 6229, key 0x0102030405
+        let mut rc4 = Rc4::new(&[1, 2, 3, 4, 5]);
+        let mut data = [0; 16];
+        rc4.apply_keystream(&mut data);
+        assert_eq!(
+            data,
+            [
+                0xb2, 0x39, 0x63, 0x05, 0xf0, 0x3d, 0xc0, 0x27, 0xcc, 0xc3, 0x52, 0x4a, 0x0a, 0x11,
+                0x18, 0xa8
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rust_stream_cipher() {
+        let data = (0..1000).map(|i| i as u8).collect::<Vec<u8>>();
+        let mut types = vec![
+            CipherType::ChaCha20,
+            CipherType::ChaCha20Ietf,
+            CipherType::Salsa20,
+            CipherType::XSalsa20,
+        ];
+        #[cfg(feature = "aes-cfb")]
+        types.extend_from_slice(&[
+            CipherType::Aes128Cfb,
+            CipherType::Aes192Cfb8,
+            CipherType::Aes256Cfb1,
+        ]);
+        #[cfg(feature = "aes-ctr")]
+        types.push(CipherType::Aes128Ctr);
+        #[cfg(feature = "rc4")]
+        types.push(CipherType::Rc4);
+
+        for t in types {
+            let key = t.bytes_to_key(b"key");
+            let iv = t.gen_init_vec();
+            let mut enc = RustStreamCipher::new(t, &key, &iv, CryptoMode::Encrypt);
+            let encrypted = update_in_chunks(&mut enc, &data);
+            assert_ne!(encrypted, data, "{}", t);
+            let mut dec = RustStreamCipher::new(t, &key, &iv, CryptoMode::Decrypt);
+            assert_eq!(update_in_chunks(&mut dec, &encrypted), data, "{}", t);
+        }
+    }
+
+    /// Encrypt with OpenSSL and decrypt in pure rust, and the other way around
+    #[cfg(feature = "openssl")]
+    #[test]
+    fn test_cross_backend_openssl() {
+        use crate::openssl::OpenSSLCipher;
+
+        let data = (0..1000).map(|i| i as u8).collect::<Vec<u8>>();
+        let mut types: Vec<CipherType> = Vec::new();
+        #[cfg(feature = "aes-cfb")]
+        types.extend_from_slice(&[
+            CipherType::Aes128Cfb,
+            CipherType::Aes192Cfb,
+            CipherType::Aes256Cfb,
+            CipherType::Aes128Cfb1,
+            CipherType::Aes192Cfb1,
+            CipherType::Aes256Cfb1,
+            CipherType::Aes128Cfb8,
+            CipherType::Aes192Cfb8,
+            CipherType::Aes256Cfb8,
+            CipherType::Aes128Cfb128,
+            CipherType::Aes192Cfb128,
+            CipherType::Aes256Cfb128,
+        ]);
+        #[cfg(feature = "aes-ctr")]
+        types.extend_from_slice(&[
+            CipherType::Aes128Ctr,
+            CipherType::Aes192Ctr,
+            CipherType::Aes256Ctr,
+        ]);
+        #[cfg(feature = "rc4")]
+        types.push(CipherType::Rc4);
+
+        for t in types {
+            let key = t.bytes_to_key(b"key");
+            let iv = t.gen_init_vec();
+
+            let mut enc = OpenSSLCipher::new(t, &key, &iv, CryptoMode::Encrypt);
+            let encrypted = update_in_chunks(&mut enc, &data);
+            let mut dec = RustStreamCipher::new(t, &key, &iv, CryptoMode::Decrypt);
+            assert_eq!(update_in_chunks(&mut dec, &encrypted), data, "{}", t);
+
+            let mut enc = RustStreamCipher::new(t, &key, &iv, CryptoMode::Encrypt);
+            let encrypted = update_in_chunks(&mut enc, &data);
+            let mut dec = OpenSSLCipher::new(t, &key, &iv, CryptoMode::Decrypt);
+            assert_eq!(update_in_chunks(&mut dec, &encrypted), data, "{}", t);
+        }
+    }
+
+    /// Encrypt with libsodium and decrypt in pure rust, and the other way around
+    #[cfg(feature = "sodium")]
+    #[test]
+    fn test_cross_backend_sodium() {
+        use crate::sodium::{SodiumAeadCipher, SodiumStreamCipher};
+
+        let data = (0..1000).map(|i| i as u8).collect::<Vec<u8>>();
+        for &t in &[
+            CipherType::ChaCha20,
+            CipherType::ChaCha20Ietf,
+            CipherType::Salsa20,
+            CipherType::XSalsa20,
+        ] {
+            let key = t.bytes_to_key(b"key");
+            let iv = t.gen_init_vec();
+
+            let mut enc = SodiumStreamCipher::new(t, &key, &iv);
+            let encrypted = update_in_chunks(&mut enc, &data);
+            let mut dec = RustStreamCipher::new(t, &key, &iv, CryptoMode::Decrypt);
+            assert_eq!(update_in_chunks(&mut dec, &encrypted), data, "{}", t);
+
+            let mut enc = RustStreamCipher::new(t, &key, &iv, CryptoMode::Encrypt);
+            let encrypted = update_in_chunks(&mut enc, &data);
+            let mut dec = SodiumStreamCipher::new(t, &key, &iv);
+            assert_eq!(update_in_chunks(&mut dec, &encrypted), data, "{}", t);
+        }
+
+        let t = CipherType::XChaCha20IetfPoly1305;
+        let key = t.bytes_to_key(b"key");
+        let salt = t.gen_salt();
+        let tag_size = t.tag_size();
+        let mut sodium_enc = SodiumAeadCipher::new(t, &key, &salt);
+        let mut sodium_dec = SodiumAeadCipher::new(t, &key, &salt);
+        let mut rust_enc = RustAeadCipher::new(t, &key, &salt);
+        let mut rust_dec = RustAeadCipher::new(t, &key, &salt);
+        // Several chunks, so both count their nonces the same way.
+        for chunk in data.chunks(300) {
+            let mut encrypted = vec![0; chunk.len() + tag_size];
+            let mut decrypted = vec![0; chunk.len()];
+            sodium_enc.encrypt(chunk, &mut encrypted);
+            rust_dec.decrypt(&encrypted, &mut decrypted).unwrap();
+            assert_eq!(&decrypted[..], chunk);
+
+            rust_enc.encrypt(chunk, &mut encrypted);
+            sodium_dec.decrypt(&encrypted, &mut decrypted).unwrap();
+            assert_eq!(&decrypted[..], chunk);
+        }
+    }
+}
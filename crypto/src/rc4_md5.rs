@@ -2,15 +2,14 @@
 
 use crate::{
     digest::{self, Digest, DigestType},
-    openssl::OpenSSLCrypto,
-    CipherResult, CipherType, CryptoMode, StreamCipher,
+    new_stream, BoxStreamCipher, CipherResult, CipherType, CryptoMode, StreamCipher,
 };
 
 use bytes::{BufMut, BytesMut};
 
-/// Rc4Md5 Cipher
+/// Rc4Md5 Cipher, RC4 with the MD5 of key and iv as its key
 pub struct Rc4Md5Cipher {
-    crypto: OpenSSLCrypto,
+    crypto: BoxStreamCipher,
 }
 
 impl Rc4Md5Cipher {
@@ -22,7 +21,7 @@ impl Rc4Md5Cipher {
         md5_digest.digest(&mut key);
 
         Rc4Md5Cipher {
-            crypto: OpenSSLCrypto::new(CipherType::Rc4, &key, b"", mode),
+            crypto: new_stream(CipherType::Rc4, &key, b"", mode),
         }
     }
 }
@@ -41,8 +40,6 @@ impl StreamCipher for Rc4Md5Cipher {
     }
 }
 
-unsafe impl Send for Rc4Md5Cipher {}
-
 #[cfg(test)]
 mod test {
     use super::*;
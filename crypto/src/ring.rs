@@ -46,12 +46,17 @@ impl RingAeadCipher {
         }
 
         let skey = make_skey(t, key, salt);
-        let cipher = RingAeadCipher::new_variant(t, &skey, &nonce, is_seal);
+        RingAeadCipher::with_nonce(t, &skey, &nonce, is_seal)
+    }
+
+    /// Initialize context with `key` as the subkey and `nonce` as the first nonce
+    pub fn with_nonce(t: CipherType, key: &[u8], nonce: &[u8], is_seal: bool) -> RingAeadCipher {
+        let cipher = RingAeadCipher::new_variant(t, key, nonce, is_seal);
         RingAeadCipher {
             cipher,
             cipher_type: t,
-            key: skey,
-            nonce,
+            key: Bytes::from(key),
+            nonce: BytesMut::from(nonce),
         }
     }
 
@@ -75,9 +80,15 @@ impl RingAeadCipher {
         }
 
         match t {
-            CipherType::Aes128Gcm => seal_or_open!(AES_128_GCM),
-            CipherType::Aes256Gcm => seal_or_open!(AES_256_GCM),
-            CipherType::ChaCha20IetfPoly1305 => seal_or_open!(CHACHA20_POLY1305),
+            CipherType::Aes128Gcm | CipherType::Aead2022Blake3Aes128Gcm => {
+                seal_or_open!(AES_128_GCM)
+            }
+            CipherType::Aes256Gcm | CipherType::Aead2022Blake3Aes256Gcm => {
+                seal_or_open!(AES_256_GCM)
+            }
+            CipherType::ChaCha20IetfPoly1305 | CipherType::Aead2022Blake3ChaCha20Poly1305 => {
+                seal_or_open!(CHACHA20_POLY1305)
+            }
             _ => panic!("unsupported cipher in ring {:?}", t),
         }
     }
@@ -210,4 +221,26 @@ mod test {
     fn test_ring_chacha20poly1305() {
         test_ring_aead(CipherType::ChaCha20IetfPoly1305);
     }
+
+    #[test]
+    fn test_ring_aead_2022() {
+        for &ct in &[
+            CipherType::Aead2022Blake3Aes128Gcm,
+            CipherType::Aead2022Blake3Aes256Gcm,
+            CipherType::Aead2022Blake3ChaCha20Poly1305,
+        ] {
+            let key = ct.gen_salt();
+            let salt = ct.gen_salt();
+            let message = b"message";
+
+            let mut enc = RingAeadCipher::new(ct, &key, &salt, true);
+            let mut encrypted_msg = vec![0u8; message.len() + ct.tag_size()];
+            enc.encrypt(message, &mut encrypted_msg);
+
+            let mut dec = RingAeadCipher::new(ct, &key, &salt, false);
+            let mut decrypted_msg = vec![0u8; message.len()];
+            dec.decrypt(&encrypted_msg, &mut decrypted_msg).unwrap();
+            assert_eq!(&decrypted_msg[..], message);
+        }
+    }
 }
@@ -172,11 +172,19 @@ impl SodiumAeadCipher {
         }
 
         let skey = make_skey(t, key, salt);
+        SodiumAeadCipher::with_nonce(t, &skey, &nonce)
+    }
+
+    /// Initialize with `key` as the subkey and `nonce` as the first nonce
+    pub fn with_nonce(t: CipherType, key: &[u8], nonce: &[u8]) -> SodiumAeadCipher {
+        SODIUM_INIT_FLAG.call_once(|| unsafe {
+            assert_eq!(sodium_init(), 0);
+        });
 
         SodiumAeadCipher {
             cipher_type: t,
-            key: skey,
-            nonce,
+            key: Bytes::from(key),
+            nonce: BytesMut::from(nonce),
         }
     }
 
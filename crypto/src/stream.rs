@@ -2,6 +2,8 @@
 
 #[cfg(feature = "openssl")]
 use crate::openssl;
+#[cfg(feature = "pure-rust")]
+use crate::pure_rust;
 #[cfg(feature = "rc4")]
 use crate::rc4_md5;
 #[cfg(feature = "sodium")]
@@ -44,11 +46,21 @@ pub fn new_stream(t: CipherType, key: &[u8], iv: &[u8], mode: CryptoMode) -> Box
         | CipherType::Salsa20
         | CipherType::XSalsa20
         | CipherType::ChaCha20Ietf => Box::new(sodium::SodiumStreamCipher::new(t, key, iv)),
+        #[cfg(all(feature = "pure-rust", not(feature = "sodium")))]
+        CipherType::ChaCha20
+        | CipherType::Salsa20
+        | CipherType::XSalsa20
+        | CipherType::ChaCha20Ietf => Box::new(pure_rust::RustStreamCipher::new(t, key, iv, mode)),
 
         #[cfg(feature = "rc4")]
         CipherType::Rc4Md5 => Box::new(rc4_md5::Rc4Md5Cipher::new(key, iv, mode)),
 
-        #[cfg(feature = "aes-cfb")]
+        #[cfg(all(feature = "rc4", feature = "openssl"))]
+        CipherType::Rc4 => Box::new(openssl::OpenSSLCipher::new(t, key, iv, mode)),
+        #[cfg(all(feature = "rc4", feature = "pure-rust", not(feature = "openssl")))]
+        CipherType::Rc4 => Box::new(pure_rust::RustStreamCipher::new(t, key, iv, mode)),
+
+        #[cfg(all(feature = "aes-cfb", feature = "openssl"))]
         CipherType::Aes128Cfb
         | CipherType::Aes128Cfb1
         | CipherType::Aes128Cfb8
@@ -61,11 +73,28 @@ pub fn new_stream(t: CipherType, key: &[u8], iv: &[u8], mode: CryptoMode) -> Box
         | CipherType::Aes256Cfb1
         | CipherType::Aes256Cfb8
         | CipherType::Aes256Cfb128 => Box::new(openssl::OpenSSLCipher::new(t, key, iv, mode)),
+        #[cfg(all(feature = "aes-cfb", feature = "pure-rust", not(feature = "openssl")))]
+        CipherType::Aes128Cfb
+        | CipherType::Aes128Cfb1
+        | CipherType::Aes128Cfb8
+        | CipherType::Aes128Cfb128
+        | CipherType::Aes192Cfb
+        | CipherType::Aes192Cfb1
+        | CipherType::Aes192Cfb8
+        | CipherType::Aes192Cfb128
+        | CipherType::Aes256Cfb
+        | CipherType::Aes256Cfb1
+        | CipherType::Aes256Cfb8
+        | CipherType::Aes256Cfb128 => Box::new(pure_rust::RustStreamCipher::new(t, key, iv, mode)),
 
-        #[cfg(feature = "aes-ctr")]
+        #[cfg(all(feature = "aes-ctr", feature = "openssl"))]
         CipherType::Aes128Ctr | CipherType::Aes192Ctr | CipherType::Aes256Ctr => {
             Box::new(openssl::OpenSSLCipher::new(t, key, iv, mode))
         }
+        #[cfg(all(feature = "aes-ctr", feature = "pure-rust", not(feature = "openssl")))]
+        CipherType::Aes128Ctr | CipherType::Aes192Ctr | CipherType::Aes256Ctr => {
+            Box::new(pure_rust::RustStreamCipher::new(t, key, iv, mode))
+        }
 
         #[cfg(feature = "camellia-cfb")]
         CipherType::Camellia128Cfb
@@ -0,0 +1,253 @@
+//! Known-answer tests for every cipher
+//!
+//! The expected values are computed with libsodium and OpenSSL, the libraries shadowsocks-libev
+//! is built on, instead of with this crate. Every test encrypts `PLAIN`, a socks5 address
+//! followed by a http request, with the key derived from `PASSWORD` and an iv or salt of
+//! `0, 1, 2, ...`.
+
+use crate::aead::{increase_nonce, make_skey};
+use crate::{new_aead_decryptor, new_aead_encryptor, new_stream, CipherType, CryptoMode};
+
+const PASSWORD: &[u8] = b"foobar";
+const PLAIN: &[u8] = b"\x03\x0bexample.com\x00\x50GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+
+fn unhex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn seq(len: usize) -> Vec<u8> {
+    (0..len).map(|i| i as u8).collect()
+}
+
+#[test]
+fn test_bytes_to_key() {
+    assert_eq!(
+        &CipherType::Aes128Gcm.bytes_to_key(PASSWORD)[..],
+        &unhex("3858f62230ac3c915f300c664312c63f")[..]
+    );
+    assert_eq!(
+        &CipherType::Aes256Gcm.bytes_to_key(PASSWORD)[..],
+        &unhex("3858f62230ac3c915f300c664312c63f568378529614d22ddb49237d2f60bfdf")[..]
+    );
+    #[cfg(feature = "aes-cfb")]
+    assert_eq!(
+        &CipherType::Aes192Cfb.bytes_to_key(PASSWORD)[..],
+        &unhex("3858f62230ac3c915f300c664312c63f568378529614d22d")[..]
+    );
+}
+
+/// Ciphertexts of stream ciphers, which are sent after the iv in both TCP streams and UDP packets
+fn stream_vectors() -> Vec<(CipherType, &'static str)> {
+    let mut vectors = vec![
+        (CipherType::Table, "f4e31894ae40001b1886b8ee40cd9bef76305ab75a8b30309bb75f865f01558bee2b3d2f5a1894ae40001b1886b8ee4001550155"),
+        (CipherType::Plain, "030b6578616d706c652e636f6d0050474554202f20485454502f312e310d0a486f73743a206578616d706c652e636f6d0d0a0d0a"),
+    ];
+    #[cfg(feature = "aes-cfb")]
+    vectors.extend_from_slice(&[
+        (CipherType::Aes128Cfb, "0da8706e594401000e1bf3a50ad40c31d9f4d7595d68201c6663c733e9c6a92fe9fd33c79634df9a2aa01e5b0cee091e545a1227"),
+        (CipherType::Aes128Cfb1, "3390375a5b1c09c998dcd5f387ea74d62bf1fa4983077ac2e2fe7f20c76c7555f4d8153ba91a2ab4f57e755f11f479c5aa538233"),
+        (CipherType::Aes128Cfb8, "0d908ef44941c57835631bd2080ce374ae9b4c608386a79d3e2ce7348ae9f70a7b975b9692cff6d0ef67a6b127c142a5f498f294"),
+        (CipherType::Aes128Cfb128, "0da8706e594401000e1bf3a50ad40c31d9f4d7595d68201c6663c733e9c6a92fe9fd33c79634df9a2aa01e5b0cee091e545a1227"),
+        (CipherType::Aes192Cfb, "0c45dfd7f3225b1f2463e302831f391ac1c660a1c36c5683360f22be7ab382e678d6e733901d8e1df3505330ca5b3fa0ce8fb24e"),
+        (CipherType::Aes192Cfb1, "6497de84c8c9a936f69722acfe1ffaed8c2e73cb041b96f6b9ff76e5e181a69e9a4c60e2dfc1d75073205e349e227e80bbdb7970"),
+        (CipherType::Aes192Cfb8, "0cf94f7f1f32913819d8517da4085c36f67dcbc3325de0d7046a336a8c84143babbd213f44f382a4c3a09c82276b6f99d931b8ab"),
+        (CipherType::Aes192Cfb128, "0c45dfd7f3225b1f2463e302831f391ac1c660a1c36c5683360f22be7ab382e678d6e733901d8e1df3505330ca5b3fa0ce8fb24e"),
+        (CipherType::Aes256Cfb, "2f2393e4ca1c96f15411da38aee42a9112d2e60fa6de883ef13b5918438499920460117c09e03e385e1387cf9ef96b290694f01d"),
+        (CipherType::Aes256Cfb1, "7ffe776151e517fd1fb5d7427edfd3b18e3a9279262e623af32a0254474958a0ec19c73533a57982d276601e9ea37d897ae8b0b3"),
+        (CipherType::Aes256Cfb8, "2f7e6f75406433298848d5bf512a9b18685e2b95c6580894464a1fd377a160d8d8417d1f106919166b05ab76422b44720b8f939d"),
+        (CipherType::Aes256Cfb128, "2f2393e4ca1c96f15411da38aee42a9112d2e60fa6de883ef13b5918438499920460117c09e03e385e1387cf9ef96b290694f01d"),
+    ]);
+    #[cfg(feature = "aes-ctr")]
+    vectors.extend_from_slice(&[
+        (CipherType::Aes128Ctr, "0da8706e594401000e1bf3a50ad40c3121257866ee4dc26559a87c53db291ac00c03772d06f633c0aa758f7fd8f8e5e34f695b27"),
+        (CipherType::Aes192Ctr, "0c45dfd7f3225b1f2463e302831f391abb2182ca911524b2ab1b0a0f5c65bbad36db939b948e9823169cb3fc780cb9fa5fea2f4e"),
+        (CipherType::Aes256Ctr, "2f2393e4ca1c96f15411da38aee42a91c2666db69a23790bb89c50ba855251046a7e2cecdb06bd1b7a189f2f418cd48fb146f7ec"),
+    ]);
+    #[cfg(feature = "camellia-cfb")]
+    vectors.extend_from_slice(&[
+        (CipherType::Camellia128Cfb, "46f02de739ac97b4cdc1609263b8c2a7c024eec368eaffefe4034b0e0a2509e46fe322fec93e397b08e783c9f2495b7988bd4cd8"),
+        (CipherType::Camellia128Cfb1, "409bc2088b6ffe0143ba56acc752ef09c97dd0e1a942d30dcd2da3ff2094081e9f7072969ad825d172fc26819b7dc3703c039bcf"),
+        (CipherType::Camellia128Cfb8, "46a756a91bccda5e66d7c135f08a6bce4ac24599874538074eaecbea6612831916b9d017794e5ec57bca23b30e6ae54197441070"),
+        (CipherType::Camellia128Cfb128, "46f02de739ac97b4cdc1609263b8c2a7c024eec368eaffefe4034b0e0a2509e46fe322fec93e397b08e783c9f2495b7988bd4cd8"),
+        (CipherType::Camellia192Cfb, "33b10df965f627f0c0b58a83857c2fa162fbcdf5af9263a98646e843ce073879d0cd74d6cc7e68dfc1034eba20fe364665dbed42"),
+        (CipherType::Camellia192Cfb1, "678387d0cdc7dd3bd0b935627e371f4be446ad3ab95c267c487e0d534f7c492cf0062bcf26abdd97fb572a82f5c48665e5f5a315"),
+        (CipherType::Camellia192Cfb8, "33695e7299fc1418bd7363d3f04f6880d24be30427361ccab41170d0f3a4b5c3b9c60df7516385771e25930f1590ff566e3b922c"),
+        (CipherType::Camellia192Cfb128, "33b10df965f627f0c0b58a83857c2fa162fbcdf5af9263a98646e843ce073879d0cd74d6cc7e68dfc1034eba20fe364665dbed42"),
+        (CipherType::Camellia256Cfb, "9accd1b8009efbd696df6fb8043bad1134a8bb0c357357cee2292ef4806d29ff3716c37cfcc15cceee63a4bb290bf3872ee139af"),
+        (CipherType::Camellia256Cfb1, "c21502bbef55ed90114cc3c921b8cfdff1f4f886feeefcdbb148ad863413a764877019562b6fb616aa4131e6857e4c1e54b36d15"),
+        (CipherType::Camellia256Cfb8, "9a8a92ef3f7471db79b037f22b6b69cc719543d9d901281a6073851bbbc519bbb3fd282a2da6df05b68032d8e8e50c11aed8e0c0"),
+        (CipherType::Camellia256Cfb128, "9accd1b8009efbd696df6fb8043bad1134a8bb0c357357cee2292ef4806d29ff3716c37cfcc15cceee63a4bb290bf3872ee139af"),
+    ]);
+    #[cfg(feature = "rc4")]
+    vectors.extend_from_slice(&[
+        (CipherType::Rc4, "bb7ff2b4a0bb4bc27a7fa70aeb98e7d3be1fe56b3503385eaef5f56ff8a8f692e4571de5683da42c4e702eda01e86dec482f297a"),
+        (CipherType::Rc4Md5, "ebe2d05128174f25c3ee1eca48f1562fc63071ad266a221bd5639c79eb966a586c6b7b588ff60f660e24aa7b13ae44d40e02766b"),
+    ]);
+    #[cfg(any(feature = "sodium", feature = "pure-rust"))]
+    vectors.extend_from_slice(&[
+        (CipherType::ChaCha20, "402272a77206cc8b962b991d3952a19d612b32721d8ce9735a0ec0c4a52285b43cfeecdcd4b1ff93b63bdabfd38e1bf1de77f541"),
+        (CipherType::Salsa20, "7b3c1e3faf9af7c7d3a4eaebd08478b4f9c9e9944415916e6323bb7ea3de481f8cf241ce24a1b305fc55df779dbfcab6b94684f3"),
+        (CipherType::XSalsa20, "1c3d38c3fff5be949a04c0cc7c51a644b2c344e575b51b354589b39361e548e0636f06e2fb6ff5cae2d9bdf745c06a2be98b9d75"),
+        (CipherType::ChaCha20Ietf, "2a3345845bbf35ff0d1d0863a8563c994e487b1f069d18008e4ccd3ef40a03f56f24466a1c8940869e5f90310af35f43a98dc9c4"),
+    ]);
+    vectors
+}
+
+#[test]
+fn test_stream_ciphers() {
+    for (t, expected) in stream_vectors() {
+        // The table cipher builds its table from the password itself.
+        let key = match t {
+            CipherType::Table => PASSWORD.into(),
+            _ => t.bytes_to_key(PASSWORD),
+        };
+        let iv = seq(t.iv_size());
+        let expected = unhex(expected);
+
+        // Split in two updates, as a TCP stream is encrypted.
+        let mut enc = new_stream(t, &key, &iv, CryptoMode::Encrypt);
+        let mut encrypted = Vec::new();
+        enc.update(&PLAIN[..7], &mut encrypted).unwrap();
+        enc.update(&PLAIN[7..], &mut encrypted).unwrap();
+        enc.finalize(&mut encrypted).unwrap();
+        assert_eq!(encrypted, expected, "{}", t);
+
+        let mut dec = new_stream(t, &key, &iv, CryptoMode::Decrypt);
+        let mut decrypted = Vec::new();
+        dec.update(&expected, &mut decrypted).unwrap();
+        dec.finalize(&mut decrypted).unwrap();
+        assert_eq!(&decrypted[..], PLAIN, "{}", t);
+    }
+}
+
+/// Subkeys, TCP streams of the salt and two chunks, the length and `PLAIN`, and UDP packets of
+/// the salt and `PLAIN` of AEAD ciphers
+fn aead_vectors() -> Vec<(CipherType, &'static str, &'static str, &'static str)> {
+    let mut vectors = vec![
+        (
+            CipherType::Aes128Gcm,
+            "e59e945699e8699144c332b9e641ef65",
+            "000102030405060708090a0b0c0d0e0ff87ae4fcadc8ffe8b33590e133684517d6c93c0fc8a22dda49f5462a585eb764a006b81c0dadbbca471fb4e4ba2585eff47a846a0b81bd4061f5ea98f6489752e2d74f573fc8041a3bb5b1969acaa213d118abb52ee3",
+            "000102030405060708090a0b0c0d0e0ffb450e7e79ea325fe392940ed011a74263b314cc5a014229dd9d9d9de6adf069dbc6393a2f7f61c9085766b305682f310a3cf2e694f8aee7084db727ced0a78c43e01df5",
+        ),
+        (
+            CipherType::Aes256Gcm,
+            "c4f0e9818348b2f30188d82b37a4cddc9f5ea531070ec67225160209faff573c",
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f26c84ec3b04bfd7a277bebb47daeb2053fb2241815b04c2dd91251e282d8589929080d6537ba418a416c0fa94437cde13af3f59bd92a2698c4ab229f2aa5f514110a4c388de5bb6a7a265fb0811ce9948211c90094fb",
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f25f72e4cff9894ee5136655767010b961afa89fc39452201645d33d3b091a302e6caf04ec20c2b90e4b8b5dbb0b8dd4a6c883cdbda0c38c9630b27c51858ecc18ea83d0c",
+        ),
+        (
+            CipherType::ChaCha20IetfPoly1305,
+            "c4f0e9818348b2f30188d82b37a4cddc9f5ea531070ec67225160209faff573c",
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f5da393ef066f8e166627a0491252e54c11cdac89d42411949fff2de8a5e263a062e62515e9a8c5e81437f00724cb1c6692cbe45511a4eb015ea3e4ebef585c7efb87740f94367e17f561b8f67d74be9d3ed1e3922ab1",
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f5e9c418d226d29c24fe82c13e1acbef699f55a53e5850c664c76ffa9a78303e5ae124306bb104876da68ef6bd70e6942af0096b86f9fa3478c0cfa4ae45dc70b6f543317",
+        ),
+    ];
+    #[cfg(any(feature = "sodium", feature = "pure-rust"))]
+    vectors.push((
+        CipherType::XChaCha20IetfPoly1305,
+        "c4f0e9818348b2f30188d82b37a4cddc9f5ea531070ec67225160209faff573c",
+        "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f2c2a14e2c3d3f282eb34aac699832fd8d9e9916634f24e4b4b185dbc9e360a0dad56c7873c610785f4c268591f1e4226f881faac7606582a2d3b61d7f633db0c09625a308c8ded6b9dbb817c09540cba9a68a6664fd3",
+        "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f2f15cfb28329d05c5df22beb37380fba6ec2a495fde686eccdb7ab2b23706737bca8cd06fe73a3f85d4a0f7c71cf5bd8c1ac061e798c95c0116f86afaeb47177c3cf82f8",
+    ));
+    vectors
+}
+
+/// Seal the chunks of a TCP stream, each with the next nonce
+fn seal_tcp(t: CipherType, key: &[u8], salt: &[u8]) -> Vec<u8> {
+    let tag_size = t.tag_size();
+    let mut enc = new_aead_encryptor(t, key, salt);
+    let mut stream = salt.to_vec();
+    for chunk in &[&(PLAIN.len() as u16).to_be_bytes()[..], PLAIN] {
+        let offset = stream.len();
+        stream.resize(offset + chunk.len() + tag_size, 0);
+        enc.encrypt(chunk, &mut stream[offset..]);
+    }
+    stream
+}
+
+/// Open the chunks sealed by `seal_tcp`
+fn open_tcp(t: CipherType, key: &[u8], stream: &[u8]) -> Vec<u8> {
+    let tag_size = t.tag_size();
+    let (salt, mut rest) = stream.split_at(t.salt_size());
+    let mut dec = new_aead_decryptor(t, key, salt);
+    let mut len = [0; 2];
+    dec.decrypt(&rest[..2 + tag_size], &mut len).unwrap();
+    rest = &rest[2 + tag_size..];
+    let mut payload = vec![0; u16::from_be_bytes(len) as usize];
+    dec.decrypt(rest, &mut payload).unwrap();
+    payload
+}
+
+#[test]
+fn test_aead_ciphers() {
+    for (t, skey, tcp, udp) in aead_vectors() {
+        let key = t.bytes_to_key(PASSWORD);
+        let salt = seq(t.salt_size());
+        assert_eq!(&make_skey(t, &key, &salt)[..], &unhex(skey)[..], "{}", t);
+
+        let tcp = unhex(tcp);
+        assert_eq!(seal_tcp(t, &key, &salt), tcp, "{}", t);
+        assert_eq!(&open_tcp(t, &key, &tcp)[..], PLAIN, "{}", t);
+
+        let mut enc = new_aead_encryptor(t, &key, &salt);
+        let mut packet = salt.clone();
+        packet.resize(salt.len() + PLAIN.len() + t.tag_size(), 0);
+        enc.encrypt(PLAIN, &mut packet[salt.len()..]);
+        assert_eq!(packet, unhex(udp), "{}", t);
+    }
+}
+
+/// Subkeys and the TCP streams of `seal_tcp` of AEAD 2022 ciphers, for the key `0, 1, 2, ...`
+/// and the salt `0x80, 0x81, 0x82, ...`
+const AEAD_2022_VECTORS: &[(CipherType, &str, &str)] = &[
+    (
+        CipherType::Aead2022Blake3Aes128Gcm,
+        "722b3033c5d021365a8521bfb41157a3",
+        "808182838485868788898a8b8c8d8e8fa37629f834750eaf61cda9798148ff04861729d3b0438a29361410a8bd8dcaf5f5c07ee19fce2bc2f2b8432cb423d618785afc634f5182b4082b49ae6bf982fe4d0388ed2919112096b715ed80982d7aeb4be9f90631",
+    ),
+    (
+        CipherType::Aead2022Blake3Aes256Gcm,
+        "11289b9d205255930f83932405c2b0a38ec32be703fe33f290ff25ffeff402f9",
+        "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f4ed5d51f68696cfd1b36e5c78ad5cfb6d40c64413f06e7c767427ae7b001d9297da87144e8a66e544c32d59131b292a2f92f894e304e5299e328a5f834c8ecac6940510cf08f4eb388a98a1f3ef7e6a136859520d2f5",
+    ),
+    (
+        CipherType::Aead2022Blake3ChaCha20Poly1305,
+        "11289b9d205255930f83932405c2b0a38ec32be703fe33f290ff25ffeff402f9",
+        "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f3b0d01d5d5aca3c6c36fa57d4aa48c7e48b1141cff65442db1d9c8413e651a8cc7af49fc7b810ffcc7c8bcd901c04a9e38ddb24946129aef6878470fec1b69958100b830fc4d337b413e2f940570b1d5d11092030436",
+    ),
+];
+
+#[test]
+fn test_aead_2022_ciphers() {
+    for &(t, skey, tcp) in AEAD_2022_VECTORS {
+        let key = seq(t.key_size());
+        let salt = (0..t.salt_size())
+            .map(|i| 0x80 + i as u8)
+            .collect::<Vec<u8>>();
+        assert_eq!(&make_skey(t, &key, &salt)[..], &unhex(skey)[..], "{}", t);
+
+        let tcp = unhex(tcp);
+        assert_eq!(seal_tcp(t, &key, &salt), tcp, "{}", t);
+        assert_eq!(&open_tcp(t, &key, &tcp)[..], PLAIN, "{}", t);
+    }
+}
+
+#[test]
+fn test_increase_nonce() {
+    // Nonces are little endian counters, as `sodium_increment`.
+    let mut nonce = [0u8; 12];
+    increase_nonce(&mut nonce);
+    assert_eq!(nonce, [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+    let mut nonce = [0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    increase_nonce(&mut nonce);
+    assert_eq!(nonce, [0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+    let mut nonce = [0xff; 12];
+    increase_nonce(&mut nonce);
+    assert_eq!(nonce, [0; 12]);
+}
@@ -2,16 +2,17 @@ pub mod resolver;
 
 use hermesdns::DnsUdpServer;
 use resolver::RuleBasedDnsResolver;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::path::Path;
 
 pub async fn create_dns_server<P: AsRef<Path>>(
     path: P,
     listen: String,
     start_ip: Ipv4Addr,
+    start_ipv6: Option<Ipv6Addr>,
 ) -> (DnsUdpServer, RuleBasedDnsResolver) {
     let n = u32::from_be_bytes(start_ip.octets());
-    let resolver = RuleBasedDnsResolver::new(path, n).await;
+    let resolver = RuleBasedDnsResolver::new(path, n, start_ipv6).await;
     let server = DnsUdpServer::new(listen, Box::new(resolver.clone())).await;
     (server, resolver)
 }
@@ -43,6 +44,7 @@ mod tests {
                 dir.path(),
                 format!("127.0.0.1:{}", LOCAL_UDP_PORT),
                 "10.0.0.1".parse().unwrap(),
+                None,
             )
             .await;
             task::spawn(server.run_server());
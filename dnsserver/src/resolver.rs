@@ -4,7 +4,7 @@ use hermesdns::{DnsPacket, DnsRecord, DnsResolver, Hosts, QueryType, TransientTt
 use sled::Db;
 use std::any::Any;
 use std::io::Result;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::path::Path;
 use std::sync::Arc;
 use tracing::debug;
@@ -20,9 +20,11 @@ pub struct RuleBasedDnsResolver {
 }
 
 impl RuleBasedDnsResolver {
-    pub async fn new<P: AsRef<Path>>(path: P, next_ip: u32) -> Self {
+    /// Fake IPv4 addresses start from `next_ip`. If `start_ipv6` is set, AAAA queries are
+    /// answered with fake IPv6 addresses too, otherwise with no answers.
+    pub async fn new<P: AsRef<Path>>(path: P, next_ip: u32, start_ipv6: Option<Ipv6Addr>) -> Self {
         RuleBasedDnsResolver {
-            inner: Arc::new(Mutex::new(Inner::new(path, next_ip).await)),
+            inner: Arc::new(Mutex::new(Inner::new(path, next_ip, start_ipv6).await)),
         }
     }
 
@@ -33,12 +35,16 @@ impl RuleBasedDnsResolver {
 
 struct Inner {
     db: Db,
+    start_ip: u32,
     next_ip: u32,
+    /// The fake IPv6 address of a domain is `start_ipv6` plus the offset of its fake IPv4
+    /// address from `start_ip`, so both map back to the same domain.
+    start_ipv6: Option<u128>,
     hosts: Hosts,
 }
 
 impl Inner {
-    async fn new<P: AsRef<Path>>(path: P, next_ip: u32) -> Self {
+    async fn new<P: AsRef<Path>>(path: P, start_ip: u32, start_ipv6: Option<Ipv6Addr>) -> Self {
         let db = Db::open(path).expect("open db error");
         let next_ip = match db.get(NEXT_IP.as_bytes()) {
             Ok(Some(v)) => {
@@ -48,25 +54,46 @@ impl Inner {
             }
             _ => {
                 db.clear().unwrap();
-                next_ip
+                start_ip
             }
         };
 
         Self {
             db,
+            start_ip,
             next_ip,
+            start_ipv6: start_ipv6.map(u128::from),
             hosts: Hosts::load().expect("load /etc/hosts"),
         }
     }
 
     fn lookup_host(&self, addr: &str) -> Option<String> {
         debug!("lookup host: {}", addr);
+        let addr = match addr.parse::<Ipv6Addr>() {
+            Ok(ip) => self.to_ipv4(ip)?.to_string(),
+            Err(_) => addr.to_string(),
+        };
         self.db
             .get(addr.as_bytes())
             .unwrap()
             .map(|host| String::from_utf8(host.to_vec()).unwrap())
     }
 
+    fn to_ipv6(&self, ip: Ipv4Addr) -> Option<Ipv6Addr> {
+        let offset = u32::from(ip).wrapping_sub(self.start_ip);
+        Some(Ipv6Addr::from(
+            self.start_ipv6?.wrapping_add(offset as u128),
+        ))
+    }
+
+    fn to_ipv4(&self, ip: Ipv6Addr) -> Option<Ipv4Addr> {
+        let offset = u128::from(ip).wrapping_sub(self.start_ipv6?);
+        if offset > u128::from(u32::max_value()) {
+            return None;
+        }
+        Some(Ipv4Addr::from(self.start_ip.wrapping_add(offset as u32)))
+    }
+
     fn gen_ipaddr(&mut self) -> String {
         let [a, b, c, d] = self.next_ip.to_be_bytes();
         self.next_ip += 1;
@@ -76,7 +103,34 @@ impl Inner {
         addr.to_string()
     }
 
-    async fn resolve(&mut self, domain: &str) -> Result<DnsPacket> {
+    async fn resolve(&mut self, domain: &str, qtype: QueryType) -> Result<DnsPacket> {
+        let mut packet = DnsPacket::new();
+        let ip = self.resolve_ip(domain);
+        if qtype == QueryType::AAAA {
+            // Domains in /etc/hosts only have IPv4 addresses.
+            let addr = match self.hosts.get(domain) {
+                Some(_) => None,
+                None => self.to_ipv6(ip),
+            };
+            if let Some(addr) = addr {
+                packet.answers.push(DnsRecord::AAAA {
+                    domain: domain.to_string(),
+                    addr,
+                    ttl: TransientTtl(1),
+                });
+            }
+            return Ok(packet);
+        }
+        packet.answers.push(DnsRecord::A {
+            domain: domain.to_string(),
+            addr: ip,
+            ttl: TransientTtl(1),
+        });
+        Ok(packet)
+    }
+
+    /// The address in /etc/hosts, or the fake IPv4 address of `domain`
+    fn resolve_ip(&mut self, domain: &str) -> Ipv4Addr {
         let ip = if let Some(ip) = self.hosts.get(domain) {
             ip.to_string()
         } else if let Some(addr) = self.db.get(domain).expect("get domain") {
@@ -94,26 +148,15 @@ impl Inner {
             self.db.insert(ip.as_bytes(), domain.as_bytes()).unwrap();
             ip
         };
-        let mut packet = DnsPacket::new();
-        packet.answers.push(DnsRecord::A {
-            domain: domain.to_string(),
-            addr: ip.parse().unwrap(),
-            ttl: TransientTtl(1),
-        });
-        Ok(packet)
+        ip.parse().unwrap()
     }
 }
 
 #[async_trait]
 impl DnsResolver for RuleBasedDnsResolver {
-    async fn resolve(
-        &self,
-        domain: &str,
-        _qtype: QueryType,
-        _recursive: bool,
-    ) -> Result<DnsPacket> {
+    async fn resolve(&self, domain: &str, qtype: QueryType, _recursive: bool) -> Result<DnsPacket> {
         let mut guard = self.inner.lock().await;
-        guard.resolve(domain).await
+        guard.resolve(domain, qtype).await
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -132,17 +175,64 @@ mod tests {
         let start_ip = "10.0.0.1".parse::<Ipv4Addr>().unwrap();
         let n = u32::from_be_bytes(start_ip.octets());
         task::block_on(async {
-            let mut inner = Inner::new(dir.path(), n).await;
+            let mut inner = Inner::new(dir.path(), n, None).await;
             assert_eq!(
-                inner.resolve("baidu.com").await.unwrap().get_random_a(),
+                inner
+                    .resolve("baidu.com", QueryType::A)
+                    .await
+                    .unwrap()
+                    .get_random_a(),
                 Some("10.0.0.1".to_string())
             );
             assert_eq!(
-                inner.resolve("www.ali.com").await.unwrap().get_random_a(),
+                inner
+                    .resolve("www.ali.com", QueryType::A)
+                    .await
+                    .unwrap()
+                    .get_random_a(),
                 Some("10.0.0.2".to_string())
             );
+            assert!(inner
+                .resolve("baidu.com", QueryType::AAAA)
+                .await
+                .unwrap()
+                .answers
+                .is_empty());
             assert_eq!(inner.lookup_host("10.0.0.1"), Some("baidu.com".to_string()));
             assert_eq!(inner.lookup_host("10.1.0.1"), None);
         });
     }
+
+    #[test]
+    fn test_inner_resolve_ipv6() {
+        let dir = tempfile::tempdir().unwrap();
+        let start_ip = "10.0.0.1".parse::<Ipv4Addr>().unwrap();
+        let n = u32::from_be_bytes(start_ip.octets());
+        let start_ipv6 = "fd00::1".parse::<Ipv6Addr>().unwrap();
+        task::block_on(async {
+            let mut inner = Inner::new(dir.path(), n, Some(start_ipv6)).await;
+            inner.resolve("baidu.com", QueryType::A).await.unwrap();
+            let packet = inner.resolve("www.ali.com", QueryType::AAAA).await.unwrap();
+            match &packet.answers[..] {
+                [DnsRecord::AAAA { addr, .. }] => {
+                    assert_eq!(*addr, "fd00::2".parse::<Ipv6Addr>().unwrap())
+                }
+                answers => panic!("{:?}", answers),
+            }
+            assert_eq!(
+                inner
+                    .resolve("www.ali.com", QueryType::A)
+                    .await
+                    .unwrap()
+                    .get_random_a(),
+                Some("10.0.0.2".to_string())
+            );
+            assert_eq!(
+                inner.lookup_host("fd00::2"),
+                Some("www.ali.com".to_string())
+            );
+            assert_eq!(inner.lookup_host("fd00::1"), Some("baidu.com".to_string()));
+            assert_eq!(inner.lookup_host("fd01::1"), None);
+        });
+    }
 }
@@ -1,20 +1,59 @@
 pub mod direct_client;
+pub mod health_check;
+pub mod http_client;
+pub mod proxy_client;
+pub mod proxy_group;
 pub mod ruled_client;
+pub mod socks5_client;
+mod tcp_relay;
 
+use async_std::io::{Read, Write};
 use config::Address;
 use ssclient::SSClient;
 use std::io::Result;
+use std::net::SocketAddr;
 use tun::socket::{TunTcpSocket, TunUdpSocket};
 
+/// A TCP connection accepted by seeker, either from tun or from a local proxy listener.
+pub trait LocalTcpSocket: Read + Write + Clone + Unpin + Send + Sync + 'static {
+    /// Address of the application's end of the connection
+    fn remote_addr(&self) -> SocketAddr;
+
+    /// Reset the connection, eg. when the upstream connection is reset
+    fn abort(&self);
+}
+
+impl LocalTcpSocket for TunTcpSocket {
+    fn remote_addr(&self) -> SocketAddr {
+        TunTcpSocket::remote_addr(self)
+    }
+
+    fn abort(&self) {
+        TunTcpSocket::abort(self)
+    }
+}
+
+/// Large enough for any udp datagram, smaller buffers can't receive large ones
+pub(crate) const UDP_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Address to bind a udp socket that sends to `addr`
+pub(crate) fn unspecified_addr(addr: &SocketAddr) -> &'static str {
+    if addr.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    }
+}
+
 #[async_trait::async_trait]
 pub trait Client {
-    async fn handle_tcp(&self, socket: TunTcpSocket, addr: Address) -> Result<()>;
+    async fn handle_tcp<S: LocalTcpSocket>(&self, socket: S, addr: Address) -> Result<()>;
     async fn handle_udp(&self, socket: TunUdpSocket, addr: Address) -> Result<()>;
 }
 
 #[async_trait::async_trait]
 impl Client for SSClient {
-    async fn handle_tcp(&self, socket: TunTcpSocket, addr: Address) -> Result<()> {
+    async fn handle_tcp<S: LocalTcpSocket>(&self, socket: S, addr: Address) -> Result<()> {
         self.handle_tcp_connection(socket, addr).await
     }
 
@@ -1,7 +1,7 @@
-use crate::client::Client;
+use crate::client::tcp_relay::relay_tcp;
+use crate::client::{unspecified_addr, Client, LocalTcpSocket, UDP_BUFFER_SIZE};
 use async_std::io;
 use async_std::net::{TcpStream, UdpSocket};
-use async_std::prelude::*;
 use async_std::sync::Mutex;
 use async_std::task;
 use async_std::task::JoinHandle;
@@ -19,7 +19,7 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tracing::{trace, trace_span};
 use tracing_futures::Instrument;
-use tun::socket::{TunTcpSocket, TunUdpSocket};
+use tun::socket::TunUdpSocket;
 
 pub(crate) struct DirectClient {
     resolver: DnsNetworkClient,
@@ -28,6 +28,7 @@ pub(crate) struct DirectClient {
     read_timeout: Duration,
     write_timeout: Duration,
     probe_timeout: Duration,
+    relay_buffer_size: usize,
     stats: ClientStats,
     prob_cache: Mutex<HashMap<Address, (bool, DateTime<Local>)>>,
 }
@@ -39,6 +40,7 @@ impl DirectClient {
         read_timeout: Duration,
         write_timeout: Duration,
         probe_timeout: Duration,
+        relay_buffer_size: usize,
     ) -> Self {
         DirectClient {
             resolver: DnsNetworkClient::new(0, connect_timeout).await,
@@ -47,6 +49,7 @@ impl DirectClient {
             read_timeout,
             write_timeout,
             probe_timeout,
+            relay_buffer_size,
             stats: ClientStats::new(),
             prob_cache: Mutex::new(HashMap::new()),
         }
@@ -60,7 +63,7 @@ impl DirectClient {
         &self.stats
     }
 
-    async fn resolve_domain(&self, domain: &str) -> Result<Option<IpAddr>> {
+    pub(crate) async fn resolve_domain(&self, domain: &str) -> Result<Option<IpAddr>> {
         resolve_domain(&self.resolver, self.dns_server(), domain).await
     }
 
@@ -111,58 +114,18 @@ impl DirectClient {
 
 #[async_trait::async_trait]
 impl Client for DirectClient {
-    #[allow(unreachable_code)]
-    async fn handle_tcp(&self, tun_socket: TunTcpSocket, addr: Address) -> Result<()> {
+    async fn handle_tcp<S: LocalTcpSocket>(&self, tun_socket: S, addr: Address) -> Result<()> {
         let conn = self.connect(&addr, self.connect_timeout).await?;
-        let mut tun_socket_clone = tun_socket.clone();
-        let mut tun_socket_clone2 = tun_socket.clone();
-        let mut ref_conn = &conn;
-        let mut ref_conn2 = &conn;
-        let idx = self.stats.add_connection(addr).await;
-        let a = async {
-            let mut buf = vec![0; 10240];
-            loop {
-                let rs = io::timeout(self.read_timeout, tun_socket_clone.read(&mut buf)).await?;
-                trace!(read_size = rs, "DirectClient::handle_tcp: read from tun");
-                if rs == 0 {
-                    break;
-                }
-                io::timeout(self.write_timeout, ref_conn.write_all(&buf[..rs])).await?;
-                trace!(write_size = rs, "DirectClient::handle_tcp: write to remote");
-                self.stats
-                    .update_connection_stats(idx, |stats| {
-                        stats.sent_bytes += rs as u64;
-                    })
-                    .await;
-            }
-            Ok::<(), io::Error>(())
-        };
-        let b = async {
-            let mut buf = vec![0; 10240];
-            loop {
-                let rs = io::timeout(self.read_timeout, ref_conn2.read(&mut buf)).await?;
-                trace!(read_size = rs, "DirectClient::handle_tcp: read from remote");
-                if rs == 0 {
-                    break;
-                }
-                io::timeout(self.write_timeout, tun_socket_clone2.write_all(&buf[..rs])).await?;
-                trace!(write_size = rs, "DirectClient::handle_tcp: write to tun");
-                self.stats
-                    .update_connection_stats(idx, |stats| {
-                        stats.recv_bytes += rs as u64;
-                    })
-                    .await;
-            }
-            Ok::<(), io::Error>(())
-        };
-        let ret = a.race(b).await;
-        self.stats
-            .update_connection_stats(idx, |stats| {
-                stats.close_time = Local::now();
-            })
-            .await;
-        ret?;
-        Ok(())
+        relay_tcp(
+            tun_socket,
+            conn,
+            addr,
+            &self.stats,
+            self.read_timeout,
+            self.write_timeout,
+            self.relay_buffer_size,
+        )
+        .await
     }
 
     #[allow(unreachable_code)]
@@ -183,7 +146,7 @@ impl Client for DirectClient {
             }
         };
 
-        let mut buf = vec![0; 1024];
+        let mut buf = vec![0; UDP_BUFFER_SIZE];
         let mut udp_map = HashMap::new();
 
         loop {
@@ -194,7 +157,7 @@ impl Client for DirectClient {
             let udp_socket = match udp_map.get(&local_src).cloned() {
                 Some(socket) => socket,
                 None => {
-                    let new_udp = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+                    let new_udp = Arc::new(UdpSocket::bind(unspecified_addr(&sock_addr)).await?);
                     let bind_addr = new_udp.local_addr()?;
                     trace!(addr = %bind_addr, "bind new udp socket");
                     udp_map.insert(local_src, new_udp.clone());
@@ -204,7 +167,7 @@ impl Client for DirectClient {
                     let read_timeout = self.read_timeout;
                     let write_timeout = self.write_timeout;
                     let _handle: JoinHandle<Result<_>> = task::spawn(async move {
-                        let mut recv_buf = vec![0; 1024];
+                        let mut recv_buf = vec![0; UDP_BUFFER_SIZE];
                         loop {
                             let now = Instant::now();
                             let (recv_from_ss_size, udp_ss_addr) =
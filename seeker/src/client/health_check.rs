@@ -0,0 +1,71 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Number of latest checks kept for each server
+const HISTORY_SIZE: usize = 10;
+
+/// Results of the latest health checks of a server. `None` is a failed check.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ServerHealth {
+    history: VecDeque<Option<Duration>>,
+}
+
+impl ServerHealth {
+    pub(crate) fn record(&mut self, rtt: Option<Duration>) {
+        if self.history.len() == HISTORY_SIZE {
+            let _ = self.history.pop_front();
+        }
+        self.history.push_back(rtt);
+    }
+
+    /// Whether the latest check succeeded. A server not checked yet is alive.
+    pub(crate) fn is_alive(&self) -> bool {
+        self.history.back().map_or(true, |rtt| rtt.is_some())
+    }
+
+    /// Average round trip time of the successful checks
+    pub(crate) fn rtt(&self) -> Option<Duration> {
+        let rtts = self.history.iter().filter_map(|r| *r).collect::<Vec<_>>();
+        if rtts.is_empty() {
+            return None;
+        }
+        Some(rtts.iter().sum::<Duration>() / rtts.len() as u32)
+    }
+
+    /// Ratio of successful checks, `None` if the server is not checked yet
+    pub(crate) fn success_rate(&self) -> Option<f64> {
+        if self.history.is_empty() {
+            return None;
+        }
+        let successes = self.history.iter().filter(|r| r.is_some()).count();
+        Some(successes as f64 / self.history.len() as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_health() {
+        let mut health = ServerHealth::default();
+        assert!(health.is_alive());
+        assert_eq!(health.rtt(), None);
+        assert_eq!(health.success_rate(), None);
+
+        health.record(Some(Duration::from_millis(100)));
+        health.record(None);
+        assert!(!health.is_alive());
+        health.record(Some(Duration::from_millis(300)));
+        health.record(Some(Duration::from_millis(200)));
+        assert!(health.is_alive());
+        assert_eq!(health.rtt(), Some(Duration::from_millis(200)));
+        assert_eq!(health.success_rate(), Some(0.75));
+
+        for _ in 0..HISTORY_SIZE {
+            health.record(Some(Duration::from_millis(50)));
+        }
+        assert_eq!(health.rtt(), Some(Duration::from_millis(50)));
+        assert_eq!(health.success_rate(), Some(1.0));
+    }
+}
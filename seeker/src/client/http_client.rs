@@ -0,0 +1,271 @@
+use crate::client::proxy_client::resolve_server_addr;
+use crate::client::tcp_relay::relay_tcp;
+use crate::client::{Client, LocalTcpSocket};
+use async_std::io;
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use config::{Address, ServerConfig};
+use hermesdns::DnsNetworkClient;
+use ssclient::client_stats::ClientStats;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tracing::trace;
+use tun::socket::TunUdpSocket;
+
+/// Responses with longer headers are rejected
+const MAX_HEADER_SIZE: usize = 8192;
+
+/// Client for one upstream HTTP proxy supporting `CONNECT`. UDP is not supported.
+pub struct HttpClient {
+    srv_cfg: Arc<ServerConfig>,
+    dns_server: (String, u16),
+    resolver: DnsNetworkClient,
+    connect_errors: AtomicUsize,
+    relay_buffer_size: usize,
+    stats: ClientStats,
+}
+
+impl HttpClient {
+    pub async fn new(
+        server_config: ServerConfig,
+        dns_server: (String, u16),
+        relay_buffer_size: usize,
+    ) -> HttpClient {
+        HttpClient {
+            resolver: DnsNetworkClient::new(0, server_config.read_timeout()).await,
+            srv_cfg: Arc::new(server_config),
+            dns_server,
+            connect_errors: AtomicUsize::new(0),
+            relay_buffer_size,
+            stats: ClientStats::new(),
+        }
+    }
+
+    pub fn server_config(&self) -> &ServerConfig {
+        &self.srv_cfg
+    }
+
+    pub fn connect_errors(&self) -> usize {
+        self.connect_errors.load(Ordering::SeqCst)
+    }
+
+    pub fn reset_connect_errors(&self) {
+        let _ = self.connect_errors.swap(0, Ordering::SeqCst);
+    }
+
+    pub fn stats(&self) -> &ClientStats {
+        &self.stats
+    }
+
+    /// Open a tunnel through the server to `addr` with `CONNECT`.
+    pub async fn connect(&self, addr: &Address) -> Result<TcpStream> {
+        let server_addr =
+            resolve_server_addr(&self.resolver, &self.dns_server, self.srv_cfg.addr()).await?;
+        let ret = io::timeout(self.srv_cfg.connect_timeout(), async {
+            let mut conn = TcpStream::connect(server_addr).await?;
+            let request = connect_request(addr, self.srv_cfg.username(), self.srv_cfg.password());
+            conn.write_all(request.as_bytes()).await?;
+            let header = read_header(&mut conn).await?;
+            trace!(addr = %addr, header = %header, "http connect response");
+            check_status(&header)?;
+            Ok(conn)
+        })
+        .await;
+        if let Err(e) = &ret {
+            if e.kind() != ErrorKind::TimedOut {
+                self.connect_errors.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        ret
+    }
+}
+
+#[async_trait::async_trait]
+impl Client for HttpClient {
+    async fn handle_tcp<S: LocalTcpSocket>(&self, tun_socket: S, addr: Address) -> Result<()> {
+        let conn = self.connect(&addr).await?;
+        relay_tcp(
+            tun_socket,
+            conn,
+            addr,
+            &self.stats,
+            self.srv_cfg.read_timeout(),
+            self.srv_cfg.write_timeout(),
+            self.relay_buffer_size,
+        )
+        .await
+    }
+
+    async fn handle_udp(&self, _socket: TunUdpSocket, addr: Address) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "http server {} does not support udp, drop packets to {}",
+                self.srv_cfg.name(),
+                addr
+            ),
+        ))
+    }
+}
+
+fn connect_request(addr: &Address, username: Option<&str>, password: &str) -> String {
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", addr);
+    if let Some(username) = username {
+        let credentials = base64::encode(&format!("{}:{}", username, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+    }
+    request.push_str("\r\n");
+    request
+}
+
+/// Read an http header byte by byte so no data after it is consumed.
+pub(crate) async fn read_header(conn: &mut TcpStream) -> Result<String> {
+    let mut header = Vec::with_capacity(128);
+    let mut byte = [0; 1];
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() >= MAX_HEADER_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "http header is too long",
+            ));
+        }
+        conn.read_exact(&mut byte).await?;
+        header.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&header).into_owned())
+}
+
+fn check_status(header: &str) -> Result<()> {
+    let status_line = header.lines().next().unwrap_or("");
+    let mut parts = status_line.split_whitespace();
+    let version = parts.next().unwrap_or("");
+    let status = parts.next().unwrap_or("");
+    if !version.starts_with("HTTP/1.") {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid http connect response: {}", status_line),
+        ));
+    }
+    match status {
+        "200" => Ok(()),
+        "407" => Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("http proxy authentication failed: {}", status_line),
+        )),
+        _ => Err(Error::new(
+            ErrorKind::ConnectionRefused,
+            format!("http connect failed: {}", status_line),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::net::TcpListener;
+    use async_std::prelude::*;
+    use async_std::task;
+    use config::{ServerAddr, ServerType};
+    use crypto::CipherType;
+    use std::time::Duration;
+
+    async fn new_client(listener: &TcpListener, username: Option<&str>) -> HttpClient {
+        let mut config = ServerConfig::new(
+            "http".to_string(),
+            ServerAddr::SocketAddr(listener.local_addr().unwrap()),
+            "pass".to_string(),
+            CipherType::ChaCha20Ietf,
+            Duration::from_secs(3),
+            Duration::from_secs(3),
+            Duration::from_secs(3),
+            0,
+        );
+        config.set_server_type(ServerType::Http);
+        if let Some(username) = username {
+            config.set_username(username.to_string());
+        }
+        HttpClient::new(config, ("127.0.0.1".to_string(), 53), 1024).await
+    }
+
+    /// Accept one connection, answer the request with `response` and return the request header.
+    async fn mock_server(listener: TcpListener, response: &'static str) -> Result<String> {
+        let (mut conn, _) = listener.accept().await?;
+        let header = read_header(&mut conn).await?;
+        conn.write_all(response.as_bytes()).await?;
+        Ok(header)
+    }
+
+    #[test]
+    fn test_connect_request() {
+        let addr = Address::DomainNameAddress("example.com".to_string(), 443);
+        assert_eq!(
+            connect_request(&addr, None, ""),
+            "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n"
+        );
+        assert_eq!(
+            connect_request(&addr, Some("user"), "pass"),
+            "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_check_status() {
+        assert!(check_status("HTTP/1.1 200 Connection established\r\n\r\n").is_ok());
+        assert!(check_status("HTTP/1.0 200 OK\r\n\r\n").is_ok());
+        assert_eq!(
+            check_status("HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::PermissionDenied
+        );
+        assert!(check_status("HTTP/1.1 403 Forbidden\r\n\r\n").is_err());
+        assert!(check_status("SSH-2.0-OpenSSH\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_connect() {
+        let target = Address::DomainNameAddress("example.com".to_string(), 443);
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = new_client(&listener, Some("user")).await;
+            let server = task::spawn(mock_server(
+                listener,
+                "HTTP/1.1 200 Connection established\r\n\r\ndata",
+            ));
+            let mut conn = client.connect(&target).await.unwrap();
+            // Data sent right after the response is left for the tunnel
+            let mut data = [0; 4];
+            conn.read_exact(&mut data).await.unwrap();
+            assert_eq!(&data, b"data");
+            assert_eq!(
+                server.await.unwrap(),
+                connect_request(&target, Some("user"), "pass")
+            );
+        });
+    }
+
+    #[test]
+    fn test_connect_rejected() {
+        let target = Address::SocketAddress("1.2.3.4:443".parse().unwrap());
+        let responses = vec![
+            (
+                "HTTP/1.1 403 Forbidden\r\n\r\n",
+                ErrorKind::ConnectionRefused,
+            ),
+            (
+                "HTTP/1.1 407 Proxy Authentication Required\r\n\r\n",
+                ErrorKind::PermissionDenied,
+            ),
+        ];
+        task::block_on(async {
+            for (response, kind) in responses {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let client = new_client(&listener, None).await;
+                let _server = task::spawn(mock_server(listener, response));
+                assert_eq!(client.connect(&target).await.unwrap_err().kind(), kind);
+                assert_eq!(client.connect_errors(), 1);
+            }
+        });
+    }
+}
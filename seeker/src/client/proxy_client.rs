@@ -0,0 +1,178 @@
+use crate::client::http_client::HttpClient;
+use crate::client::socks5_client::Socks5Client;
+use crate::client::{Client, LocalTcpSocket};
+use async_std::io::{self, Read, Write};
+use async_std::prelude::*;
+use config::{Address, ServerAddr, ServerConfig, ServerType};
+use hermesdns::DnsClient;
+use ssclient::client_stats::ClientStats;
+use ssclient::{resolve_domain, SSClient};
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tun::socket::TunUdpSocket;
+
+/// Client for one server in `server_configs`, picked by the server's `type`.
+pub enum ProxyClient {
+    Shadowsocks(SSClient),
+    Socks5(Socks5Client),
+    Http(HttpClient),
+}
+
+impl ProxyClient {
+    pub async fn new(
+        server_config: ServerConfig,
+        dns_server: (String, u16),
+        relay_buffer_size: usize,
+    ) -> Result<Self> {
+        ProxyClient::create(server_config, dns_server, relay_buffer_size, true).await
+    }
+
+    /// Create a client that keeps no idle connections to the server, eg. one only used by
+    /// health checks.
+    pub async fn new_unpooled(
+        server_config: ServerConfig,
+        dns_server: (String, u16),
+        relay_buffer_size: usize,
+    ) -> Result<Self> {
+        ProxyClient::create(server_config, dns_server, relay_buffer_size, false).await
+    }
+
+    async fn create(
+        server_config: ServerConfig,
+        dns_server: (String, u16),
+        relay_buffer_size: usize,
+        pooled: bool,
+    ) -> Result<Self> {
+        let client = match server_config.server_type() {
+            ServerType::Shadowsocks if pooled => ProxyClient::Shadowsocks(
+                SSClient::new(server_config, dns_server, relay_buffer_size).await?,
+            ),
+            ServerType::Shadowsocks => ProxyClient::Shadowsocks(
+                SSClient::without_idle_connections(server_config, dns_server, relay_buffer_size)
+                    .await?,
+            ),
+            ServerType::Socks5 => ProxyClient::Socks5(
+                Socks5Client::new(server_config, dns_server, relay_buffer_size).await,
+            ),
+            ServerType::Http => ProxyClient::Http(
+                HttpClient::new(server_config, dns_server, relay_buffer_size).await,
+            ),
+        };
+        Ok(client)
+    }
+
+    pub fn name(&self) -> &str {
+        self.server_config().name()
+    }
+
+    pub fn server_config(&self) -> &ServerConfig {
+        match self {
+            ProxyClient::Shadowsocks(c) => c.server_config(),
+            ProxyClient::Socks5(c) => c.server_config(),
+            ProxyClient::Http(c) => c.server_config(),
+        }
+    }
+
+    pub fn stats(&self) -> &ClientStats {
+        match self {
+            ProxyClient::Shadowsocks(c) => c.stats(),
+            ProxyClient::Socks5(c) => c.stats(),
+            ProxyClient::Http(c) => c.stats(),
+        }
+    }
+
+    pub fn connect_errors(&self) -> usize {
+        match self {
+            ProxyClient::Shadowsocks(c) => c.connect_errors(),
+            ProxyClient::Socks5(c) => c.connect_errors(),
+            ProxyClient::Http(c) => c.connect_errors(),
+        }
+    }
+
+    pub fn reset_connect_errors(&self) {
+        match self {
+            ProxyClient::Shadowsocks(c) => c.reset_connect_errors(),
+            ProxyClient::Socks5(c) => c.reset_connect_errors(),
+            ProxyClient::Http(c) => c.reset_connect_errors(),
+        }
+    }
+
+    /// Open a new connection through the server to `target`, send `request` and wait for the
+    /// first bytes of the response. Returns the time it takes, including connecting to the server.
+    pub async fn probe(
+        &self,
+        target: &Address,
+        request: &[u8],
+        probe_timeout: Duration,
+    ) -> Result<Duration> {
+        let now = Instant::now();
+        match self {
+            ProxyClient::Shadowsocks(c) => return c.probe(target, request, probe_timeout).await,
+            ProxyClient::Socks5(c) => {
+                io::timeout(probe_timeout, async {
+                    send_probe(c.connect(target).await?, request).await
+                })
+                .await?
+            }
+            ProxyClient::Http(c) => {
+                io::timeout(probe_timeout, async {
+                    send_probe(c.connect(target).await?, request).await
+                })
+                .await?
+            }
+        }
+        Ok(now.elapsed())
+    }
+}
+
+async fn send_probe<T: Read + Write + Unpin>(mut conn: T, request: &[u8]) -> Result<()> {
+    conn.write_all(request).await?;
+    let mut buf = [0; 1];
+    if conn.read(&mut buf).await? == 0 {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "connection closed before response",
+        ));
+    }
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl Client for ProxyClient {
+    async fn handle_tcp<S: LocalTcpSocket>(&self, socket: S, addr: Address) -> Result<()> {
+        match self {
+            ProxyClient::Shadowsocks(c) => c.handle_tcp(socket, addr).await,
+            ProxyClient::Socks5(c) => c.handle_tcp(socket, addr).await,
+            ProxyClient::Http(c) => c.handle_tcp(socket, addr).await,
+        }
+    }
+
+    async fn handle_udp(&self, socket: TunUdpSocket, addr: Address) -> Result<()> {
+        match self {
+            ProxyClient::Shadowsocks(c) => c.handle_udp(socket, addr).await,
+            ProxyClient::Socks5(c) => c.handle_udp(socket, addr).await,
+            ProxyClient::Http(c) => c.handle_udp(socket, addr).await,
+        }
+    }
+}
+
+/// Resolve the address of a socks5 or http server with `dns_server`.
+pub(crate) async fn resolve_server_addr<T: DnsClient>(
+    resolver: &T,
+    dns_server: &(String, u16),
+    server_addr: &ServerAddr,
+) -> Result<SocketAddr> {
+    match server_addr {
+        ServerAddr::SocketAddr(addr) => Ok(*addr),
+        ServerAddr::DomainName(domain, port) => {
+            match resolve_domain(resolver, (&dns_server.0, dns_server.1), domain).await? {
+                Some(ip) => Ok(SocketAddr::new(ip, *port)),
+                None => Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("domain {} not found", domain),
+                )),
+            }
+        }
+    }
+}
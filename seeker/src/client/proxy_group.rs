@@ -0,0 +1,129 @@
+use super::health_check::ServerHealth;
+use config::{Address, ProxyGroup, ProxyGroupType};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+
+/// Pick the server in `group` for a connection to `addr`. Falls back to the first server
+/// when all servers are down.
+pub(crate) fn select_server<'a>(
+    group: &'a ProxyGroup,
+    healths: &HashMap<String, ServerHealth>,
+    addr: &Address,
+) -> &'a str {
+    let mut alive = group
+        .proxies
+        .iter()
+        .filter(|p| healths.get(*p).map_or(true, |h| h.is_alive()))
+        .peekable();
+    if alive.peek().is_none() {
+        return &group.proxies[0];
+    }
+    let server = match group.group_type {
+        ProxyGroupType::Select => group.proxies.first(),
+        ProxyGroupType::Fallback => alive.next(),
+        ProxyGroupType::UrlTest => {
+            alive.min_by_key(|p| match healths.get(*p).and_then(|h| h.rtt()) {
+                Some(rtt) => (0, rtt),
+                None => (1, Duration::default()),
+            })
+        }
+        ProxyGroupType::LoadBalance => {
+            let host = match addr {
+                Address::SocketAddress(a) => a.ip().to_string(),
+                Address::DomainNameAddress(domain, _) => domain.clone(),
+            };
+            // Rendezvous hashing, a host keeps its server unless the server goes down.
+            alive.max_by_key(|p| {
+                let mut hasher = DefaultHasher::new();
+                (&host, p).hash(&mut hasher);
+                hasher.finish()
+            })
+        }
+    };
+    server.expect("proxies is not empty")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(group_type: ProxyGroupType) -> ProxyGroup {
+        ProxyGroup {
+            name: "group".to_string(),
+            group_type,
+            proxies: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            interval: None,
+        }
+    }
+
+    fn healths(values: &[(&str, Option<u64>)]) -> HashMap<String, ServerHealth> {
+        values
+            .iter()
+            .map(|(name, ms)| {
+                let mut health = ServerHealth::default();
+                health.record(ms.map(Duration::from_millis));
+                (name.to_string(), health)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_select_server() {
+        let addr = Address::DomainNameAddress("example.com".to_string(), 443);
+        let lat = healths(&[("a", None), ("b", Some(200)), ("c", Some(100))]);
+
+        assert_eq!(
+            select_server(&group(ProxyGroupType::Select), &lat, &addr),
+            "a"
+        );
+        assert_eq!(
+            select_server(&group(ProxyGroupType::Fallback), &lat, &addr),
+            "b"
+        );
+        assert_eq!(
+            select_server(&group(ProxyGroupType::UrlTest), &lat, &addr),
+            "c"
+        );
+        assert_eq!(
+            select_server(&group(ProxyGroupType::UrlTest), &HashMap::new(), &addr),
+            "a"
+        );
+
+        let down = healths(&[("a", None), ("b", None), ("c", None)]);
+        assert_eq!(
+            select_server(&group(ProxyGroupType::Fallback), &down, &addr),
+            "a"
+        );
+    }
+
+    #[test]
+    fn test_load_balance() {
+        let g = group(ProxyGroupType::LoadBalance);
+        let all_alive = HashMap::new();
+        let hosts = (0..100)
+            .map(|i| Address::DomainNameAddress(format!("host{}.com", i), 443))
+            .collect::<Vec<_>>();
+
+        let servers = hosts
+            .iter()
+            .map(|h| select_server(&g, &all_alive, h))
+            .collect::<Vec<_>>();
+        for (host, server) in hosts.iter().zip(&servers) {
+            assert_eq!(select_server(&g, &all_alive, host), *server);
+        }
+        assert!(g.proxies.iter().all(|p| servers.contains(&p.as_str())));
+
+        // Only hosts on the down server move.
+        let b_down = healths(&[("b", None)]);
+        for (host, server) in hosts.iter().zip(&servers) {
+            let new_server = select_server(&g, &b_down, host);
+            if *server == "b" {
+                assert_ne!(new_server, "b");
+            } else {
+                assert_eq!(new_server, *server);
+            }
+        }
+    }
+}
@@ -1,26 +1,28 @@
 use std::collections::HashMap;
-use std::io::Result;
+use std::io::{self, ErrorKind, Result};
 use std::net::SocketAddr;
 use std::sync::atomic::Ordering::SeqCst;
 use std::sync::atomic::{AtomicBool, AtomicU64};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use async_std::sync::RwLock;
+use async_std::sync::Mutex as AsyncMutex;
 use async_std::task;
 use chrono::{DateTime, Local};
 use tracing::{error, info, trace_span};
 use tracing_futures::Instrument;
 
-use config::rule::{Action, ProxyRules};
-use config::{Address, Config};
-use ssclient::SSClient;
-use sysconfig::{list_user_proc_socks, SocketInfo};
-use tun::socket::{TunTcpSocket, TunUdpSocket};
+use config::rule::{Action, Process};
+use config::{Address, Config, ServerConfig};
+use sysconfig::{find_socket_owner, list_user_proc_socks, SocketInfo};
+use tun::socket::TunUdpSocket;
 
-use crate::client::Client;
+use crate::client::{Client, LocalTcpSocket};
 
 use super::direct_client::DirectClient;
+use super::health_check::ServerHealth;
+use super::proxy_client::ProxyClient;
+use super::proxy_group::select_server;
 
 #[derive(Hash, Debug, Eq, PartialEq)]
 struct Connection {
@@ -33,9 +35,16 @@ struct Connection {
 
 #[derive(Clone)]
 pub struct RuledClient {
-    conf: Config,
-    rule: ProxyRules,
-    ssclient: Arc<SSClient>,
+    /// Replaced as a whole on reload, so that a connection sees one consistent config
+    conf: Arc<Mutex<Arc<Config>>>,
+    /// One client per server in `server_configs`, created on first use
+    proxy_clients: Arc<AsyncMutex<HashMap<String, Arc<ProxyClient>>>>,
+    /// Clients used only by `health_check`, they keep no idle connections
+    health_clients: Arc<AsyncMutex<HashMap<String, Arc<ProxyClient>>>>,
+    /// Server used by the `PROXY` action
+    proxy_server: Arc<Mutex<String>>,
+    /// Results of `health_check` for each server
+    healths: Arc<Mutex<HashMap<String, ServerHealth>>>,
     direct_client: Arc<DirectClient>,
     proxy_uid: Option<u32>,
     term: Arc<AtomicBool>,
@@ -43,21 +52,21 @@ pub struct RuledClient {
     connections: Arc<Mutex<HashMap<u64, Connection>>>,
 }
 
-async fn new_ssclient(conf: &Config, conf_index: usize) -> SSClient {
+/// Client for the server, `pooled` clients keep the server's `idle_connections` ready
+async fn new_proxy_client(
+    conf: &Config,
+    server_config: ServerConfig,
+    pooled: bool,
+) -> Result<ProxyClient> {
     let dns = conf.dns_server;
     let dns_server_addr = (dns.ip().to_string(), dns.port());
 
-    info!("new_ssclient: {}", conf_index);
-    SSClient::new(
-        Arc::new(RwLock::new(
-            conf.server_configs
-                .get(conf_index)
-                .expect("no config at index")
-                .clone(),
-        )),
-        dns_server_addr.clone(),
-    )
-    .await
+    info!("new_proxy_client: {}", server_config.name());
+    if pooled {
+        ProxyClient::new(server_config, dns_server_addr, conf.relay_buffer_size).await
+    } else {
+        ProxyClient::new_unpooled(server_config, dns_server_addr, conf.relay_buffer_size).await
+    }
 }
 
 async fn new_direct_client(conf: &Config) -> DirectClient {
@@ -69,6 +78,7 @@ async fn new_direct_client(conf: &Config) -> DirectClient {
         conf.direct_read_timeout,
         conf.direct_write_timeout,
         conf.probe_timeout,
+        conf.relay_buffer_size,
     )
     .await
 }
@@ -79,12 +89,20 @@ impl RuledClient {
         proxy_uid: Option<u32>,
         to_terminate: Arc<AtomicBool>,
     ) -> RuledClient {
+        let proxy_server = conf
+            .server_configs
+            .first()
+            .expect("no server configs")
+            .name()
+            .to_string();
         let c = RuledClient {
             term: to_terminate.clone(),
-            rule: conf.rules.clone(),
-            ssclient: Arc::new(new_ssclient(&conf, 0).await),
+            proxy_clients: Arc::new(AsyncMutex::new(HashMap::new())),
+            health_clients: Arc::new(AsyncMutex::new(HashMap::new())),
+            proxy_server: Arc::new(Mutex::new(proxy_server)),
+            healths: Arc::new(Mutex::new(HashMap::new())),
             direct_client: Arc::new(new_direct_client(&conf).await),
-            conf,
+            conf: Arc::new(Mutex::new(Arc::new(conf))),
             proxy_uid,
             counter: Arc::new(AtomicU64::new(0)),
             connections: Arc::new(Mutex::new(HashMap::new())),
@@ -93,34 +111,269 @@ impl RuledClient {
         let _ = task::spawn(async move {
             loop {
                 println!("\nConnections:");
-                client.ssclient.stats().print_stats().await;
+                let proxy_clients = client.proxy_clients().await;
+                for proxy_client in &proxy_clients {
+                    proxy_client.stats().print_stats().await;
+                }
                 client.direct_client.stats().print_stats().await;
+                println!("\nServers:");
+                client.print_healths();
                 println!();
-                client.ssclient.stats().recycle_stats().await;
+                for proxy_client in &proxy_clients {
+                    proxy_client.stats().recycle_stats().await;
+                }
                 client.direct_client.stats().recycle_stats().await;
                 task::sleep(Duration::from_secs(5)).await;
             }
         });
+        let client = c.clone();
+        let _ = task::spawn(async move {
+            let mut last_checks = HashMap::new();
+            loop {
+                let wait = client.check_servers(&mut last_checks).await;
+                task::sleep(wait).await;
+            }
+        });
         c
     }
 
-    async fn get_action_for_addr(&self, remote_addr: SocketAddr, addr: &Address) -> Result<Action> {
-        let domain = match &addr {
-            Address::SocketAddress(a) => a.to_string(),
-            Address::DomainNameAddress(domain, _port) => domain.to_string(),
+    async fn proxy_clients(&self) -> Vec<Arc<ProxyClient>> {
+        self.proxy_clients.lock().await.values().cloned().collect()
+    }
+
+    /// Check the servers whose interval has passed since `last_checks` with `health_check`,
+    /// each server in its own task. Returns how long to wait until the next server is due.
+    async fn check_servers(&self, last_checks: &mut HashMap<String, Instant>) -> Duration {
+        let conf = self.conf();
+        let health_check = conf.health_check.clone();
+        let now = Instant::now();
+        let mut checks = Vec::with_capacity(conf.server_configs.len());
+        for server_config in conf.server_configs.iter() {
+            let name = server_config.name().to_string();
+            let interval = conf.health_check_interval(&name);
+            if let Some(last_check) = last_checks.get(&name) {
+                if now.duration_since(*last_check) < interval {
+                    continue;
+                }
+            }
+            let _ = last_checks.insert(name.clone(), now);
+            let client = get_or_create_client(&self.health_clients, &conf, &name, false).await;
+            let health_check = health_check.clone();
+            checks.push(task::spawn(async move {
+                let rtt = match client {
+                    Ok(client) => {
+                        client
+                            .probe(
+                                &health_check.url.address(),
+                                &health_check.url.request(),
+                                health_check.timeout,
+                            )
+                            .await
+                    }
+                    Err(e) => Err(e),
+                };
+                (name, rtt)
+            }));
+        }
+
+        for check in checks {
+            let (name, rtt) = check.await;
+            let rtt = match rtt {
+                Ok(rtt) => {
+                    info!(server = %name, rtt = ?rtt, "Health check");
+                    Some(rtt)
+                }
+                Err(e) => {
+                    error!(server = %name, error = ?e, "Health check failed");
+                    None
+                }
+            };
+            self.healths
+                .lock()
+                .unwrap()
+                .entry(name)
+                .or_default()
+                .record(rtt);
+        }
+        let exists = |name: &String| conf.server_configs.iter().any(|s| s.name() == name);
+        self.healths.lock().unwrap().retain(|name, _| exists(name));
+        last_checks.retain(|name, _| exists(name));
+
+        let now = Instant::now();
+        last_checks
+            .iter()
+            .map(|(name, last_check)| {
+                (*last_check + conf.health_check_interval(name)).saturating_duration_since(now)
+            })
+            .min()
+            .unwrap_or(health_check.interval)
+    }
+
+    fn print_healths(&self) {
+        let conf = self.conf();
+        let healths = self.healths.lock().unwrap();
+        for server_config in conf.server_configs.iter() {
+            let health = match healths.get(server_config.name()) {
+                Some(h) => h,
+                None => continue,
+            };
+            if let Some(success_rate) = health.success_rate() {
+                println!(
+                    "Server: {}, alive: {}, rtt: {:?}, success rate: {:.0}%",
+                    server_config.name(),
+                    health.is_alive(),
+                    health.rtt(),
+                    success_rate * 100.0
+                );
+            }
+        }
+    }
+
+    /// Client for the server with the name in `conf`
+    async fn proxy_client(&self, conf: &Config, server_name: &str) -> Result<Arc<ProxyClient>> {
+        get_or_create_client(&self.proxy_clients, conf, server_name, true).await
+    }
+
+    /// Client for the server or proxy group with the name. Servers in a group are picked
+    /// by the group's strategy for `addr`.
+    async fn proxy_client_for(&self, name: &str, addr: &Address) -> Result<Arc<ProxyClient>> {
+        let conf = self.conf();
+        let server_name = match conf.proxy_group(name) {
+            Some(group) => {
+                let healths = self.healths.lock().unwrap();
+                select_server(group, &healths, addr).to_string()
+            }
+            None => name.to_string(),
         };
+        self.proxy_client(&conf, &server_name).await
+    }
+
+    /// Client for the `PROXY` action. Change to the next alive server once the current one
+    /// reaches `max_connect_errors`.
+    async fn proxy_action_client(&self) -> Result<Arc<ProxyClient>> {
+        let conf = self.conf();
+        let server_name = self.proxy_server.lock().unwrap().clone();
+        let client = self.proxy_client(&conf, &server_name).await?;
+        if client.connect_errors() <= conf.max_connect_errors {
+            return Ok(client);
+        }
+        let index = conf
+            .server_configs
+            .iter()
+            .position(|s| s.name() == server_name)
+            .unwrap_or(0);
+        let servers = conf.server_configs.len();
+        let next_server = {
+            let healths = self.healths.lock().unwrap();
+            let next = (1..=servers)
+                .map(|i| &conf.server_configs[(index + i) % servers])
+                .find(|s| healths.get(s.name()).map_or(true, |h| h.is_alive()));
+            next.unwrap_or(&conf.server_configs[(index + 1) % servers])
+        };
+        error!(
+            "Server '{}' reached max connect errors, change to another server '{}'",
+            server_name,
+            next_server.name()
+        );
+        client.reset_connect_errors();
+        *self.proxy_server.lock().unwrap() = next_server.name().to_string();
+        self.proxy_client(&conf, next_server.name()).await
+    }
+
+    /// Client for the `PROXY` action or an action naming a server or proxy group
+    async fn proxy_client_for_action(
+        &self,
+        action: &Action,
+        addr: &Address,
+    ) -> Result<Arc<ProxyClient>> {
+        match action {
+            Action::ProxyTo(name) => self.proxy_client_for(name, addr).await,
+            _ => self.proxy_action_client().await,
+        }
+    }
+
+    fn conf(&self) -> Arc<Config> {
+        self.conf.lock().unwrap().clone()
+    }
+
+    /// Replace rules, server configs and proxy groups with those in `conf`. Connections already
+    /// established are not affected. Clients of servers removed or changed are dropped once
+    /// their connections finish. Other options, eg. tun and dns settings, are kept as they are
+    /// and require a restart.
+    pub async fn reload(&self, conf: Config) {
+        let first_server = match conf.server_configs.first() {
+            Some(c) => c.name().to_string(),
+            None => {
+                error!("no server configs, keep using the old config");
+                return;
+            }
+        };
+        for clients in &[&self.proxy_clients, &self.health_clients] {
+            clients.lock().await.retain(|_, client| {
+                conf.server_configs
+                    .iter()
+                    .any(|s| s == client.server_config())
+            });
+        }
+        {
+            let mut proxy_server = self.proxy_server.lock().unwrap();
+            if !conf
+                .server_configs
+                .iter()
+                .any(|s| s.name() == *proxy_server)
+            {
+                *proxy_server = first_server;
+            }
+        }
+        let mut current = self.conf.lock().unwrap();
+        let mut new_conf = Config::clone(&current);
+        new_conf.rules = conf.rules;
+        new_conf.server_configs = conf.server_configs;
+        new_conf.proxy_groups = conf.proxy_groups;
+        *current = Arc::new(new_conf);
+    }
+
+    /// Drop the clients of all servers, closing their idle connections. Used on shutdown once
+    /// connections are drained.
+    pub async fn shutdown(&self) {
+        self.proxy_clients.lock().await.clear();
+        self.health_clients.lock().await.clear();
+    }
+
+    async fn get_action_for_addr(&self, remote_addr: SocketAddr, addr: &Address) -> Result<Action> {
         let mut pass_proxy = false;
         if let Some(uid) = self.proxy_uid {
             if !socket_addr_belong_to_user(remote_addr, uid)? {
                 pass_proxy = true;
             }
         }
+        let conf = self.conf();
+        let rule = &conf.rules;
         let mut action = if pass_proxy {
             Action::Direct
         } else {
-            self.rule
-                .action_for_domain(&domain)
-                .unwrap_or_else(|| self.rule.default_action())
+            let domain = match addr {
+                Address::SocketAddress(_) => None,
+                Address::DomainNameAddress(domain, _port) => Some(domain.as_str()),
+            };
+            let rule_match = rule.match_domain(domain);
+            let process = if rule_match.need_process() {
+                socket_owner(remote_addr).await
+            } else {
+                None
+            };
+            let ip = match addr {
+                Address::SocketAddress(a) => Some(a.ip()),
+                Address::DomainNameAddress(domain, _port) if rule_match.need_resolve() => self
+                    .direct_client
+                    .resolve_domain(domain)
+                    .await
+                    .unwrap_or(None),
+                Address::DomainNameAddress(..) => None,
+            };
+            rule_match
+                .action(ip, process.as_ref())
+                .unwrap_or_else(|| rule.default_action())
         };
 
         if action == Action::Probe {
@@ -140,7 +393,7 @@ impl RuledClient {
 
 #[async_trait::async_trait]
 impl Client for RuledClient {
-    async fn handle_tcp(&self, socket: TunTcpSocket, addr: Address) -> Result<()> {
+    async fn handle_tcp<S: LocalTcpSocket>(&self, socket: S, addr: Address) -> Result<()> {
         let action = self
             .get_action_for_addr(socket.remote_addr(), &addr)
             .await?;
@@ -155,11 +408,13 @@ impl Client for RuledClient {
                     connect_time: Local::now(),
                     sent_bytes: 0,
                     recv_bytes: 0,
-                    action,
+                    action: action.clone(),
                 },
             );
         }
 
+        // Kept to reset the local connection when the upstream one is reset.
+        let local_socket = socket.clone();
         let ret = match action {
             Action::Reject => Ok(()),
             Action::Direct => {
@@ -168,39 +423,26 @@ impl Client for RuledClient {
                     .instrument(trace_span!("DirectClient.handle_tcp", addr = %addr))
                     .await
             }
-            Action::Proxy => {
-                let client = self.ssclient.clone();
-                let connect_errors = client.connect_errors();
-                let old_server_name = client.name().await;
-                if connect_errors > self.conf.max_connect_errors {
-                    let old_conf_index = self
-                        .conf
-                        .server_configs
-                        .iter()
-                        .position(|s| s.name() == old_server_name)
-                        .unwrap_or(0);
-                    let next_conf_index = (old_conf_index + 1) % self.conf.server_configs.len();
-                    error!(
-                        "SSClient '{}' reached max connect errors, change to another server '{}'",
-                        self.conf.server_configs[old_conf_index].name(),
-                        self.conf.server_configs[next_conf_index].name()
-                    );
-                    let new_conf = self
-                        .conf
-                        .server_configs
-                        .get(next_conf_index)
-                        .expect("no config at index")
-                        .clone();
-                    client.change_conf(new_conf).await;
-                    error!("new ssclient with new conf");
+            Action::Proxy | Action::ProxyTo(_) => {
+                match self.proxy_client_for_action(&action, &addr).await {
+                    Ok(client) => {
+                        client
+                            .handle_tcp(socket, addr.clone())
+                            .instrument(
+                                trace_span!("ProxyClient.handle_tcp", addr = %addr, server = client.name()),
+                            )
+                            .await
+                    }
+                    Err(e) => Err(e),
                 }
-                self.ssclient
-                    .handle_tcp(socket, addr.clone())
-                    .instrument(trace_span!("SSClient.handle_tcp", addr = %addr))
-                    .await
             }
             Action::Probe => unreachable!(),
         };
+        if let Err(e) = &ret {
+            if e.kind() == ErrorKind::ConnectionReset {
+                local_socket.abort();
+            }
+        }
         {
             let conn = self.connections.lock().unwrap().remove(&index);
             if let Some(conn) = conn {
@@ -221,15 +463,68 @@ impl Client for RuledClient {
         match action {
             Action::Reject => Ok(()),
             Action::Direct => self.direct_client.handle_udp(socket, addr).await,
-            Action::Proxy => self.ssclient.handle_udp(socket, addr).await,
+            Action::Proxy | Action::ProxyTo(_) => {
+                self.proxy_client_for_action(&action, &addr)
+                    .await?
+                    .handle_udp(socket, addr)
+                    .await
+            }
             Action::Probe => unreachable!(),
         }
     }
 }
 
+/// Client in `clients` for the server with the name in `conf`, created if there is none
+async fn get_or_create_client(
+    clients: &AsyncMutex<HashMap<String, Arc<ProxyClient>>>,
+    conf: &Config,
+    server_name: &str,
+    pooled: bool,
+) -> Result<Arc<ProxyClient>> {
+    if let Some(client) = clients.lock().await.get(server_name) {
+        return Ok(client.clone());
+    }
+    let server_config = conf
+        .server_configs
+        .iter()
+        .find(|s| s.name() == server_name)
+        .ok_or_else(|| unknown_server(server_name))?
+        .clone();
+    // Created without holding the lock, so connecting to one server doesn't wait for
+    // another one to start. When two connections race, the first client inserted wins.
+    let client = Arc::new(new_proxy_client(conf, server_config, pooled).await?);
+    Ok(clients
+        .lock()
+        .await
+        .entry(server_name.to_string())
+        .or_insert(client)
+        .clone())
+}
+
 fn socket_addr_belong_to_user(addr: SocketAddr, uid: u32) -> Result<bool> {
     let user_socks: HashMap<i32, Vec<SocketInfo>> = list_user_proc_socks(uid)?;
     Ok(user_socks
         .values()
         .any(|sockets| sockets.iter().any(|s| s.local == addr)))
 }
+
+fn unknown_server(name: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::NotFound,
+        format!("server {} is not defined in server_configs", name),
+    )
+}
+
+/// Owner of the local socket, looked up in a blocking task as it scans every process
+async fn socket_owner(addr: SocketAddr) -> Option<Process> {
+    match task::spawn_blocking(move || find_socket_owner(addr)).await {
+        Ok(owner) => owner.map(|p| Process {
+            name: p.name,
+            uid: p.uid,
+        }),
+        Err(e) => {
+            error!(addr = %addr, error = ?e, "find socket owner");
+            None
+        }
+    }
+}
@@ -0,0 +1,489 @@
+use crate::client::proxy_client::resolve_server_addr;
+use crate::client::tcp_relay::relay_tcp;
+use crate::client::{unspecified_addr, Client, LocalTcpSocket, UDP_BUFFER_SIZE};
+use async_std::io;
+use async_std::net::{TcpStream, UdpSocket};
+use async_std::prelude::*;
+use async_std::task;
+use async_std::task::JoinHandle;
+use config::socks5::consts;
+use config::{Address, ServerAddr, ServerConfig};
+use hermesdns::DnsNetworkClient;
+use ssclient::client_stats::ClientStats;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{trace, trace_span};
+use tracing_futures::Instrument;
+use tun::socket::TunUdpSocket;
+
+/// Version of the username/password subnegotiation in RFC 1929
+const AUTH_PASSWORD_VERSION: u8 = 1;
+
+/// Client for one upstream socks5 server. TCP uses `CONNECT`, UDP uses `UDP ASSOCIATE`.
+pub struct Socks5Client {
+    srv_cfg: Arc<ServerConfig>,
+    dns_server: (String, u16),
+    resolver: DnsNetworkClient,
+    connect_errors: AtomicUsize,
+    relay_buffer_size: usize,
+    stats: ClientStats,
+}
+
+impl Socks5Client {
+    pub async fn new(
+        server_config: ServerConfig,
+        dns_server: (String, u16),
+        relay_buffer_size: usize,
+    ) -> Socks5Client {
+        Socks5Client {
+            resolver: DnsNetworkClient::new(0, server_config.read_timeout()).await,
+            srv_cfg: Arc::new(server_config),
+            dns_server,
+            connect_errors: AtomicUsize::new(0),
+            relay_buffer_size,
+            stats: ClientStats::new(),
+        }
+    }
+
+    pub fn server_config(&self) -> &ServerConfig {
+        &self.srv_cfg
+    }
+
+    pub fn connect_errors(&self) -> usize {
+        self.connect_errors.load(Ordering::SeqCst)
+    }
+
+    pub fn reset_connect_errors(&self) {
+        let _ = self.connect_errors.swap(0, Ordering::SeqCst);
+    }
+
+    pub fn stats(&self) -> &ClientStats {
+        &self.stats
+    }
+
+    async fn server_addr(&self) -> Result<SocketAddr> {
+        resolve_server_addr(&self.resolver, &self.dns_server, self.srv_cfg.addr()).await
+    }
+
+    /// Connect to the server and finish the handshake for `cmd`. Returns the connection and
+    /// the address in the server's reply.
+    async fn handshake(
+        &self,
+        server_addr: SocketAddr,
+        cmd: u8,
+        addr: &Address,
+    ) -> Result<(TcpStream, Address)> {
+        let ret = io::timeout(self.srv_cfg.connect_timeout(), async {
+            let mut conn = TcpStream::connect(server_addr).await?;
+            self.authenticate(&mut conn).await?;
+
+            let mut request = vec![consts::SOCKS5_VERSION, cmd, 0];
+            request.extend_from_slice(&addr.to_bytes());
+            conn.write_all(&request).await?;
+
+            let mut reply = [0; 3];
+            conn.read_exact(&mut reply).await?;
+            if reply[0] != consts::SOCKS5_VERSION {
+                return Err(invalid_data("invalid socks5 version in reply"));
+            }
+            if reply[1] != consts::SOCKS5_REPLY_SUCCEEDED {
+                return Err(Error::new(
+                    ErrorKind::ConnectionRefused,
+                    format!("socks5 server replied with error {}", reply[1]),
+                ));
+            }
+            let bound_addr = read_address(&mut conn).await?;
+            Ok((conn, bound_addr))
+        })
+        .await;
+        if let Err(e) = &ret {
+            if e.kind() != ErrorKind::TimedOut {
+                self.connect_errors.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        ret
+    }
+
+    /// Negotiate the auth method, username/password (RFC 1929) if `username` is set.
+    async fn authenticate(&self, conn: &mut TcpStream) -> Result<()> {
+        let method = match self.srv_cfg.username() {
+            Some(_) => consts::SOCKS5_AUTH_METHOD_PASSWORD,
+            None => consts::SOCKS5_AUTH_METHOD_NONE,
+        };
+        conn.write_all(&[consts::SOCKS5_VERSION, 1, method]).await?;
+        let mut reply = [0; 2];
+        conn.read_exact(&mut reply).await?;
+        if reply[0] != consts::SOCKS5_VERSION {
+            return Err(invalid_data("invalid socks5 version in reply"));
+        }
+        if reply[1] != method {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "socks5 server does not accept the auth method",
+            ));
+        }
+
+        if let Some(username) = self.srv_cfg.username() {
+            let password = self.srv_cfg.password();
+            if username.len() > 255 || password.len() > 255 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "socks5 username and password must be at most 255 bytes",
+                ));
+            }
+            let mut request = vec![AUTH_PASSWORD_VERSION, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            conn.write_all(&request).await?;
+            conn.read_exact(&mut reply).await?;
+            if reply[0] != AUTH_PASSWORD_VERSION {
+                return Err(invalid_data("invalid socks5 auth version in reply"));
+            }
+            if reply[1] != 0 {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    "socks5 username or password is wrong",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Open a connection through the server to `addr`.
+    pub async fn connect(&self, addr: &Address) -> Result<TcpStream> {
+        let server_addr = self.server_addr().await?;
+        let (conn, _) = self
+            .handshake(server_addr, consts::SOCKS5_CMD_TCP_CONNECT, addr)
+            .await?;
+        Ok(conn)
+    }
+}
+
+#[async_trait::async_trait]
+impl Client for Socks5Client {
+    async fn handle_tcp<S: LocalTcpSocket>(&self, tun_socket: S, addr: Address) -> Result<()> {
+        let conn = self.connect(&addr).await?;
+        relay_tcp(
+            tun_socket,
+            conn,
+            addr,
+            &self.stats,
+            self.srv_cfg.read_timeout(),
+            self.srv_cfg.write_timeout(),
+            self.relay_buffer_size,
+        )
+        .await
+    }
+
+    #[allow(unreachable_code)]
+    async fn handle_udp(&self, socket: TunUdpSocket, addr: Address) -> Result<()> {
+        let read_timeout = self.srv_cfg.read_timeout();
+        let write_timeout = self.srv_cfg.write_timeout();
+        let server_addr = self.server_addr().await?;
+        let unspecified = Address::SocketAddress(SocketAddr::new([0, 0, 0, 0].into(), 0));
+        let (conn, relay_addr) = self
+            .handshake(server_addr, consts::SOCKS5_CMD_UDP_ASSOCIATE, &unspecified)
+            .await?;
+        let relay_addr = match relay_addr {
+            Address::SocketAddress(a) if a.ip().is_unspecified() => {
+                SocketAddr::new(server_addr.ip(), a.port())
+            }
+            Address::SocketAddress(a) => a,
+            Address::DomainNameAddress(domain, port) => {
+                resolve_server_addr(
+                    &self.resolver,
+                    &self.dns_server,
+                    &ServerAddr::DomainName(domain, port),
+                )
+                .await?
+            }
+        };
+        trace!(relay_addr = %relay_addr, "socks5 udp associate");
+
+        // The association lasts as long as the control connection, stop relaying once the
+        // server closes it.
+        let control = async {
+            let mut buf = [0; 1];
+            let _ = (&conn).read(&mut buf).await;
+            Err::<(), _>(Error::new(
+                ErrorKind::ConnectionAborted,
+                "socks5 udp associate connection closed",
+            ))
+        };
+
+        let header_len = 3 + addr.serialized_len();
+        let relay = async {
+            let mut buf = vec![0; header_len + UDP_BUFFER_SIZE];
+            buf[3..header_len].copy_from_slice(&addr.to_bytes());
+            let mut udp_map = HashMap::new();
+            loop {
+                let now = Instant::now();
+                let (recv_from_local_size, local_src) =
+                    io::timeout(read_timeout, socket.recv_from(&mut buf[header_len..])).await?;
+                let duration = now.elapsed();
+                let udp_socket = match udp_map.get(&local_src).cloned() {
+                    Some(socket) => socket,
+                    None => {
+                        let new_udp =
+                            Arc::new(UdpSocket::bind(unspecified_addr(&relay_addr)).await?);
+                        let bind_addr = new_udp.local_addr()?;
+                        trace!(addr = %bind_addr, "bind new udp socket");
+                        udp_map.insert(local_src, new_udp.clone());
+
+                        let cloned_socket = socket.clone();
+                        let cloned_new_udp = new_udp.clone();
+                        let _handle: JoinHandle<Result<_>> = task::spawn(async move {
+                            let mut recv_buf = vec![0; UDP_BUFFER_SIZE];
+                            loop {
+                                let (size, _) = io::timeout(read_timeout, cloned_new_udp.recv_from(&mut recv_buf)).await?;
+                                if size < 3 || recv_buf[2] != 0 {
+                                    trace!(size = size, "drop fragmented or invalid socks5 udp packet");
+                                    continue;
+                                }
+                                let src_addr = Address::read_from(&mut &recv_buf[3..size])?;
+                                let payload = &recv_buf[3 + src_addr.serialized_len()..size];
+                                trace!(size = payload.len(), src_addr = %src_addr, local_udp_socket = ?bind_addr, "recv from socks5 server");
+                                let send_local_size = io::timeout(write_timeout, cloned_socket.send_to(payload, &local_src)).await?;
+                                trace!(size = send_local_size, dst_addr = %local_src, local_udp_socket = ?bind_addr, "send to tun socket");
+                            }
+                            Ok(())
+                        }.instrument(trace_span!("socks5 server to tun socket", socket = %bind_addr)));
+                        new_udp
+                    }
+                };
+                let bind_addr = udp_socket.local_addr()?;
+                trace!(duration = ?duration, size = recv_from_local_size, src_addr = %local_src, local_udp_socket = ?bind_addr, "recv from tun socket");
+                let send_size = io::timeout(
+                    write_timeout,
+                    udp_socket.send_to(&buf[..header_len + recv_from_local_size], relay_addr),
+                )
+                .await?;
+                trace!(size = send_size, dst_addr = %relay_addr, local_udp_socket = ?bind_addr, "send to socks5 server");
+            }
+            Ok::<(), Error>(())
+        };
+
+        relay.race(control).await
+    }
+}
+
+/// Read a socks5 address, eg. `BND.ADDR` and `BND.PORT` of a reply.
+pub(crate) async fn read_address(conn: &mut TcpStream) -> Result<Address> {
+    let mut buf = vec![0; 1];
+    conn.read_exact(&mut buf).await?;
+    let len = match buf[0] {
+        consts::SOCKS5_ADDR_TYPE_IPV4 => 4 + 2,
+        consts::SOCKS5_ADDR_TYPE_IPV6 => 16 + 2,
+        consts::SOCKS5_ADDR_TYPE_DOMAIN_NAME => {
+            let mut domain_len = [0; 1];
+            conn.read_exact(&mut domain_len).await?;
+            buf.push(domain_len[0]);
+            domain_len[0] as usize + 2
+        }
+        atyp => {
+            return Err(invalid_data(&format!(
+                "unsupported socks5 address type {}",
+                atyp
+            )))
+        }
+    };
+    let start = buf.len();
+    buf.resize(start + len, 0);
+    conn.read_exact(&mut buf[start..]).await?;
+    Address::read_from(&mut buf.as_slice())
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::net::TcpListener;
+    use async_std::prelude::*;
+    use config::ServerType;
+    use crypto::CipherType;
+    use std::time::Duration;
+
+    async fn new_client(listener: &TcpListener, username: Option<&str>) -> Socks5Client {
+        let mut config = ServerConfig::new(
+            "socks5".to_string(),
+            ServerAddr::SocketAddr(listener.local_addr().unwrap()),
+            "pass".to_string(),
+            CipherType::ChaCha20Ietf,
+            Duration::from_secs(3),
+            Duration::from_secs(3),
+            Duration::from_secs(3),
+            0,
+        );
+        config.set_server_type(ServerType::Socks5);
+        if let Some(username) = username {
+            config.set_username(username.to_string());
+        }
+        Socks5Client::new(config, ("127.0.0.1".to_string(), 53), 1024).await
+    }
+
+    /// Accept one connection and answer the greeting with `method`, username/password auth
+    /// with `auth_reply` and the request with `reply`. Returns the greeting, the auth request
+    /// and the requested address.
+    async fn mock_server(
+        listener: TcpListener,
+        method: u8,
+        auth_reply: [u8; 2],
+        reply: Vec<u8>,
+    ) -> Result<(Vec<u8>, Vec<u8>, Address)> {
+        let (mut conn, _) = listener.accept().await?;
+        let mut greeting = vec![0; 3];
+        conn.read_exact(&mut greeting).await?;
+        conn.write_all(&[consts::SOCKS5_VERSION, method]).await?;
+        let mut auth = vec![];
+        if method == consts::SOCKS5_AUTH_METHOD_PASSWORD {
+            // VER ULEN UNAME PLEN PASSWD
+            let mut header = [0; 2];
+            conn.read_exact(&mut header).await?;
+            let mut username = vec![0; header[1] as usize];
+            conn.read_exact(&mut username).await?;
+            let mut password_len = [0; 1];
+            conn.read_exact(&mut password_len).await?;
+            let mut password = vec![0; password_len[0] as usize];
+            conn.read_exact(&mut password).await?;
+            auth = [&header[..], &username[..], &password_len[..], &password[..]].concat();
+            conn.write_all(&auth_reply).await?;
+        }
+        let mut header = [0; 3];
+        conn.read_exact(&mut header).await?;
+        assert_eq!(
+            header,
+            [consts::SOCKS5_VERSION, consts::SOCKS5_CMD_TCP_CONNECT, 0]
+        );
+        let addr = read_address(&mut conn).await?;
+        conn.write_all(&reply).await?;
+        conn.write_all(b"data").await?;
+        Ok((greeting, auth, addr))
+    }
+
+    fn success_reply(bound_addr: Address) -> Vec<u8> {
+        let mut reply = vec![consts::SOCKS5_VERSION, consts::SOCKS5_REPLY_SUCCEEDED, 0];
+        reply.extend_from_slice(&bound_addr.to_bytes());
+        reply
+    }
+
+    #[test]
+    fn test_connect() {
+        let target = Address::DomainNameAddress("example.com".to_string(), 443);
+        let bound_addrs = vec![
+            Address::SocketAddress("10.0.0.1:1080".parse().unwrap()),
+            Address::DomainNameAddress("proxy.example.com".to_string(), 1080),
+            Address::SocketAddress("[2001:db8::1]:1080".parse().unwrap()),
+        ];
+        task::block_on(async {
+            for bound_addr in bound_addrs {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let client = new_client(&listener, None).await;
+                let server = task::spawn(mock_server(
+                    listener,
+                    consts::SOCKS5_AUTH_METHOD_NONE,
+                    [0; 2],
+                    success_reply(bound_addr),
+                ));
+                let mut conn = client.connect(&target).await.unwrap();
+                // The whole reply is consumed, whatever the address type
+                let mut data = [0; 4];
+                conn.read_exact(&mut data).await.unwrap();
+                assert_eq!(&data, b"data");
+
+                let (greeting, auth, addr) = server.await.unwrap();
+                assert_eq!(
+                    greeting,
+                    vec![consts::SOCKS5_VERSION, 1, consts::SOCKS5_AUTH_METHOD_NONE]
+                );
+                assert!(auth.is_empty());
+                assert_eq!(addr, target);
+            }
+        });
+    }
+
+    #[test]
+    fn test_connect_with_password() {
+        let target = Address::SocketAddress("1.2.3.4:80".parse().unwrap());
+        let bound_addr = Address::SocketAddress("10.0.0.1:1080".parse().unwrap());
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = new_client(&listener, Some("user")).await;
+            let server = task::spawn(mock_server(
+                listener,
+                consts::SOCKS5_AUTH_METHOD_PASSWORD,
+                [AUTH_PASSWORD_VERSION, 0],
+                success_reply(bound_addr.clone()),
+            ));
+            assert!(client.connect(&target).await.is_ok());
+            let (greeting, auth, addr) = server.await.unwrap();
+            assert_eq!(
+                greeting,
+                vec![
+                    consts::SOCKS5_VERSION,
+                    1,
+                    consts::SOCKS5_AUTH_METHOD_PASSWORD
+                ]
+            );
+            assert_eq!(auth, b"\x01\x04user\x04pass".to_vec());
+            assert_eq!(addr, target);
+
+            for (auth_reply, kind) in vec![
+                ([AUTH_PASSWORD_VERSION, 1], ErrorKind::PermissionDenied),
+                ([consts::SOCKS5_VERSION, 0], ErrorKind::InvalidData),
+            ] {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let client = new_client(&listener, Some("user")).await;
+                let _server = task::spawn(mock_server(
+                    listener,
+                    consts::SOCKS5_AUTH_METHOD_PASSWORD,
+                    auth_reply,
+                    success_reply(bound_addr.clone()),
+                ));
+                assert_eq!(client.connect(&target).await.unwrap_err().kind(), kind);
+            }
+        });
+    }
+
+    #[test]
+    fn test_connect_rejected() {
+        let target = Address::DomainNameAddress("example.com".to_string(), 443);
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = new_client(&listener, None).await;
+            let _server = task::spawn(mock_server(
+                listener,
+                consts::SOCKS5_AUTH_METHOD_NOT_ACCEPTABLE,
+                [0; 2],
+                vec![],
+            ));
+            assert_eq!(
+                client.connect(&target).await.unwrap_err().kind(),
+                ErrorKind::PermissionDenied
+            );
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = new_client(&listener, None).await;
+            let mut reply = success_reply(Address::SocketAddress("0.0.0.0:0".parse().unwrap()));
+            reply[1] = consts::SOCKS5_REPLY_CONNECTION_REFUSED;
+            let _server = task::spawn(mock_server(
+                listener,
+                consts::SOCKS5_AUTH_METHOD_NONE,
+                [0; 2],
+                reply,
+            ));
+            assert_eq!(
+                client.connect(&target).await.unwrap_err().kind(),
+                ErrorKind::ConnectionRefused
+            );
+            assert_eq!(client.connect_errors(), 1);
+        });
+    }
+}
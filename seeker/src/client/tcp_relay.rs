@@ -0,0 +1,180 @@
+use async_std::io::{self, Read, Write};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use chrono::Local;
+use config::Address;
+use ssclient::client_stats::ClientStats;
+use std::io::Result;
+use std::net::Shutdown;
+use std::time::Duration;
+use tracing::trace;
+
+/// Copy data between `tun_socket` and `conn` in both directions, recording the traffic in
+/// `stats`.
+///
+/// The end of one direction is forwarded as a half-close, the other direction keeps going until
+/// its own end or a read timeout.
+pub(crate) async fn relay_tcp<T: Read + Write + Clone + Unpin>(
+    tun_socket: T,
+    conn: TcpStream,
+    addr: Address,
+    stats: &ClientStats,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    buffer_size: usize,
+) -> Result<()> {
+    let mut tun_socket_clone = tun_socket.clone();
+    let mut tun_socket_clone2 = tun_socket;
+    let mut ref_conn = &conn;
+    let mut ref_conn2 = &conn;
+    let idx = stats.add_connection(addr).await;
+    let a = async {
+        let mut buf = vec![0; buffer_size];
+        loop {
+            let rs = io::timeout(read_timeout, tun_socket_clone.read(&mut buf)).await?;
+            trace!(read_size = rs, "relay_tcp: read from tun");
+            if rs == 0 {
+                break;
+            }
+            io::timeout(write_timeout, ref_conn.write_all(&buf[..rs])).await?;
+            trace!(write_size = rs, "relay_tcp: write to remote");
+            stats
+                .update_connection_stats(idx, |stats| {
+                    stats.sent_bytes += rs as u64;
+                })
+                .await;
+        }
+        trace!("relay_tcp: tun closed, shutdown write to remote");
+        ref_conn.shutdown(Shutdown::Write)?;
+        Ok::<(), io::Error>(())
+    };
+    let b = async {
+        let mut buf = vec![0; buffer_size];
+        loop {
+            let rs = io::timeout(read_timeout, ref_conn2.read(&mut buf)).await?;
+            trace!(read_size = rs, "relay_tcp: read from remote");
+            if rs == 0 {
+                break;
+            }
+            io::timeout(write_timeout, tun_socket_clone2.write_all(&buf[..rs])).await?;
+            trace!(write_size = rs, "relay_tcp: write to tun");
+            stats
+                .update_connection_stats(idx, |stats| {
+                    stats.recv_bytes += rs as u64;
+                })
+                .await;
+        }
+        trace!("relay_tcp: remote closed, shutdown write to tun");
+        tun_socket_clone2.close().await?;
+        Ok::<(), io::Error>(())
+    };
+    let ret = a.try_join(b).await.map(|_| ());
+    stats
+        .update_connection_stats(idx, |stats| {
+            stats.close_time = Local::now();
+        })
+        .await;
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::LocalTcpSocket;
+    use crate::proxy_server::LocalTcpStream;
+    use async_std::net::TcpListener;
+    use async_std::prelude::*;
+    use async_std::task;
+    use std::io::ErrorKind;
+    use std::net::SocketAddr;
+
+    const TIMEOUT: Duration = Duration::from_secs(3);
+
+    /// Accept one connection, read it to the end, then echo what was read and close.
+    async fn start_echo_after_eof_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut data = Vec::new();
+            conn.read_to_end(&mut data).await.unwrap();
+            conn.write_all(&data).await.unwrap();
+        });
+        addr
+    }
+
+    /// Accept one connection and reset it.
+    async fn start_reset_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(async move {
+            let (conn, peer_addr) = listener.accept().await.unwrap();
+            LocalTcpStream::new(conn, peer_addr).abort();
+        });
+        addr
+    }
+
+    /// Returns the application's end of a local connection and the end relayed by seeker.
+    async fn local_connection() -> (TcpStream, LocalTcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let app = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (conn, peer_addr) = listener.accept().await.unwrap();
+        (app, LocalTcpStream::new(conn, peer_addr))
+    }
+
+    async fn relay(local: LocalTcpStream, remote_addr: SocketAddr) -> Result<()> {
+        let conn = TcpStream::connect(remote_addr).await?;
+        let stats = ClientStats::new();
+        let ret = relay_tcp(
+            local.clone(),
+            conn,
+            Address::SocketAddress(remote_addr),
+            &stats,
+            TIMEOUT,
+            TIMEOUT,
+            1024,
+        )
+        .await;
+        // As `RuledClient` does.
+        if let Err(e) = &ret {
+            if e.kind() == ErrorKind::ConnectionReset {
+                local.abort();
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn test_relay_half_close() {
+        task::block_on(async {
+            let remote_addr = start_echo_after_eof_server().await;
+            let (mut app, local) = local_connection().await;
+            let relay = task::spawn(relay(local, remote_addr));
+
+            app.write_all(b"hello").await.unwrap();
+            app.shutdown(Shutdown::Write).unwrap();
+            let mut reply = Vec::new();
+            io::timeout(TIMEOUT, app.read_to_end(&mut reply))
+                .await
+                .unwrap();
+            assert_eq!(reply, b"hello");
+            relay.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_relay_upstream_reset() {
+        task::block_on(async {
+            let remote_addr = start_reset_server().await;
+            let (mut app, local) = local_connection().await;
+            let err = relay(local, remote_addr).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+
+            let mut buf = [0; 16];
+            let err = io::timeout(TIMEOUT, app.read(&mut buf)).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+        });
+    }
+}
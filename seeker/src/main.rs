@@ -1,65 +1,81 @@
 mod client;
-//mod signal;
+mod proxy_server;
+mod signal;
 
 use std::error::Error;
 
 use crate::client::ruled_client::RuledClient;
 use crate::client::Client;
-use async_std::io::timeout;
+use crate::proxy_server::http::run_http_server;
+use crate::proxy_server::socks5::run_socks5_server;
+use crate::signal::Signals;
+use async_std::future;
+use async_std::net::TcpListener;
 use async_std::prelude::*;
+use async_std::sync::channel;
 use async_std::task::{block_on, spawn};
 use clap::{App, Arg};
 use config::{Address, Config};
 use dnsserver::create_dns_server;
 use file_rotate::{FileRotate, RotationMode};
 use std::io;
-use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::panic;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use sysconfig::{DNSSetup, IpForward};
-use tracing::{trace, trace_span};
+use std::thread;
+use sysconfig::{restore_system_state, DNSSetup, IpForward};
+use tracing::{error, trace, trace_span};
 use tracing_futures::Instrument;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 use tun::socket::TunSocket;
-use tun::Tun;
+use tun::{BufferSizes, Tun};
 
+/// Accept connections from `tun` until `term` is set, then drain them and set `tun_terminate`.
 async fn handle_connection<T: Client + Clone + Send + Sync + 'static>(
+    tun: Tun,
     client: T,
     config: Config,
     term: Arc<AtomicBool>,
+    tun_terminate: Arc<AtomicBool>,
 ) {
-    let (dns_server, resolver) =
-        create_dns_server("dns.db", config.dns_listen.clone(), config.dns_start_ip).await;
+    let (dns_server, resolver) = create_dns_server(
+        "dns.db",
+        config.dns_listen.clone(),
+        config.dns_start_ip,
+        config.dns_start_ipv6,
+    )
+    .await;
     println!("Spawn DNS server");
     spawn(dns_server.run_server());
-    spawn(Tun::bg_send());
+    spawn(tun.bg_send());
 
-    let mut stream = Tun::listen();
+    // Every connection task holds a sender, the receiver sees the end of the channel once all
+    // of them finish.
+    let (connection_guard, connections_finished) = channel::<()>(1);
+    let mut termination = Box::pin(wait_for_termination(term));
+    let mut stream = tun.listen();
     loop {
-        let socket = timeout(Duration::from_secs(1), async {
-            stream.next().await.transpose()
-        })
-        .await;
+        let socket = async { Some(stream.next().await) }
+            .race(async {
+                (&mut termination).await;
+                None
+            })
+            .await;
         let socket: TunSocket = match socket {
-            Ok(Some(s)) => s,
-            Ok(None) => break,
-            Err(e) if e.kind() == ErrorKind::TimedOut => {
-                if term.load(Ordering::Relaxed) {
-                    break;
-                } else {
-                    continue;
-                }
-            }
-            Err(e) => panic!(e),
+            Some(Some(Ok(s))) => s,
+            Some(Some(Err(e))) => panic!(e),
+            Some(None) | None => break,
         };
+        let guard = connection_guard.clone();
         let resolver_clone = resolver.clone();
         let client_clone = client.clone();
         let remote_addr = socket.local_addr();
 
         spawn(
             async move {
+                let _guard = guard;
                 let ip = remote_addr.ip().to_string();
                 let host = resolver_clone
                     .lookup_host(&ip)
@@ -90,6 +106,85 @@ async fn handle_connection<T: Client + Clone + Send + Sync + 'static>(
             .instrument(trace_span!("handle socket", socket = %remote_addr)),
         );
     }
+
+    println!("Stop accepting connections, wait for connections in flight to finish");
+    tun.stop_accepting();
+    drop(connection_guard);
+    // The listener still reads the packets of connections in flight, new ones are rejected.
+    let listen = async {
+        while let Some(socket) = stream.next().await {
+            if let Err(e) = socket {
+                error!(error = ?e, "read tun while draining");
+                break;
+            }
+        }
+        None
+    };
+    let drained = connections_finished.recv().race(listen);
+    if future::timeout(config.drain_timeout, drained)
+        .await
+        .is_err()
+    {
+        println!(
+            "Connections not finished in {:?} are closed",
+            config.drain_timeout
+        );
+    }
+    tun_terminate.store(true, Ordering::Relaxed);
+}
+
+/// Accept connections on `socks5_listen` and `http_listen` if they are set.
+async fn run_proxy_servers(client: RuledClient, config: &Config) {
+    if let Some(addr) = config.socks5_listen {
+        let listener = bind(addr).await;
+        println!("Listen socks5 on {}", addr);
+        spawn(run_socks5_server(listener, client.clone()));
+    }
+    if let Some(addr) = config.http_listen {
+        let listener = bind(addr).await;
+        println!("Listen http on {}", addr);
+        spawn(run_http_server(listener, client));
+    }
+}
+
+async fn bind(addr: SocketAddr) -> TcpListener {
+    match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to listen on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Wait for SIGINT or SIGTERM, and set `term` for the tasks checking it.
+async fn wait_for_termination(term: Arc<AtomicBool>) {
+    let mut signals = Signals::new(&[signal_hook::SIGINT, signal_hook::SIGTERM]);
+    // Signals received before `signals` is created only set `term`.
+    if !term.load(Ordering::Relaxed) {
+        if let Some(Err(e)) = signals.next().await {
+            error!(error = ?e, "receive signals");
+        }
+    }
+    term.store(true, Ordering::Relaxed);
+}
+
+/// Reload rules, server configs and proxy groups from `path` on every SIGHUP.
+async fn reload_config_on_sighup(path: String, client: RuledClient) {
+    let mut signals = Signals::new(&[signal_hook::SIGHUP]);
+    while let Some(sigs) = signals.next().await {
+        if let Err(e) = sigs {
+            error!(error = ?e, "receive signals");
+            break;
+        }
+        match Config::from_config_file(&path) {
+            Ok(config) => {
+                client.reload(config).await;
+                println!("Reload config {}", path);
+            }
+            Err(e) => eprintln!("Failed to reload config {}: {}", path, e),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -137,6 +232,12 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .help("User id to proxy.")
                 .required(false),
         )
+        .arg(
+            Arg::with_name("no_tun")
+                .long("no-tun")
+                .help("Don't set up tun, only accept connections on socks5_listen and http_listen.")
+                .required(false),
+        )
         .arg(
             Arg::with_name("log")
                 .short("l")
@@ -150,6 +251,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     let path = matches.value_of("config").unwrap();
     let uid = matches.value_of("user_id").map(|uid| uid.parse().unwrap());
     let log_path = matches.value_of("log");
+    let no_tun = matches.is_present("no_tun");
 
     if let Some(log_path) = log_path {
         if let Some(path) = PathBuf::from(log_path).parent() {
@@ -180,21 +282,66 @@ fn main() -> Result<(), Box<dyn Error>> {
             .expect("setting tracing default failed");
     };
 
-    let mut config = Config::from_config_file(path);
+    let mut config = match Config::from_config_file(path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load config {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
 
     let term = Arc::new(AtomicBool::new(false));
     signal_hook::flag::register(signal_hook::SIGINT, Arc::clone(&term))?;
     signal_hook::flag::register(signal_hook::SIGTERM, Arc::clone(&term))?;
+    // Set once connections are drained after `term`, the tun keeps running until then.
+    let tun_terminate = Arc::new(AtomicBool::new(false));
+
+    if no_tun && config.socks5_listen.is_none() && config.http_listen.is_none() {
+        eprintln!("--no-tun requires socks5_listen or http_listen in {}", path);
+        std::process::exit(1);
+    }
 
-    Tun::setup(
-        config.tun_name.clone(),
-        config.tun_ip,
-        config.tun_cidr,
-        term.clone(),
-    );
+    if !no_tun {
+        // Dns and ip forwarding left behind by a crashed run.
+        if let Err(e) = restore_system_state() {
+            eprintln!("Failed to restore system state: {}", e);
+        }
+        // `Drop` of `DNSSetup` and `IpForward` only runs when the main thread unwinds, a
+        // panicking task leaves seeker running without them working. The system falls back to
+        // its own settings, and `Drop` finds nothing left to restore.
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            default_hook(info);
+            if thread::current().name() == Some("main") {
+                return;
+            }
+            if let Err(e) = restore_system_state() {
+                eprintln!("Failed to restore system state: {}", e);
+            }
+        }));
+    }
 
-    let _dns_setup = DNSSetup::new();
-    let _ip_forward = if config.gateway_mode {
+    let tun = if no_tun {
+        None
+    } else {
+        Some(Tun::setup(
+            config.tun_name.clone(),
+            config.tun_ip,
+            config.tun_cidr,
+            match (config.tun_ipv6, config.tun_ipv6_cidr) {
+                (Some(ip), Some(cidr)) => Some((ip, cidr)),
+                _ => None,
+            },
+            BufferSizes {
+                tcp_rx: config.tun_tcp_rx_buffer_size,
+                tcp_tx: config.tun_tcp_tx_buffer_size,
+            },
+            tun_terminate.clone(),
+        ))
+    };
+
+    let _dns_setup = if no_tun { None } else { Some(DNSSetup::new()) };
+    let _ip_forward = if config.gateway_mode && !no_tun {
         // In gateway mode, dns server need be accessible from the network.
         config.dns_listen = "0.0.0.0:53".to_string();
         Some(IpForward::new())
@@ -204,8 +351,16 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     block_on(async {
         let client = RuledClient::new(config.clone(), uid, term.clone()).await;
+        spawn(reload_config_on_sighup(path.to_string(), client.clone()));
+        run_proxy_servers(client.clone(), &config).await;
 
-        handle_connection(client, config, term.clone()).await;
+        match tun {
+            Some(tun) => {
+                handle_connection(tun, client.clone(), config, term.clone(), tun_terminate).await
+            }
+            None => wait_for_termination(term.clone()).await,
+        }
+        client.shutdown().await;
     });
 
     println!("Stop server. Bye bye...");
@@ -0,0 +1,102 @@
+pub mod http;
+pub mod socks5;
+
+use crate::client::LocalTcpSocket;
+use async_std::io::{Read, Write};
+use async_std::net::TcpStream;
+use std::io::{Error, Result};
+use std::mem;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// A connection accepted by a local proxy listener.
+#[derive(Clone)]
+pub struct LocalTcpStream {
+    stream: Arc<TcpStream>,
+    peer_addr: SocketAddr,
+    /// Read before anything from `stream`, eg. a rewritten http request header
+    pending: Arc<Mutex<Vec<u8>>>,
+}
+
+impl LocalTcpStream {
+    pub fn new(stream: TcpStream, peer_addr: SocketAddr) -> Self {
+        LocalTcpStream::with_pending(stream, peer_addr, Vec::new())
+    }
+
+    pub fn with_pending(stream: TcpStream, peer_addr: SocketAddr, pending: Vec<u8>) -> Self {
+        LocalTcpStream {
+            stream: Arc::new(stream),
+            peer_addr,
+            pending: Arc::new(Mutex::new(pending)),
+        }
+    }
+}
+
+impl LocalTcpSocket for LocalTcpStream {
+    fn remote_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// With SO_LINGER set to 0 the kernel sends a RST instead of a FIN once the last clone of
+    /// the stream is dropped and the socket closed.
+    fn abort(&self) {
+        let _ = set_linger_zero(&self.stream);
+    }
+}
+
+fn set_linger_zero(stream: &TcpStream) -> Result<()> {
+    let linger = libc::linger {
+        l_onoff: 1,
+        l_linger: 0,
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_LINGER,
+            &linger as *const libc::linger as *const libc::c_void,
+            mem::size_of::<libc::linger>() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(Error::last_os_error())
+    }
+}
+
+impl Read for LocalTcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if !pending.is_empty() {
+                let size = buf.len().min(pending.len());
+                buf[..size].copy_from_slice(&pending[..size]);
+                pending.drain(..size);
+                return Poll::Ready(Ok(size));
+            }
+        }
+        Pin::new(&mut &*self.stream).poll_read(cx, buf)
+    }
+}
+
+impl Write for LocalTcpStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut &*self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut &*self.stream).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut &*self.stream).poll_close(cx)
+    }
+}
@@ -0,0 +1,228 @@
+use crate::client::http_client::read_header;
+use crate::client::Client;
+use crate::proxy_server::LocalTcpStream;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::task::spawn;
+use config::Address;
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, SocketAddr};
+use tracing::{error, trace, trace_span};
+use tracing_futures::Instrument;
+
+/// Accept http proxy connections and pass them to `client`. `CONNECT` is tunneled as is,
+/// other requests must use an absolute `http://` url and are sent to the host in the url.
+pub async fn run_http_server<T: Client + Clone + Send + Sync + 'static>(
+    listener: TcpListener,
+    client: T,
+) {
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                error!(error = ?e, "accept http connection");
+                continue;
+            }
+        };
+        let peer_addr = match stream.peer_addr() {
+            Ok(a) => a,
+            Err(_) => continue,
+        };
+        let client = client.clone();
+        spawn(
+            async move {
+                if let Err(e) = serve(stream, peer_addr, client).await {
+                    trace!(error = ?e, "http connection");
+                }
+            }
+            .instrument(trace_span!("http connection", peer_addr = %peer_addr)),
+        );
+    }
+}
+
+async fn serve<T: Client>(mut stream: TcpStream, peer_addr: SocketAddr, client: T) -> Result<()> {
+    let header = read_header(&mut stream).await?;
+    let request = match parse_request(&header) {
+        Some(r) => r,
+        None => {
+            stream
+                .write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n")
+                .await?;
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "invalid http proxy request: {}",
+                    header.lines().next().unwrap_or("")
+                ),
+            ));
+        }
+    };
+    trace!(addr = %request.addr, "http proxy request");
+    let socket = match request.header {
+        None => {
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await?;
+            LocalTcpStream::new(stream, peer_addr)
+        }
+        Some(header) => LocalTcpStream::with_pending(stream, peer_addr, header.into_bytes()),
+    };
+    client.handle_tcp(socket, request.addr).await
+}
+
+#[derive(Debug, PartialEq)]
+struct Request {
+    addr: Address,
+    /// Header to send to `addr`, `None` for `CONNECT`
+    header: Option<String>,
+}
+
+fn parse_request(header: &str) -> Option<Request> {
+    let mut lines = header.split("\r\n");
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?;
+    let target = request_line.next()?;
+    let version = request_line.next()?;
+    if !version.starts_with("HTTP/1.") {
+        return None;
+    }
+    if method == "CONNECT" {
+        return Some(Request {
+            addr: parse_host_port(target, 443)?,
+            header: None,
+        });
+    }
+
+    if !target.starts_with("http://") {
+        return None;
+    }
+    let target = &target["http://".len()..];
+    let (host, path) = match target.find('/') {
+        Some(i) => (&target[..i], &target[i..]),
+        None => (target, "/"),
+    };
+    let mut rewritten = format!("{} {} {}\r\n", method, path, version);
+    for line in lines {
+        let name = line.split(':').next().unwrap_or("").to_ascii_lowercase();
+        if line.is_empty()
+            || name == "connection"
+            || name == "proxy-connection"
+            || name == "proxy-authorization"
+        {
+            continue;
+        }
+        rewritten.push_str(line);
+        rewritten.push_str("\r\n");
+    }
+    // Only the first request of the connection is rewritten, so the server must close the
+    // connection after it instead of getting later requests as the browser sent them.
+    rewritten.push_str("Connection: close\r\n\r\n");
+    Some(Request {
+        addr: parse_host_port(host, 80)?,
+        header: Some(rewritten),
+    })
+}
+
+/// Parse `host[:port]`, `ip[:port]` or `[ipv6][:port]`.
+fn parse_host_port(s: &str, default_port: u16) -> Option<Address> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Some(Address::SocketAddress(addr));
+    }
+    let (host, port) = match s.rfind(':') {
+        Some(i) if !s[i..].contains(']') => (&s[..i], s[i + 1..].parse().ok()?),
+        _ => (s, default_port),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+    Some(match host.parse::<IpAddr>() {
+        Ok(ip) => Address::SocketAddress(SocketAddr::new(ip, port)),
+        Err(_) => Address::DomainNameAddress(host.to_string(), port),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_connect() {
+        let request =
+            parse_request("CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n");
+        assert_eq!(
+            request,
+            Some(Request {
+                addr: Address::DomainNameAddress("example.com".to_string(), 443),
+                header: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_absolute_url() {
+        let request = parse_request(
+            "GET http://example.com/index.html?a=1 HTTP/1.1\r\nHost: example.com\r\nProxy-Connection: keep-alive\r\n\r\n",
+        );
+        assert_eq!(
+            request,
+            Some(Request {
+                addr: Address::DomainNameAddress("example.com".to_string(), 80),
+                header: Some(
+                    "GET /index.html?a=1 HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n"
+                        .to_string()
+                ),
+            })
+        );
+
+        let request = parse_request("GET http://127.0.0.1:8080 HTTP/1.0\r\n\r\n").unwrap();
+        assert_eq!(
+            request.addr,
+            Address::SocketAddress("127.0.0.1:8080".parse().unwrap())
+        );
+        assert_eq!(
+            request.header.unwrap(),
+            "GET / HTTP/1.0\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_connection_close() {
+        let request = parse_request(
+            "POST http://example.com/ HTTP/1.1\r\nConnection: keep-alive\r\nHost: example.com\r\nContent-Length: 0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            request.header.unwrap(),
+            "POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_request() {
+        assert_eq!(parse_request("GET /index.html HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(
+            parse_request("GET https://example.com/ HTTP/1.1\r\n\r\n"),
+            None
+        );
+        assert_eq!(parse_request("CONNECT :443 HTTP/1.1\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn test_parse_host_port() {
+        assert_eq!(
+            parse_host_port("[::1]:8080", 80),
+            Some(Address::SocketAddress("[::1]:8080".parse().unwrap()))
+        );
+        assert_eq!(
+            parse_host_port("[::1]", 80),
+            Some(Address::SocketAddress("[::1]:80".parse().unwrap()))
+        );
+        assert_eq!(
+            parse_host_port("example.com", 80),
+            Some(Address::DomainNameAddress("example.com".to_string(), 80))
+        );
+        assert_eq!(parse_host_port("example.com:http", 80), None);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::client::socks5_client::read_address;
+use crate::client::Client;
+use crate::proxy_server::LocalTcpStream;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::task::spawn;
+use config::socks5::consts;
+use config::Address;
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use tracing::{error, trace, trace_span};
+use tracing_futures::Instrument;
+
+/// Accept socks5 connections and pass them to `client`. Only `CONNECT` without
+/// authentication is supported.
+pub async fn run_socks5_server<T: Client + Clone + Send + Sync + 'static>(
+    listener: TcpListener,
+    client: T,
+) {
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                error!(error = ?e, "accept socks5 connection");
+                continue;
+            }
+        };
+        let peer_addr = match stream.peer_addr() {
+            Ok(a) => a,
+            Err(_) => continue,
+        };
+        let client = client.clone();
+        spawn(
+            async move {
+                if let Err(e) = serve(stream, peer_addr, client).await {
+                    trace!(error = ?e, "socks5 connection");
+                }
+            }
+            .instrument(trace_span!("socks5 connection", peer_addr = %peer_addr)),
+        );
+    }
+}
+
+async fn serve<T: Client>(mut stream: TcpStream, peer_addr: SocketAddr, client: T) -> Result<()> {
+    let mut buf = [0; 255];
+    stream.read_exact(&mut buf[..2]).await?;
+    if buf[0] != consts::SOCKS5_VERSION {
+        return Err(invalid_data("invalid socks5 version"));
+    }
+    let methods = buf[1] as usize;
+    stream.read_exact(&mut buf[..methods]).await?;
+    if !buf[..methods].contains(&consts::SOCKS5_AUTH_METHOD_NONE) {
+        stream
+            .write_all(&[
+                consts::SOCKS5_VERSION,
+                consts::SOCKS5_AUTH_METHOD_NOT_ACCEPTABLE,
+            ])
+            .await?;
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "socks5 client requires authentication",
+        ));
+    }
+    stream
+        .write_all(&[consts::SOCKS5_VERSION, consts::SOCKS5_AUTH_METHOD_NONE])
+        .await?;
+
+    stream.read_exact(&mut buf[..3]).await?;
+    let cmd = buf[1];
+    let addr = read_address(&mut stream).await?;
+    if cmd != consts::SOCKS5_CMD_TCP_CONNECT {
+        reply(&mut stream, consts::SOCKS5_REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("unsupported socks5 command {}", cmd),
+        ));
+    }
+    // The connection to `addr` is made by `client`, so the reply can not carry its result.
+    reply(&mut stream, consts::SOCKS5_REPLY_SUCCEEDED).await?;
+    trace!(addr = %addr, "socks5 connect");
+    client
+        .handle_tcp(LocalTcpStream::new(stream, peer_addr), addr)
+        .await
+}
+
+async fn reply(stream: &mut TcpStream, rep: u8) -> Result<()> {
+    let bound_addr = Address::SocketAddress(stream.local_addr()?);
+    let mut buf = vec![consts::SOCKS5_VERSION, rep, 0];
+    buf.extend_from_slice(&bound_addr.to_bytes());
+    stream.write_all(&buf).await
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::LocalTcpSocket;
+    use async_std::prelude::*;
+    use async_std::task;
+    use std::sync::{Arc, Mutex};
+    use tun::socket::TunUdpSocket;
+
+    /// Records the requested addresses and echoes what the application sends
+    #[derive(Clone, Default)]
+    struct EchoClient {
+        addrs: Arc<Mutex<Vec<Address>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Client for EchoClient {
+        async fn handle_tcp<S: LocalTcpSocket>(&self, mut socket: S, addr: Address) -> Result<()> {
+            self.addrs.lock().unwrap().push(addr);
+            let mut buf = [0; 4];
+            socket.read_exact(&mut buf).await?;
+            socket.write_all(&buf).await
+        }
+
+        async fn handle_udp(&self, _socket: TunUdpSocket, _addr: Address) -> Result<()> {
+            Err(Error::new(ErrorKind::Other, "udp is not supported"))
+        }
+    }
+
+    async fn start_server() -> (SocketAddr, EchoClient) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = EchoClient::default();
+        let _ = spawn(run_socks5_server(listener, client.clone()));
+        (addr, client)
+    }
+
+    /// Connect to the server and negotiate no authentication
+    async fn greet(server_addr: SocketAddr) -> TcpStream {
+        let mut conn = TcpStream::connect(server_addr).await.unwrap();
+        conn.write_all(&[
+            consts::SOCKS5_VERSION,
+            2,
+            consts::SOCKS5_AUTH_METHOD_PASSWORD,
+            consts::SOCKS5_AUTH_METHOD_NONE,
+        ])
+        .await
+        .unwrap();
+        let mut reply = [0; 2];
+        conn.read_exact(&mut reply).await.unwrap();
+        assert_eq!(
+            reply,
+            [consts::SOCKS5_VERSION, consts::SOCKS5_AUTH_METHOD_NONE]
+        );
+        conn
+    }
+
+    /// Send a request for `cmd` and return the reply code
+    async fn request(conn: &mut TcpStream, cmd: u8, addr: &Address) -> u8 {
+        let mut request = vec![consts::SOCKS5_VERSION, cmd, 0];
+        request.extend_from_slice(&addr.to_bytes());
+        conn.write_all(&request).await.unwrap();
+        let mut reply = [0; 3];
+        conn.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[0], consts::SOCKS5_VERSION);
+        let _bound_addr = read_address(conn).await.unwrap();
+        reply[1]
+    }
+
+    #[test]
+    fn test_connect() {
+        let targets = vec![
+            Address::SocketAddress("1.2.3.4:80".parse().unwrap()),
+            Address::DomainNameAddress("example.com".to_string(), 443),
+            Address::SocketAddress("[2001:db8::1]:443".parse().unwrap()),
+        ];
+        task::block_on(async {
+            let (server_addr, client) = start_server().await;
+            for target in &targets {
+                let mut conn = greet(server_addr).await;
+                assert_eq!(
+                    request(&mut conn, consts::SOCKS5_CMD_TCP_CONNECT, target).await,
+                    consts::SOCKS5_REPLY_SUCCEEDED
+                );
+                conn.write_all(b"ping").await.unwrap();
+                let mut data = [0; 4];
+                conn.read_exact(&mut data).await.unwrap();
+                assert_eq!(&data, b"ping");
+            }
+            assert_eq!(*client.addrs.lock().unwrap(), targets);
+        });
+    }
+
+    #[test]
+    fn test_auth_required() {
+        task::block_on(async {
+            let (server_addr, client) = start_server().await;
+            let mut conn = TcpStream::connect(server_addr).await.unwrap();
+            conn.write_all(&[
+                consts::SOCKS5_VERSION,
+                1,
+                consts::SOCKS5_AUTH_METHOD_PASSWORD,
+            ])
+            .await
+            .unwrap();
+            let mut reply = [0; 2];
+            conn.read_exact(&mut reply).await.unwrap();
+            assert_eq!(
+                reply,
+                [
+                    consts::SOCKS5_VERSION,
+                    consts::SOCKS5_AUTH_METHOD_NOT_ACCEPTABLE
+                ]
+            );
+            assert_eq!(conn.read(&mut reply).await.unwrap(), 0);
+            assert!(client.addrs.lock().unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_unsupported_command() {
+        let target = Address::SocketAddress("1.2.3.4:80".parse().unwrap());
+        task::block_on(async {
+            let (server_addr, client) = start_server().await;
+            for cmd in &[
+                consts::SOCKS5_CMD_TCP_BIND,
+                consts::SOCKS5_CMD_UDP_ASSOCIATE,
+            ] {
+                let mut conn = greet(server_addr).await;
+                assert_eq!(
+                    request(&mut conn, *cmd, &target).await,
+                    consts::SOCKS5_REPLY_COMMAND_NOT_SUPPORTED
+                );
+                let mut buf = [0; 1];
+                assert_eq!(conn.read(&mut buf).await.unwrap(), 0);
+            }
+            assert!(client.addrs.lock().unwrap().is_empty());
+        });
+    }
+}
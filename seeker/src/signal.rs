@@ -1,10 +1,10 @@
 use async_std::net::driver::Watcher;
-use async_std::task::Context;
-use futures::{Poll, Stream};
+use async_std::stream::Stream;
 use std::borrow::Borrow;
-use std::io::Result;
+use std::io::{ErrorKind, Result};
 use std::os::raw::c_int;
 use std::pin::Pin;
+use std::task::{Context, Poll};
 
 pub struct Signals {
     watcher: Watcher<signal_hook::iterator::Signals>,
@@ -31,6 +31,10 @@ impl Stream for Signals {
         let r = self.watcher.poll_read_with(cx, |signals| {
             if !signals.is_closed() {
                 let signals: Vec<c_int> = signals.pending().collect();
+                if signals.is_empty() {
+                    // Wait for the next signal instead of waking up with nothing.
+                    return Err(ErrorKind::WouldBlock.into());
+                }
                 Ok(Some(signals))
             } else {
                 Ok(None)
@@ -46,8 +50,8 @@ impl Stream for Signals {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use async_std::prelude::*;
     use async_std::task;
-    use futures::StreamExt;
 
     #[test]
     fn test_signal() {
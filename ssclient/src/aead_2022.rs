@@ -0,0 +1,74 @@
+//! Parts shared by the TCP and UDP protocols of ShadowSocks 2022 (SIP022)
+
+use std::io::{Error, ErrorKind, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+
+/// Header type of requests from the client
+pub(crate) const HEADER_TYPE_CLIENT: u8 = 0;
+/// Header type of responses from the server
+pub(crate) const HEADER_TYPE_SERVER: u8 = 1;
+
+/// Headers with a timestamp further than this from now are rejected
+const MAX_TIME_DIFF_SECS: u64 = 30;
+const MAX_PADDING_SIZE: usize = 900;
+
+/// Seconds since the unix epoch
+pub(crate) fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before unix epoch")
+        .as_secs()
+}
+
+pub(crate) fn check_header(header_type: u8, timestamp: u64) -> Result<()> {
+    if header_type != HEADER_TYPE_SERVER {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid header type {}", header_type),
+        ));
+    }
+    let now = self::timestamp();
+    let diff = if now > timestamp {
+        now - timestamp
+    } else {
+        timestamp - now
+    };
+    if diff > MAX_TIME_DIFF_SECS {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("header timestamp {} is {}s away from now", timestamp, diff),
+        ));
+    }
+    Ok(())
+}
+
+/// Random padding for a request header without payload, which must not be empty.
+pub(crate) fn gen_padding() -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+    let mut padding = vec![0; rng.gen_range(1, MAX_PADDING_SIZE + 1)];
+    rng.fill(&mut padding[..]);
+    padding
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_header() {
+        assert!(check_header(HEADER_TYPE_SERVER, timestamp()).is_ok());
+        assert!(check_header(HEADER_TYPE_CLIENT, timestamp()).is_err());
+        assert!(check_header(HEADER_TYPE_SERVER, timestamp() - 60).is_err());
+        assert!(check_header(HEADER_TYPE_SERVER, timestamp() + 60).is_err());
+    }
+
+    #[test]
+    fn test_gen_padding() {
+        for _ in 0..100 {
+            let len = gen_padding().len();
+            assert!(len >= 1 && len <= MAX_PADDING_SIZE);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::VecDeque;
+use std::future::Future;
 use std::io::Result;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -11,6 +12,10 @@ use tracing::{error, trace};
 use crate::encrypted_stream::EncryptedTcpStream;
 use crate::BoxFuture;
 
+/// First delay before creating a connection again after an error, doubled on every error
+const MIN_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
 pub(crate) type EncryptedStremBox = Box<dyn EncryptedTcpStream + Send + Sync>;
 
 pub(crate) type Connector =
@@ -39,45 +44,52 @@ impl Pool {
         }
     }
 
-    pub(crate) async fn run_connection_pool(&self) {
+    /// Keep `max_idle` connections in the pool. The returned future only holds the receiving
+    /// end of the refill channel, so it ends once the pool and all its clones are dropped.
+    pub(crate) fn run_connection_pool(&self) -> impl Future<Output = ()> + Send + 'static {
+        let max_idle = self.max_idle;
         let connections = self.connections.clone();
-        loop {
-            let mut len = connections.lock().await.len();
-            while len < self.max_idle {
-                trace!(
-                    current_idle = len,
-                    max_idle = self.max_idle,
-                    "create new connection"
-                );
-                let conn = match self.new_connection().await {
-                    Ok(conn) => conn,
-                    Err(e) => {
-                        error!(error = ?e, "create new connection error");
-                        continue;
-                    }
-                };
-                let mut conns = connections.lock().await;
-                conns.push_back(conn);
-                len = conns.len();
-            }
-            if self.receiver.recv().await == None {
-                break;
+        let connector = self.connector.clone();
+        let connect_timeout = self.connect_timeout;
+        let receiver = self.receiver.clone();
+        async move {
+            let mut retry_delay = MIN_RETRY_DELAY;
+            loop {
+                let mut len = connections.lock().await.len();
+                while len < max_idle {
+                    trace!(
+                        current_idle = len,
+                        max_idle = max_idle,
+                        "create new connection"
+                    );
+                    let conn = match new_connection(&connector, connect_timeout).await {
+                        Ok(conn) => {
+                            retry_delay = MIN_RETRY_DELAY;
+                            conn
+                        }
+                        Err(e) => {
+                            error!(error = ?e, retry_delay = ?retry_delay, "create new connection error");
+                            if !wait_before_retry(&receiver, retry_delay).await {
+                                return;
+                            }
+                            retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+                            continue;
+                        }
+                    };
+                    let mut conns = connections.lock().await;
+                    conns.push_back(conn);
+                    len = conns.len();
+                }
+                if receiver.recv().await == None {
+                    break;
+                }
             }
         }
     }
 
-    async fn new_connection(&self) -> Result<EncryptedStremBox> {
-        let now = Instant::now();
-        let conn = match io::timeout(self.connect_timeout, (self.connector)()).await {
-            Ok(conn) => conn,
-            Err(e) => {
-                error!(err = ?e, "new connection error");
-                return Err(e);
-            }
-        };
-        let duration = now.elapsed();
-        trace!(duration = ?duration, "Pool.new_connection");
-        Ok(conn)
+    /// Create a connection bypassing the idle connections
+    pub(crate) async fn connect(&self) -> Result<EncryptedStremBox> {
+        new_connection(&self.connector, self.connect_timeout).await
     }
 
     pub(crate) async fn get_connection(&self) -> Result<EncryptedStremBox> {
@@ -86,7 +98,7 @@ impl Pool {
             Some(conn) => Ok(conn),
             None => {
                 trace!("connection pool empty, create connection directly");
-                self.new_connection().await
+                new_connection(&self.connector, self.connect_timeout).await
             }
         };
         let size = self.size().await;
@@ -111,6 +123,38 @@ impl Pool {
     }
 }
 
+/// Wait `delay` ignoring refill requests, so every connection taken from the pool doesn't
+/// cut the delay short. Returns false once the pool and all its clones are dropped.
+async fn wait_before_retry(receiver: &Receiver<()>, delay: Duration) -> bool {
+    let deadline = Instant::now() + delay;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        if let Ok(None) = future::timeout(deadline - now, receiver.recv()).await {
+            return false;
+        }
+    }
+}
+
+async fn new_connection(
+    connector: &Connector,
+    connect_timeout: Duration,
+) -> Result<EncryptedStremBox> {
+    let now = Instant::now();
+    let conn = match io::timeout(connect_timeout, connector()).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!(err = ?e, "new connection error");
+            return Err(e);
+        }
+    };
+    let duration = now.elapsed();
+    trace!(duration = ?duration, "Pool.new_connection");
+    Ok(conn)
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Result;
@@ -163,10 +207,7 @@ mod tests {
                 10,
                 Duration::from_secs(5),
             );
-            let pool_clone = pool.clone();
-            task::spawn(async move {
-                pool_clone.run_connection_pool().await;
-            });
+            task::spawn(pool.run_connection_pool());
             let _conn = pool.get_connection().await?;
             task::sleep(Duration::from_secs(1)).await;
             assert!(pool.size().await > 0);
@@ -174,4 +215,35 @@ mod tests {
         });
         ret.unwrap();
     }
+
+    #[test]
+    fn test_pool_connect_error() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        task::block_on(async move {
+            let pool = Pool::new(
+                Arc::new(move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Box::pin(async {
+                        let err = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "");
+                        Err::<EncryptedStremBox, _>(err)
+                    })
+                }),
+                10,
+                Duration::from_secs(5),
+            );
+            let handle = task::spawn(pool.run_connection_pool());
+            assert!(pool.get_connection().await.is_err());
+            task::sleep(Duration::from_millis(200)).await;
+            // One by the pool, which then waits before retrying, and one direct connection.
+            assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+            drop(pool);
+            future::timeout(Duration::from_secs(1), handle)
+                .await
+                .expect("pool task keeps running after the pool is dropped");
+        });
+    }
 }
@@ -0,0 +1,425 @@
+//! TCP stream of ShadowSocks 2022 (SIP022)
+//!
+//! Request stream
+//! ```plain
+//! +--------+------------------------+---------------------------+------------------------+-----+
+//! |  salt  | fixed-length header    | variable-length header    | length chunk + payload | ... |
+//! +--------+------------------------+---------------------------+------------------------+-----+
+//! |        | type, timestamp, len   | addr, padding len, padding|                        |     |
+//! +--------+------------------------+---------------------------+------------------------+-----+
+//! ```
+//!
+//! Response stream
+//! ```plain
+//! +--------+----------------------------------------------+-----------------+-----+
+//! |  salt  | fixed-length header                          | initial payload | ... |
+//! +--------+----------------------------------------------+-----------------+-----+
+//! |        | type, timestamp, request salt, payload len   |                 |     |
+//! +--------+----------------------------------------------+-----------------+-----+
+//! ```
+
+use std::io::{Error, ErrorKind, Result};
+use std::net::Shutdown;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_std::io;
+use async_std::net::{SocketAddr, TcpStream};
+use async_std::prelude::*;
+use byteorder::{BigEndian, ByteOrder};
+use bytes::{BufMut, Bytes, BytesMut};
+use tracing::trace;
+
+use config::Address;
+use crypto::{BoxAeadDecryptor, BoxAeadEncryptor, CipherType};
+
+use crate::aead_2022::{check_header, gen_padding, timestamp, HEADER_TYPE_CLIENT};
+use crate::replay_filter;
+use crate::tcp_io::{aead_encrypted_write, read_exact_or_eof};
+use crate::{recv_iv, send_iv, BoxFuture, MAX_PACKET_SIZE};
+
+use super::{EncryptedReader, EncryptedTcpStream, EncryptedWriter};
+
+/// Chunks of SIP022 carry up to 0xFFFF bytes
+const MAX_CHUNK_SIZE: usize = 0xFFFF;
+
+pub struct Aead2022EncryptedTcpStream {
+    conn: TcpStream,
+    method: CipherType,
+    key: Bytes,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    /// Salt of the request, which the server sends back in its response header
+    request_salt: Mutex<Option<Bytes>>,
+}
+
+impl Aead2022EncryptedTcpStream {
+    pub async fn new(
+        ssserver: SocketAddr,
+        method: CipherType,
+        key: Bytes,
+        connect_timeout: Duration,
+        read_timeout: Duration,
+        write_timeout: Duration,
+    ) -> Result<Self> {
+        let now = Instant::now();
+        let conn = io::timeout(connect_timeout, TcpStream::connect(ssserver)).await?;
+        let duration = now.elapsed();
+        trace!(duration = ?duration, addr = %ssserver, "TcpStream::connect");
+
+        Ok(Self {
+            conn,
+            method,
+            key,
+            read_timeout,
+            write_timeout,
+            request_salt: Mutex::new(None),
+        })
+    }
+}
+
+impl EncryptedTcpStream for Aead2022EncryptedTcpStream {
+    fn get_writer<'a, 'b: 'a>(
+        &'b self,
+    ) -> BoxFuture<'b, Result<Box<dyn EncryptedWriter<'a> + 'a + Send>>> {
+        Box::pin(async move {
+            let writer = Aead2022EncryptedWriter::new(
+                &self.conn,
+                self.method,
+                self.key.clone(),
+                &self.request_salt,
+                self.write_timeout,
+            )
+            .await?;
+            let w: Box<dyn EncryptedWriter + Send> = Box::new(writer);
+            Ok(w)
+        })
+    }
+
+    fn get_reader<'a, 'b: 'a>(
+        &'b self,
+    ) -> BoxFuture<'b, Result<Box<dyn EncryptedReader<'a> + 'a + Send>>> {
+        Box::pin(async move {
+            let reader = Aead2022EncryptedReader::new(
+                &self.conn,
+                self.method,
+                self.key.clone(),
+                &self.request_salt,
+                self.read_timeout,
+            )
+            .await?;
+            let r: Box<dyn EncryptedReader + Send> = Box::new(reader);
+            Ok(r)
+        })
+    }
+}
+
+pub struct Aead2022EncryptedWriter<'a> {
+    conn: &'a TcpStream,
+    encrypt_cipher: BoxAeadEncryptor,
+    send_buf: Vec<u8>,
+    method: CipherType,
+    write_timeout: Duration,
+}
+
+impl<'a> Aead2022EncryptedWriter<'a> {
+    pub async fn new(
+        conn: &'a TcpStream,
+        method: CipherType,
+        key: Bytes,
+        request_salt: &'a Mutex<Option<Bytes>>,
+        write_timeout: Duration,
+    ) -> Result<Aead2022EncryptedWriter<'a>> {
+        let salt = send_iv(&conn, method, write_timeout).await?;
+        let cipher = crypto::new_aead_encryptor(method, &key, &salt);
+        *request_salt.lock().unwrap() = Some(salt);
+
+        Ok(Aead2022EncryptedWriter {
+            conn: &conn,
+            encrypt_cipher: cipher,
+            send_buf: vec![0; MAX_PACKET_SIZE + 2 + 2 * method.tag_size()],
+            method,
+            write_timeout,
+        })
+    }
+
+    async fn write_all(&mut self, size: usize) -> Result<()> {
+        let now = Instant::now();
+        io::timeout(
+            self.write_timeout,
+            self.conn.write_all(&self.send_buf[..size]),
+        )
+        .await?;
+        let duration = now.elapsed();
+        trace!(duration = ?duration, size = size, "send to ss server");
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl EncryptedWriter<'_> for Aead2022EncryptedWriter<'_> {
+    /// Send the fixed-length and variable-length request headers. No payload is sent with
+    /// them, so the padding is never empty.
+    async fn send_addr(&mut self, addr: &Address) -> Result<()> {
+        let padding = gen_padding();
+        let mut var_header = BytesMut::with_capacity(addr.serialized_len() + 2 + padding.len());
+        addr.write_to_buf(&mut var_header);
+        var_header.put_u16_be(padding.len() as u16);
+        var_header.put_slice(&padding);
+
+        let mut fixed_header = [0; 11];
+        fixed_header[0] = HEADER_TYPE_CLIENT;
+        BigEndian::write_u64(&mut fixed_header[1..9], timestamp());
+        BigEndian::write_u16(&mut fixed_header[9..11], var_header.len() as u16);
+
+        let tag_size = self.method.tag_size();
+        let fixed_size = fixed_header.len() + tag_size;
+        let size = fixed_size + var_header.len() + tag_size;
+        self.encrypt_cipher
+            .encrypt(&fixed_header, &mut self.send_buf[..fixed_size]);
+        self.encrypt_cipher
+            .encrypt(&var_header, &mut self.send_buf[fixed_size..size]);
+        self.write_all(size).await
+    }
+
+    async fn send_all(&mut self, buf: &[u8]) -> Result<()> {
+        let size = aead_encrypted_write(
+            &mut self.encrypt_cipher,
+            &buf,
+            &mut self.send_buf,
+            self.method,
+        )?;
+        self.write_all(size).await
+    }
+
+    async fn shutdown(&mut self) -> Result<()> {
+        self.conn.shutdown(Shutdown::Write)
+    }
+}
+
+pub struct Aead2022EncryptedReader<'a> {
+    conn: &'a TcpStream,
+    decrypt_cipher: BoxAeadDecryptor,
+    /// Checked for replay once the response header decrypts, so data not from the server
+    /// can't fill the filter
+    unchecked_salt: Option<Vec<u8>>,
+    request_salt: &'a Mutex<Option<Bytes>>,
+    recv_buf: Vec<u8>,
+    method: CipherType,
+    read_timeout: Duration,
+    header_received: bool,
+    /// Decrypted data of the last chunk not returned by `recv` yet, chunks may be larger
+    /// than the buffer passed to `recv`.
+    pending: Vec<u8>,
+    pending_pos: usize,
+}
+
+impl<'a> Aead2022EncryptedReader<'a> {
+    pub async fn new(
+        conn: &'a TcpStream,
+        method: CipherType,
+        key: Bytes,
+        request_salt: &'a Mutex<Option<Bytes>>,
+        read_timeout: Duration,
+    ) -> Result<Aead2022EncryptedReader<'a>> {
+        let salt = recv_iv(&conn, method, read_timeout).await?;
+        let decrypt_cipher = crypto::new_aead_decryptor(method, &key, &salt);
+
+        Ok(Aead2022EncryptedReader {
+            conn: &conn,
+            decrypt_cipher,
+            unchecked_salt: Some(salt),
+            request_salt,
+            recv_buf: vec![0; MAX_CHUNK_SIZE + method.tag_size()],
+            method,
+            read_timeout,
+            header_received: false,
+            pending: Vec::new(),
+            pending_pos: 0,
+        })
+    }
+
+    async fn read_decrypted(&mut self, len: usize) -> Result<()> {
+        let size = len + self.method.tag_size();
+        self.conn.read_exact(&mut self.recv_buf[..size]).await?;
+        self.decrypt_pending(len)
+    }
+
+    fn decrypt_pending(&mut self, len: usize) -> Result<()> {
+        let size = len + self.method.tag_size();
+        self.pending.resize(len, 0);
+        self.decrypt_cipher
+            .decrypt(&self.recv_buf[..size], &mut self.pending)?;
+        Ok(())
+    }
+
+    /// Read the response header and return the length of the initial payload
+    async fn read_header(&mut self) -> Result<usize> {
+        let salt_size = self.method.salt_size();
+        self.read_decrypted(1 + 8 + salt_size + 2).await?;
+        if let Some(salt) = self.unchecked_salt.take() {
+            replay_filter::check_salt(&salt)?;
+        }
+        let header = &self.pending;
+        check_header(header[0], BigEndian::read_u64(&header[1..9]))?;
+        let request_salt = self.request_salt.lock().unwrap().clone();
+        if request_salt.as_ref().map(|s| &s[..]) != Some(&header[9..9 + salt_size]) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "request salt in response header mismatch",
+            ));
+        }
+        Ok(BigEndian::read_u16(&header[9 + salt_size..]) as usize)
+    }
+
+    /// Read the next chunk into `pending`, returns false if the stream ends before it.
+    async fn read_chunk(&mut self) -> Result<bool> {
+        let len = if self.header_received {
+            let mut conn = self.conn;
+            let size = 2 + self.method.tag_size();
+            if !read_exact_or_eof(&mut conn, &mut self.recv_buf[..size]).await? {
+                return Ok(false);
+            }
+            self.decrypt_pending(2)?;
+            BigEndian::read_u16(&self.pending) as usize
+        } else {
+            let len = self.read_header().await?;
+            self.header_received = true;
+            len
+        };
+        self.read_decrypted(len).await?;
+        self.pending_pos = 0;
+        Ok(true)
+    }
+}
+
+#[async_trait::async_trait]
+impl EncryptedReader<'_> for Aead2022EncryptedReader<'_> {
+    async fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let now = Instant::now();
+        // The initial payload may be empty.
+        while self.pending_pos == self.pending.len() {
+            if !io::timeout(self.read_timeout, self.read_chunk()).await? {
+                return Ok(0);
+            }
+        }
+        let size = buf.len().min(self.pending.len() - self.pending_pos);
+        buf[..size].copy_from_slice(&self.pending[self.pending_pos..self.pending_pos + size]);
+        self.pending_pos += size;
+        let duration = now.elapsed();
+        trace!(duration = ?duration, size = size, "read from ss server");
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_std::net::TcpListener;
+    use async_std::task;
+
+    use crate::aead_2022::HEADER_TYPE_SERVER;
+
+    use super::*;
+
+    /// Accept one request, check its headers and payload, then send `response` back.
+    async fn serve_once(
+        listener: TcpListener,
+        method: CipherType,
+        key: Bytes,
+        addr: Address,
+        request: &[u8],
+        response: Vec<u8>,
+    ) -> Result<()> {
+        let (mut conn, _) = listener.accept().await?;
+        let tag_size = method.tag_size();
+        let mut salt = vec![0; method.salt_size()];
+        conn.read_exact(&mut salt).await?;
+        let mut decryptor = crypto::new_aead_decryptor(method, &key, &salt);
+
+        let mut buf = vec![0; MAX_CHUNK_SIZE + tag_size];
+        let mut fixed_header = [0; 11];
+        conn.read_exact(&mut buf[..11 + tag_size]).await?;
+        decryptor.decrypt(&buf[..11 + tag_size], &mut fixed_header)?;
+        assert_eq!(fixed_header[0], HEADER_TYPE_CLIENT);
+        let len = BigEndian::read_u16(&fixed_header[9..]) as usize;
+        let mut var_header = vec![0; len];
+        conn.read_exact(&mut buf[..len + tag_size]).await?;
+        decryptor.decrypt(&buf[..len + tag_size], &mut var_header)?;
+        let mut cursor = &var_header[..];
+        assert_eq!(Address::read_from(&mut cursor)?, addr);
+        let padding_len = BigEndian::read_u16(cursor) as usize;
+        assert!(padding_len > 0);
+        assert_eq!(cursor.len(), 2 + padding_len);
+
+        let mut len_buf = [0; 2];
+        conn.read_exact(&mut buf[..2 + tag_size]).await?;
+        decryptor.decrypt(&buf[..2 + tag_size], &mut len_buf)?;
+        let len = BigEndian::read_u16(&len_buf) as usize;
+        let mut payload = vec![0; len];
+        conn.read_exact(&mut buf[..len + tag_size]).await?;
+        decryptor.decrypt(&buf[..len + tag_size], &mut payload)?;
+        assert_eq!(payload, request);
+
+        let response_salt = method.gen_salt();
+        let mut encryptor = crypto::new_aead_encryptor(method, &key, &response_salt);
+        let mut header = vec![HEADER_TYPE_SERVER];
+        header.extend_from_slice(&timestamp().to_be_bytes());
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&(response.len() as u16).to_be_bytes());
+        let mut out = response_salt.to_vec();
+        let offset = out.len();
+        out.resize(offset + header.len() + tag_size, 0);
+        encryptor.encrypt(&header, &mut out[offset..]);
+        let offset = out.len();
+        out.resize(offset + response.len() + tag_size, 0);
+        encryptor.encrypt(&response, &mut out[offset..]);
+        conn.write_all(&out).await
+    }
+
+    #[test]
+    fn test_encrypted_stream_2022() {
+        const BIND_ADDR: &str = "127.0.0.1:65511";
+        let method = CipherType::Aead2022Blake3Aes256Gcm;
+        let key = method.gen_salt();
+        let addr = Address::DomainNameAddress("www.baidu.com".to_string(), 80);
+        let response = vec![7u8; MAX_PACKET_SIZE + 100];
+
+        task::block_on(async {
+            let listener = TcpListener::bind(BIND_ADDR).await.unwrap();
+            let server = task::spawn(serve_once(
+                listener,
+                method,
+                key.clone(),
+                addr.clone(),
+                b"request",
+                response.clone(),
+            ));
+
+            let stream = Aead2022EncryptedTcpStream::new(
+                BIND_ADDR.parse().unwrap(),
+                method,
+                key,
+                Duration::from_secs(3),
+                Duration::from_secs(3),
+                Duration::from_secs(3),
+            )
+            .await
+            .unwrap();
+            let mut writer = stream.get_writer().await.unwrap();
+            writer.send_addr(&addr).await.unwrap();
+            writer.send_all(b"request").await.unwrap();
+
+            let mut reader = stream.get_reader().await.unwrap();
+            let mut buf = vec![0; MAX_PACKET_SIZE];
+            let mut received = Vec::new();
+            while received.len() < response.len() {
+                let size = reader.recv(&mut buf).await.unwrap();
+                received.extend_from_slice(&buf[..size]);
+            }
+            assert_eq!(received, response);
+            server.await.unwrap();
+        });
+    }
+}
@@ -1,4 +1,5 @@
 use std::io::Result;
+use std::net::Shutdown;
 use std::time::{Duration, Instant};
 
 use async_std::io;
@@ -10,7 +11,8 @@ use tracing::trace;
 use config::Address;
 use crypto::{BoxAeadDecryptor, BoxAeadEncryptor, CipherType};
 
-use crate::tcp_io::{aead_decrypted_read, aead_encrypted_write};
+use crate::replay_filter;
+use crate::tcp_io::{aead_decrypted_read_data, aead_decrypted_read_len, aead_encrypted_write};
 use crate::{recv_iv, send_iv, BoxFuture, MAX_PACKET_SIZE};
 
 use super::{EncryptedReader, EncryptedTcpStream, EncryptedWriter};
@@ -141,11 +143,18 @@ impl EncryptedWriter<'_> for AeadEncryptedWriter<'_> {
 
         Ok(())
     }
+
+    async fn shutdown(&mut self) -> Result<()> {
+        self.conn.shutdown(Shutdown::Write)
+    }
 }
 
 pub struct AeadEncryptedReader<'a> {
     conn: &'a TcpStream,
     decrypt_cipher: BoxAeadDecryptor,
+    /// Checked for replay once the first chunk length decrypts, so data not from the server
+    /// can't fill the filter
+    unchecked_salt: Option<Vec<u8>>,
     recv_buf: Vec<u8>,
     method: CipherType,
     read_timeout: Duration,
@@ -166,6 +175,7 @@ impl<'a> AeadEncryptedReader<'a> {
         Ok(AeadEncryptedReader {
             conn: &conn,
             decrypt_cipher,
+            unchecked_salt: Some(iv),
             recv_buf: vec![0; MAX_PACKET_SIZE],
             method,
             read_timeout,
@@ -179,16 +189,32 @@ impl EncryptedReader<'_> for AeadEncryptedReader<'_> {
         let cipher_type = self.method;
 
         let now = Instant::now();
-        let size = io::timeout(
-            self.read_timeout,
-            aead_decrypted_read(
+        let size = io::timeout(self.read_timeout, async {
+            let mut conn = self.conn;
+            let len = match aead_decrypted_read_len(
+                &mut self.decrypt_cipher,
+                &mut conn,
+                &mut self.recv_buf,
+                cipher_type,
+            )
+            .await?
+            {
+                Some(len) => len,
+                None => return Ok(0),
+            };
+            if let Some(salt) = self.unchecked_salt.take() {
+                replay_filter::check_salt(&salt)?;
+            }
+            aead_decrypted_read_data(
                 &mut self.decrypt_cipher,
-                self.conn,
+                &mut conn,
                 &mut self.recv_buf,
                 buf,
+                len,
                 cipher_type,
-            ),
-        )
+            )
+            .await
+        })
         .await?;
         let duration = now.elapsed();
         trace!(duration = ?duration, size = size, "read from ss server");
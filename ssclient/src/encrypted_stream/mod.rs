@@ -11,6 +11,9 @@ pub(crate) trait EncryptedWriter<'a> {
     async fn send_addr(&mut self, addr: &Address) -> Result<()>;
 
     async fn send_all(&mut self, buf: &[u8]) -> Result<()>;
+
+    /// Shut down the write side of the connection, sending a FIN to the ss server
+    async fn shutdown(&mut self) -> Result<()>;
 }
 
 pub(crate) trait EncryptedTcpStream {
@@ -23,9 +26,13 @@ pub(crate) trait EncryptedTcpStream {
     ) -> BoxFuture<'b, Result<Box<dyn EncryptedReader<'a> + 'a + Send>>>;
 }
 
+mod aead_2022_encrypt;
 mod aead_encrypt;
 mod stream_encrypt;
 
+pub use aead_2022_encrypt::{
+    Aead2022EncryptedReader, Aead2022EncryptedTcpStream, Aead2022EncryptedWriter,
+};
 pub use aead_encrypt::{AeadEncryptedReader, AeadEncryptedTcpStream, AeadEncryptedWriter};
 use config::Address;
 pub use stream_encrypt::{StreamEncryptedReader, StreamEncryptedTcpStream, StreamEncryptedWriter};
@@ -1,4 +1,5 @@
 use std::io::Result;
+use std::net::Shutdown;
 use std::time::{Duration, Instant};
 
 use async_std::io;
@@ -128,6 +129,10 @@ impl EncryptedWriter<'_> for StreamEncryptedWriter<'_> {
         trace!(duration = ?duration, size = send_size, "send to ss server");
         Ok(())
     }
+
+    async fn shutdown(&mut self) -> Result<()> {
+        self.conn.shutdown(Shutdown::Write)
+    }
 }
 
 pub struct StreamEncryptedReader<'a> {
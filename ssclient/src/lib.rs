@@ -10,17 +10,19 @@ use std::time::{Duration, Instant};
 use async_std::io::{timeout, Read, Write};
 use async_std::net::{TcpStream, UdpSocket};
 use async_std::prelude::*;
-use async_std::sync::RwLock;
 use async_std::task;
 use async_std::task::JoinHandle;
 use bytes::{Bytes, BytesMut};
-use tracing::{trace, trace_span};
+use tracing::{debug, trace, trace_span};
 use tracing_futures::Instrument;
 
 use crate::client_stats::ClientStats;
 use crate::connection_pool::{EncryptedStremBox, Pool};
-use crate::encrypted_stream::{AeadEncryptedTcpStream, StreamEncryptedTcpStream};
-use crate::udp_io::{decrypt_payload, encrypt_payload};
+use crate::encrypted_stream::{
+    Aead2022EncryptedTcpStream, AeadEncryptedTcpStream, StreamEncryptedTcpStream,
+};
+use crate::plugin::Plugin;
+use crate::udp_io::{decrypt_payload, encrypt_payload, UdpSession};
 use chrono::Local;
 use config::{Address, ServerAddr, ServerConfig};
 use crypto::{CipherCategory, CipherType};
@@ -29,35 +31,78 @@ use std::future::Future;
 use std::pin::Pin;
 use tun::socket::TunUdpSocket;
 
+mod aead_2022;
 pub mod client_stats;
 mod connection_pool;
 mod encrypted_stream;
+mod plugin;
+mod replay_filter;
 mod tcp_io;
 mod udp_io;
 
 const MAX_PACKET_SIZE: usize = 0x3FFF;
+/// Large enough for any udp datagram, smaller buffers can't receive large ones
+const UDP_BUFFER_SIZE: usize = 64 * 1024;
 
 type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a + Send>>;
 
+/// Client for one shadowsocks server. Each client has its own connection pool and stats.
 pub struct SSClient {
-    srv_cfg: Arc<RwLock<ServerConfig>>,
+    srv_cfg: Arc<ServerConfig>,
     dns_server: (String, u16),
     resolver: Arc<DnsNetworkClient>,
     pool: Pool,
     connect_errors: Arc<AtomicUsize>,
     stats: ClientStats,
+    /// Size of the buffers reading relayed tcp data from the local connection
+    relay_buffer_size: usize,
+    /// Kept to stop the plugin when the client is dropped
+    _plugin: Option<Plugin>,
 }
 
 impl SSClient {
+    /// Create a client for the server keeping `idle_connections` connections ready. TCP
+    /// connections go through the server's SIP003 plugin if it has one, UDP is always sent to
+    /// the server directly.
     pub async fn new(
-        server_config: Arc<RwLock<ServerConfig>>,
+        server_config: ServerConfig,
         dns_server: (String, u16),
-    ) -> SSClient {
+        relay_buffer_size: usize,
+    ) -> Result<SSClient> {
+        let idle_connections = server_config.idle_connections();
+        SSClient::with_idle_connections(
+            server_config,
+            dns_server,
+            relay_buffer_size,
+            idle_connections,
+        )
+        .await
+    }
+
+    /// Create a client that keeps no idle connections, eg. one only used to probe the server.
+    pub async fn without_idle_connections(
+        server_config: ServerConfig,
+        dns_server: (String, u16),
+        relay_buffer_size: usize,
+    ) -> Result<SSClient> {
+        SSClient::with_idle_connections(server_config, dns_server, relay_buffer_size, 0).await
+    }
+
+    async fn with_idle_connections(
+        server_config: ServerConfig,
+        dns_server: (String, u16),
+        relay_buffer_size: usize,
+        idle_connections: usize,
+    ) -> Result<SSClient> {
+        let plugin = match server_config.plugin() {
+            Some(plugin) => Some(Plugin::start(plugin, &server_config)?),
+            None => None,
+        };
+        let plugin_addr = plugin.as_ref().map(|p| p.local_addr());
+        let server_config = Arc::new(server_config);
         let server_config_clone = server_config.clone();
-        let idle_connections = server_config.read().await.idle_connections();
-        let connect_timeout = server_config.read().await.connect_timeout();
-        let resolver =
-            Arc::new(DnsNetworkClient::new(0, server_config.read().await.read_timeout()).await);
+        let connect_timeout = server_config.connect_timeout();
+        let resolver = Arc::new(DnsNetworkClient::new(0, server_config.read_timeout()).await);
         let resolver_clone = resolver.clone();
         let dns_server_clone = dns_server.clone();
         let connect_errors = Arc::new(AtomicUsize::new(0));
@@ -70,50 +115,12 @@ impl SSClient {
                 let connect_errors = connect_errors_clone.clone();
 
                 Box::pin(async move {
-                    let ret = async move {
-                        let srv_cfg = srv_cfg.read().await;
-                        let read_timeout = srv_cfg.read_timeout();
-                        let write_timeout = srv_cfg.write_timeout();
-                        let connect_timeout = srv_cfg.connect_timeout();
-                        let key = srv_cfg.key();
-                        let method = srv_cfg.method();
-                        let server_addr = srv_cfg.addr().clone();
-                        drop(srv_cfg);
-
-                        trace!(server_addr=?server_addr, "connect to ssserver");
-                        let ssserver = get_remote_ssserver_addr(
-                            &*resolver,
-                            &server_addr,
-                            (&dns_server.0, dns_server.1),
-                        )
-                        .await?;
-
-                        let conn: EncryptedStremBox = match method.category() {
-                            CipherCategory::Stream => Box::new(
-                                StreamEncryptedTcpStream::new(
-                                    ssserver,
-                                    method,
-                                    key,
-                                    connect_timeout,
-                                    read_timeout,
-                                    write_timeout,
-                                )
-                                .await?,
-                            ),
-                            CipherCategory::Aead => Box::new(
-                                AeadEncryptedTcpStream::new(
-                                    ssserver,
-                                    method,
-                                    key,
-                                    connect_timeout,
-                                    read_timeout,
-                                    write_timeout,
-                                )
-                                .await?,
-                            ),
-                        };
-                        Ok(conn)
-                    }
+                    let ret = connect_ssserver(
+                        &*resolver,
+                        (&dns_server.0, dns_server.1),
+                        &srv_cfg,
+                        plugin_addr,
+                    )
                     .await;
                     if ret.is_err() {
                         connect_errors.fetch_add(1, Ordering::SeqCst);
@@ -125,33 +132,35 @@ impl SSClient {
             connect_timeout,
         );
 
-        let pool_clone = pool.clone();
         let _ = task::spawn(
-            async move {
-                pool_clone.run_connection_pool().await;
-            }
-            .instrument(trace_span!("background connection pool")),
+            pool.run_connection_pool()
+                .instrument(trace_span!("background connection pool")),
         );
-        SSClient {
+        Ok(SSClient {
             srv_cfg: server_config.clone(),
             connect_errors,
             resolver,
             dns_server,
             pool,
             stats: ClientStats::new(),
-        }
+            relay_buffer_size,
+            _plugin: plugin,
+        })
     }
 
-    pub async fn name(&self) -> String {
-        self.srv_cfg.read().await.name().to_string()
+    pub fn name(&self) -> &str {
+        self.srv_cfg.name()
+    }
+
+    pub fn server_config(&self) -> &ServerConfig {
+        &self.srv_cfg
     }
 
     pub fn connect_errors(&self) -> usize {
         self.connect_errors.load(Ordering::SeqCst)
     }
 
-    pub async fn change_conf(&self, conf: ServerConfig) {
-        *self.srv_cfg.write().await = conf;
+    pub fn reset_connect_errors(&self) {
         let _ = self.connect_errors.swap(0, Ordering::SeqCst);
     }
 
@@ -159,6 +168,36 @@ impl SSClient {
         &self.stats
     }
 
+    /// Open a new connection through the server to `target`, send `request` and wait for the
+    /// first bytes of the response. Returns the time it takes, including connecting to the server.
+    pub async fn probe(
+        &self,
+        target: &Address,
+        request: &[u8],
+        probe_timeout: Duration,
+    ) -> Result<Duration> {
+        let now = Instant::now();
+        timeout(probe_timeout, async {
+            let conn = self.pool.connect().await?;
+            let mut writer = conn.get_writer().await?;
+            writer.send_addr(target).await?;
+            writer.send_all(request).await?;
+            let mut reader = conn.get_reader().await?;
+            let mut buf = vec![0; MAX_PACKET_SIZE];
+            if reader.recv(&mut buf).await? == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "connection closed before response",
+                ));
+            }
+            Ok(())
+        })
+        .await?;
+        let duration = now.elapsed();
+        trace!(server = self.name(), duration = ?duration, "probe server");
+        Ok(duration)
+    }
+
     #[allow(unreachable_code)]
     async fn handle_encrypted_tcp_stream<T: Read + Write + Clone + Unpin>(
         &self,
@@ -170,10 +209,8 @@ impl SSClient {
         let conn1 = &conn;
         let conn2 = &conn;
         let mut tun_socket_clone = tun_socket.clone();
-        let (read_timeout, write_timeout) = {
-            let srv_cfg = self.srv_cfg.read().await;
-            (srv_cfg.read_timeout(), srv_cfg.write_timeout())
-        };
+        let read_timeout = self.srv_cfg.read_timeout();
+        let write_timeout = self.srv_cfg.write_timeout();
 
         let send_task = async move {
             let mut writer = conn1.get_writer().await?;
@@ -182,7 +219,7 @@ impl SSClient {
             let duration = now.elapsed();
             trace!(duration = ?duration, addr = %addr, "send addr to ssserver");
 
-            let mut buf = vec![0; MAX_PACKET_SIZE];
+            let mut buf = vec![0; self.relay_buffer_size];
             loop {
                 let now = Instant::now();
                 let size = timeout(read_timeout, tun_socket_clone.read(&mut buf)).await?;
@@ -193,7 +230,9 @@ impl SSClient {
                     break;
                 }
 
-                writer.send_all(&buf[..size]).await?;
+                for chunk in buf[..size].chunks(MAX_PACKET_SIZE) {
+                    writer.send_all(chunk).await?;
+                }
 
                 self.stats
                     .update_connection_stats(idx, |stats| {
@@ -201,12 +240,15 @@ impl SSClient {
                     })
                     .await;
             }
+            trace!("tun socket closed, shutdown write to ssserver");
+            writer.shutdown().await?;
             Ok::<(), io::Error>(())
         };
 
         let recv_task = async move {
             let mut reader = conn2.get_reader().await?;
-            let mut buf = vec![0; MAX_PACKET_SIZE];
+            // Chunks from the server are decrypted at once, the buffer holds the largest one.
+            let mut buf = vec![0; self.relay_buffer_size.max(MAX_PACKET_SIZE)];
             loop {
                 let size = reader.recv(&mut buf).await?;
                 if size == 0 {
@@ -223,10 +265,13 @@ impl SSClient {
                     })
                     .await;
             }
+            trace!("ssserver closed, shutdown write to tun socket");
+            tun_socket.close().await?;
             Ok::<(), io::Error>(())
         };
 
-        send_task.race(recv_task).await?;
+        // Each direction ends on its own, forwarding the FIN, until both are done.
+        send_task.try_join(recv_task).await?;
         Ok(())
     }
 
@@ -265,24 +310,21 @@ impl SSClient {
         tun_socket: TunUdpSocket,
         addr: Address,
     ) -> Result<()> {
-        let (key, method, server_addr, read_timeout, write_timeout) = {
-            let srv_cfg = self.srv_cfg.read().await;
-            let key = srv_cfg.key();
-            let method = srv_cfg.method();
-            let server_addr = srv_cfg.addr().clone();
-            let read_timeout = srv_cfg.read_timeout();
-            let write_timeout = srv_cfg.write_timeout();
-            (key, method, server_addr, read_timeout, write_timeout)
-        };
+        let key = self.srv_cfg.key();
+        let method = self.srv_cfg.method();
+        let read_timeout = self.srv_cfg.read_timeout();
+        let write_timeout = self.srv_cfg.write_timeout();
         let ssserver = get_remote_ssserver_addr(
             &*self.resolver,
-            &server_addr,
+            self.srv_cfg.addr(),
             (&self.dns_server.0, self.dns_server.1),
         )
         .await?;
-        let mut buf = vec![0; MAX_PACKET_SIZE];
-        let mut encrypt_buf = BytesMut::with_capacity(MAX_PACKET_SIZE);
+        let mut buf = vec![0; addr.serialized_len() + UDP_BUFFER_SIZE];
+        let mut encrypt_buf = BytesMut::with_capacity(UDP_BUFFER_SIZE);
         let mut udp_map = HashMap::new();
+        // AEAD 2022 servers tell the sources apart by their sessions
+        let mut sessions = HashMap::new();
         let cipher_type = method;
         let key = key.to_vec();
 
@@ -296,9 +338,11 @@ impl SSClient {
             )
             .await?;
             let duration = now.elapsed();
+            let session = sessions.entry(local_src).or_insert_with(UdpSession::new);
             let encrypt_size = encrypt_payload(
                 cipher_type,
                 &key,
+                session,
                 &buf[..addr.serialized_len() + recv_from_tun_size],
                 &mut encrypt_buf,
             )?;
@@ -306,7 +350,12 @@ impl SSClient {
             let udp_socket = match udp_map.get(&local_src).cloned() {
                 Some(socket) => socket,
                 None => {
-                    let new_udp = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+                    let unspecified = if ssserver.is_ipv6() {
+                        "[::]:0"
+                    } else {
+                        "0.0.0.0:0"
+                    };
+                    let new_udp = Arc::new(UdpSocket::bind(unspecified).await?);
                     let bind_addr = new_udp.local_addr()?;
                     trace!(addr = %bind_addr, "bind new udp socket");
                     udp_map.insert(local_src, new_udp.clone());
@@ -314,9 +363,10 @@ impl SSClient {
                     let cloned_socket = tun_socket.clone();
                     let cloned_new_udp = new_udp.clone();
                     let key_cloned = key.clone();
+                    let mut session = sessions[&local_src].clone();
                     let _handle: JoinHandle<Result<()>> = task::spawn(async move {
-                        let mut recv_buf = vec![0; MAX_PACKET_SIZE];
-                        let mut decrypt_buf = BytesMut::with_capacity(MAX_PACKET_SIZE);
+                        let mut recv_buf = vec![0; UDP_BUFFER_SIZE];
+                        let mut decrypt_buf = BytesMut::with_capacity(UDP_BUFFER_SIZE);
                         loop {
                             decrypt_buf.clear();
                             let now = Instant::now();
@@ -325,12 +375,20 @@ impl SSClient {
                                     .await?;
                             let duration = now.elapsed();
                             trace!(duration = ?duration, size = recv_from_ss_size, src_addr = %udp_ss_addr, local_udp_socket = ?bind_addr, "recv from ss server");
-                            let decrypt_size = decrypt_payload(
+                            let decrypt_size = match decrypt_payload(
                                 cipher_type,
                                 &key_cloned,
+                                &mut session,
                                 &recv_buf[..recv_from_ss_size],
                                 &mut decrypt_buf,
-                            )?;
+                            ) {
+                                Ok(size) => size,
+                                // Replayed or corrupted packets are dropped without ending the relay.
+                                Err(e) => {
+                                    debug!(error = ?e, src_addr = %udp_ss_addr, "drop udp packet");
+                                    continue;
+                                }
+                            };
                             trace!(
                                 "decrypt {} bytes with {} to {} bytes",
                                 recv_from_ss_size,
@@ -381,7 +439,7 @@ async fn send_iv(
 ) -> Result<Bytes> {
     let iv = match method.category() {
         CipherCategory::Stream => method.gen_init_vec(),
-        CipherCategory::Aead => method.gen_salt(),
+        CipherCategory::Aead | CipherCategory::Aead2022 => method.gen_salt(),
     };
 
     let now = Instant::now();
@@ -399,7 +457,7 @@ async fn recv_iv(
 ) -> Result<Vec<u8>> {
     let iv_size = match method.category() {
         CipherCategory::Stream => method.iv_size(),
-        CipherCategory::Aead => method.salt_size(),
+        CipherCategory::Aead | CipherCategory::Aead2022 => method.salt_size(),
     };
 
     let mut iv = vec![0; iv_size];
@@ -434,6 +492,63 @@ pub async fn resolve_domain<T: DnsClient>(
     ip
 }
 
+async fn connect_ssserver(
+    resolver: &impl DnsClient,
+    dns_server: (&str, u16),
+    srv_cfg: &ServerConfig,
+    plugin_addr: Option<SocketAddr>,
+) -> Result<EncryptedStremBox> {
+    let read_timeout = srv_cfg.read_timeout();
+    let write_timeout = srv_cfg.write_timeout();
+    let connect_timeout = srv_cfg.connect_timeout();
+    let key = srv_cfg.key();
+    let method = srv_cfg.method();
+    let server_addr = srv_cfg.addr();
+
+    trace!(server_addr=?server_addr, plugin_addr=?plugin_addr, "connect to ssserver");
+    let ssserver = match plugin_addr {
+        Some(addr) => addr,
+        None => get_remote_ssserver_addr(resolver, server_addr, dns_server).await?,
+    };
+
+    let conn: EncryptedStremBox = match method.category() {
+        CipherCategory::Stream => Box::new(
+            StreamEncryptedTcpStream::new(
+                ssserver,
+                method,
+                key,
+                connect_timeout,
+                read_timeout,
+                write_timeout,
+            )
+            .await?,
+        ),
+        CipherCategory::Aead => Box::new(
+            AeadEncryptedTcpStream::new(
+                ssserver,
+                method,
+                key,
+                connect_timeout,
+                read_timeout,
+                write_timeout,
+            )
+            .await?,
+        ),
+        CipherCategory::Aead2022 => Box::new(
+            Aead2022EncryptedTcpStream::new(
+                ssserver,
+                method,
+                key,
+                connect_timeout,
+                read_timeout,
+                write_timeout,
+            )
+            .await?,
+        ),
+    };
+    Ok(conn)
+}
+
 async fn get_remote_ssserver_addr(
     resolver: &impl DnsClient,
     server_addr: &ServerAddr,
@@ -507,4 +622,174 @@ mod tests {
             assert_eq!(addr.unwrap(), "1.2.3.4:7789".parse().unwrap());
         });
     }
+
+    #[test]
+    fn test_probe_server() {
+        use crate::encrypted_stream::{
+            AeadEncryptedReader, AeadEncryptedWriter, EncryptedReader, EncryptedWriter,
+        };
+        use async_std::net::TcpListener;
+
+        const REQUEST: &[u8] = b"HEAD /generate_204 HTTP/1.1\r\nHost: www.gstatic.com\r\n\r\n";
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let cfg = mock_server_config(listener.local_addr().unwrap());
+            let (method, key) = (cfg.method(), cfg.key());
+            let server = task::spawn(async move {
+                let (conn, _) = listener.accept().await?;
+                let mut reader =
+                    AeadEncryptedReader::new(&conn, method, key.clone(), Duration::from_secs(3))
+                        .await?;
+                let mut buf = vec![0; 1024];
+                let size = reader.recv(&mut buf).await?;
+                let addr = Address::read_from(&mut &buf[..size])?;
+                let size = reader.recv(&mut buf).await?;
+                let request = buf[..size].to_vec();
+                let mut writer =
+                    AeadEncryptedWriter::new(&conn, method, key, Duration::from_secs(3)).await?;
+                writer.send_all(b"HTTP/1.1 204 No Content\r\n\r\n").await?;
+                Ok::<_, Error>((addr, request))
+            });
+
+            let client =
+                SSClient::without_idle_connections(cfg, ("127.0.0.1".to_string(), 53), 1024)
+                    .await
+                    .unwrap();
+            let target = Address::DomainNameAddress("www.gstatic.com".to_string(), 80);
+            let rtt = client.probe(&target, REQUEST, Duration::from_secs(3)).await;
+            assert!(rtt.is_ok(), "{:?}", rtt);
+            let (addr, request) = server.await.unwrap();
+            assert_eq!(addr, target);
+            assert_eq!(request, REQUEST);
+
+            // Nothing listens on the port anymore
+            assert!(client
+                .probe(&target, REQUEST, Duration::from_secs(3))
+                .await
+                .is_err());
+        });
+    }
+
+    fn mock_server_config(addr: SocketAddr) -> ServerConfig {
+        ServerConfig::new(
+            "servername".to_string(),
+            ServerAddr::SocketAddr(addr),
+            "pass".to_string(),
+            CipherType::Aes128Gcm,
+            Duration::from_secs(3),
+            Duration::from_secs(3),
+            Duration::from_secs(3),
+            0,
+        )
+    }
+
+    /// Returns the application's end of a local connection and the end relayed to the server.
+    async fn local_connection() -> (TcpStream, TcpStream) {
+        use async_std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let app = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (local, _) = listener.accept().await.unwrap();
+        (app, local)
+    }
+
+    #[test]
+    fn test_relay_half_close() {
+        use crate::encrypted_stream::{
+            AeadEncryptedReader, AeadEncryptedWriter, EncryptedReader, EncryptedWriter,
+        };
+        use async_std::net::TcpListener;
+        use std::net::Shutdown;
+
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let cfg = mock_server_config(listener.local_addr().unwrap());
+            let (method, key) = (cfg.method(), cfg.key());
+            // Echo what the client sends once it shuts down its write side.
+            let server = task::spawn(async move {
+                let (conn, _) = listener.accept().await?;
+                let mut reader =
+                    AeadEncryptedReader::new(&conn, method, key.clone(), Duration::from_secs(3))
+                        .await?;
+                let mut buf = vec![0; 1024];
+                let size = reader.recv(&mut buf).await?;
+                let addr = Address::read_from(&mut &buf[..size])?;
+                let mut data = Vec::new();
+                loop {
+                    let size = reader.recv(&mut buf).await?;
+                    if size == 0 {
+                        break;
+                    }
+                    data.extend_from_slice(&buf[..size]);
+                }
+                let mut writer =
+                    AeadEncryptedWriter::new(&conn, method, key, Duration::from_secs(3)).await?;
+                writer.send_all(&data).await?;
+                Ok::<_, Error>(addr)
+            });
+
+            let client =
+                SSClient::without_idle_connections(cfg, ("127.0.0.1".to_string(), 53), 1024)
+                    .await
+                    .unwrap();
+            let target = Address::DomainNameAddress("example.com".to_string(), 80);
+            let (mut app, local) = local_connection().await;
+            let relay = client.handle_tcp_connection(&local, target.clone());
+            let exchange = async {
+                app.write_all(b"hello").await?;
+                app.shutdown(Shutdown::Write)?;
+                let mut reply = Vec::new();
+                app.read_to_end(&mut reply).await?;
+                Ok::<_, Error>(reply)
+            };
+            let (ret, reply) = relay.join(exchange).await;
+            assert!(ret.is_ok(), "{:?}", ret);
+            assert_eq!(reply.unwrap(), b"hello");
+            assert_eq!(server.await.unwrap(), target);
+        });
+    }
+
+    #[test]
+    fn test_relay_upstream_reset() {
+        use async_std::net::TcpListener;
+        use std::os::unix::io::AsRawFd;
+
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let cfg = mock_server_config(listener.local_addr().unwrap());
+            // Reset the connection as soon as it is accepted.
+            task::spawn(async move {
+                let (conn, _) = listener.accept().await.unwrap();
+                let linger = libc::linger {
+                    l_onoff: 1,
+                    l_linger: 0,
+                };
+                let ret = unsafe {
+                    libc::setsockopt(
+                        conn.as_raw_fd(),
+                        libc::SOL_SOCKET,
+                        libc::SO_LINGER,
+                        &linger as *const libc::linger as *const libc::c_void,
+                        std::mem::size_of::<libc::linger>() as libc::socklen_t,
+                    )
+                };
+                assert_eq!(ret, 0);
+            });
+
+            let client =
+                SSClient::without_idle_connections(cfg, ("127.0.0.1".to_string(), 53), 1024)
+                    .await
+                    .unwrap();
+            let target = Address::DomainNameAddress("example.com".to_string(), 80);
+            let (_app, local) = local_connection().await;
+            let err = client
+                .handle_tcp_connection(&local, target)
+                .await
+                .unwrap_err();
+            // `RuledClient` resets the local connection on this error.
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+        });
+    }
 }
@@ -0,0 +1,166 @@
+use std::io::Result;
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_std::task;
+use config::ServerConfig;
+use tracing::{error, info};
+
+/// How often the plugin process is checked and restarted after it exits
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A running SIP003 plugin. Shadowsocks traffic is sent to `local_addr`, the plugin
+/// forwards it to the server. The process is restarted when it exits and killed on drop.
+pub(crate) struct Plugin {
+    local_addr: SocketAddr,
+    process: Arc<Mutex<Option<Child>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Plugin {
+    /// Start the plugin of `srv_cfg` listening on a free local port.
+    pub(crate) fn start(plugin: &str, srv_cfg: &ServerConfig) -> Result<Plugin> {
+        let local_addr = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let mut command = Command::new(plugin);
+        command
+            .env("SS_REMOTE_HOST", srv_cfg.addr().host())
+            .env("SS_REMOTE_PORT", srv_cfg.addr().port().to_string())
+            .env("SS_LOCAL_HOST", local_addr.ip().to_string())
+            .env("SS_LOCAL_PORT", local_addr.port().to_string());
+        if let Some(opts) = srv_cfg.plugin_opts() {
+            command.env("SS_PLUGIN_OPTIONS", opts);
+        }
+
+        let process = Arc::new(Mutex::new(spawn(&mut command, srv_cfg.name())));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let process_clone = process.clone();
+        let stopped_clone = stopped.clone();
+        let name = srv_cfg.name().to_string();
+        let _ = task::spawn(async move {
+            loop {
+                task::sleep(WATCH_INTERVAL).await;
+                let mut process = process_clone.lock().unwrap();
+                // Checked with the lock held, so no process is started after `drop`.
+                if stopped_clone.load(Ordering::SeqCst) {
+                    break;
+                }
+                let exited = match process.as_mut().map(|p| p.try_wait()) {
+                    Some(Ok(Some(status))) => {
+                        error!(server = %name, status = %status, "plugin exited, restart it");
+                        true
+                    }
+                    Some(Ok(None)) => false,
+                    Some(Err(e)) => {
+                        error!(server = %name, error = ?e, "check plugin status");
+                        false
+                    }
+                    None => true,
+                };
+                if exited {
+                    *process = spawn(&mut command, &name);
+                }
+            }
+        });
+
+        Ok(Plugin {
+            local_addr,
+            process,
+            stopped,
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+fn spawn(command: &mut Command, server: &str) -> Option<Child> {
+    match command.spawn() {
+        Ok(child) => {
+            info!(server, pid = child.id(), "start plugin");
+            Some(child)
+        }
+        Err(e) => {
+            error!(server, error = ?e, "start plugin");
+            None
+        }
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(mut process) = self.process.lock().unwrap().take() {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::ServerAddr;
+    use crypto::CipherType;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn test_plugin_restart() {
+        let dir = std::env::temp_dir().join(format!("seeker-plugin-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("output");
+        let plugin = dir.join("dummy-plugin");
+        // Record the environment and exit immediately, so it is restarted.
+        fs::write(
+            &plugin,
+            format!(
+                "#!/bin/sh\necho \"$SS_REMOTE_HOST:$SS_REMOTE_PORT $SS_LOCAL_HOST:$SS_LOCAL_PORT $SS_PLUGIN_OPTIONS\" >> {}\n",
+                output.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&plugin, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut srv_cfg = ServerConfig::new(
+            "servername".to_string(),
+            ServerAddr::DomainName("example.com".to_string(), 8388),
+            "pass".to_string(),
+            CipherType::ChaCha20Ietf,
+            Duration::from_secs(3),
+            Duration::from_secs(3),
+            Duration::from_secs(3),
+            10,
+        );
+        srv_cfg.set_plugin(
+            plugin.to_string_lossy().to_string(),
+            Some("obfs=http".to_string()),
+        );
+
+        let plugin = Plugin::start(srv_cfg.plugin().unwrap(), &srv_cfg).unwrap();
+        // Wait until the plugin has been started and restarted.
+        let deadline = Instant::now() + WATCH_INTERVAL * 10;
+        let content = loop {
+            let content = fs::read_to_string(&output).unwrap_or_default();
+            if content.lines().count() >= 2 || Instant::now() > deadline {
+                break content;
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
+        let local_addr = plugin.local_addr();
+        drop(plugin);
+
+        let lines = content.lines().collect::<Vec<_>>();
+        assert!(lines.len() >= 2, "{}", content);
+        assert_eq!(
+            lines[0],
+            format!("example.com:8388 {} obfs=http", local_addr)
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,179 @@
+//! Replay protection for AEAD salts
+//!
+//! Every salt received from servers is recorded in a ping-pong bloom filter, a pair of bloom
+//! filters where the newer one takes new salts and the older one is cleared and swapped in once
+//! the newer one is full. A salt seen before means someone on the path replays an earlier
+//! response.
+//!
+//! UDP packets of AEAD 2022 ciphers have no salt, they carry a packet id counting up in each
+//! server session instead. `PacketIdFilter` is a sliding window over the latest packet ids of a
+//! server session, as required by SIP022.
+
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Mutex;
+
+use bloomfilter::Bloom;
+use lazy_static::lazy_static;
+
+/// Salts remembered by each filter, so 10,000 to 20,000 of the latest salts are checked
+const ENTRIES_PER_FILTER: usize = 10_000;
+const FALSE_POSITIVE_RATE: f64 = 1e-15;
+
+lazy_static! {
+    static ref SALT_FILTER: Mutex<PingPongBloom> =
+        Mutex::new(PingPongBloom::new(ENTRIES_PER_FILTER));
+}
+
+struct PingPongBloom {
+    blooms: [Bloom<[u8]>; 2],
+    counts: [usize; 2],
+    current: usize,
+    capacity: usize,
+}
+
+impl PingPongBloom {
+    fn new(capacity: usize) -> PingPongBloom {
+        PingPongBloom {
+            blooms: [
+                Bloom::new_for_fp_rate(capacity, FALSE_POSITIVE_RATE),
+                Bloom::new_for_fp_rate(capacity, FALSE_POSITIVE_RATE),
+            ],
+            counts: [0, 0],
+            current: 0,
+            capacity,
+        }
+    }
+
+    /// Record `item`, returns whether it has been recorded before.
+    fn check_and_set(&mut self, item: &[u8]) -> bool {
+        if self.blooms.iter().any(|bloom| bloom.check(item)) {
+            return true;
+        }
+        if self.counts[self.current] >= self.capacity {
+            self.current = 1 - self.current;
+            self.blooms[self.current].clear();
+            self.counts[self.current] = 0;
+        }
+        self.blooms[self.current].set(item);
+        self.counts[self.current] += 1;
+        false
+    }
+}
+
+/// Record a salt received from a server, fails if the salt has been seen before.
+pub(crate) fn check_salt(salt: &[u8]) -> Result<()> {
+    if SALT_FILTER.lock().unwrap().check_and_set(salt) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "repeated salt, the data may be replayed",
+        ));
+    }
+    Ok(())
+}
+
+/// Packet ids remembered behind the highest one received
+const PACKET_ID_WINDOW: u64 = 1024;
+const PACKET_ID_WINDOW_WORDS: usize = (PACKET_ID_WINDOW / 64) as usize;
+
+/// Packet ids received in one server session
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PacketIdFilter {
+    highest: Option<u64>,
+    /// Bit `id % PACKET_ID_WINDOW` is set if `id` in the window has been received
+    window: [u64; PACKET_ID_WINDOW_WORDS],
+}
+
+impl PacketIdFilter {
+    /// Record `id`, returns whether it has been recorded before or is too old to tell.
+    pub(crate) fn check_and_set(&mut self, id: u64) -> bool {
+        match self.highest {
+            Some(highest) if id <= highest => {
+                if highest - id >= PACKET_ID_WINDOW || self.is_set(id) {
+                    return true;
+                }
+            }
+            Some(highest) => {
+                // Ids skipped over move into the window unreceived.
+                if id - highest >= PACKET_ID_WINDOW {
+                    self.window = [0; PACKET_ID_WINDOW_WORDS];
+                } else {
+                    for skipped in highest + 1..id {
+                        self.clear(skipped);
+                    }
+                }
+                self.highest = Some(id);
+            }
+            None => self.highest = Some(id),
+        }
+        self.set(id);
+        false
+    }
+
+    fn position(id: u64) -> (usize, u64) {
+        let bit = id % PACKET_ID_WINDOW;
+        ((bit / 64) as usize, 1 << (bit % 64))
+    }
+
+    fn is_set(&self, id: u64) -> bool {
+        let (word, mask) = Self::position(id);
+        self.window[word] & mask != 0
+    }
+
+    fn set(&mut self, id: u64) {
+        let (word, mask) = Self::position(id);
+        self.window[word] |= mask;
+    }
+
+    fn clear(&mut self, id: u64) {
+        let (word, mask) = Self::position(id);
+        self.window[word] &= !mask;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ping_pong_bloom() {
+        let mut filter = PingPongBloom::new(2);
+        assert!(!filter.check_and_set(b"1"));
+        assert!(filter.check_and_set(b"1"));
+        assert!(!filter.check_and_set(b"2"));
+        // The first filter is full, "3" goes to the second one.
+        assert!(!filter.check_and_set(b"3"));
+        assert!(filter.check_and_set(b"1"));
+        assert!(!filter.check_and_set(b"4"));
+        // Both are full, the first one is cleared for "5".
+        assert!(!filter.check_and_set(b"5"));
+        assert!(!filter.check_and_set(b"1"));
+        assert!(filter.check_and_set(b"3"));
+    }
+
+    #[test]
+    fn test_packet_id_filter() {
+        let mut filter = PacketIdFilter::default();
+        assert!(!filter.check_and_set(5));
+        assert!(filter.check_and_set(5));
+        // Packets may arrive out of order within the window.
+        assert!(!filter.check_and_set(3));
+        assert!(filter.check_and_set(3));
+        // 4 falls out of the window, though it has never been received.
+        assert!(!filter.check_and_set(4 + PACKET_ID_WINDOW));
+        assert!(filter.check_and_set(4));
+        assert!(filter.check_and_set(5));
+        assert!(!filter.check_and_set(7));
+        // Jumping over the whole window forgets every packet in it.
+        assert!(!filter.check_and_set(10 * PACKET_ID_WINDOW));
+        assert!(filter.check_and_set(7));
+        assert!(!filter.check_and_set(9 * PACKET_ID_WINDOW + 1));
+        assert!(!filter.check_and_set(u64::MAX));
+        assert!(filter.check_and_set(u64::MAX));
+    }
+
+    #[test]
+    fn test_check_salt() {
+        assert!(check_salt(b"received salt").is_ok());
+        assert!(check_salt(b"received salt").is_err());
+    }
+}
@@ -35,22 +35,50 @@ pub(crate) fn aead_encrypted_write(
     Ok(output_length)
 }
 
-pub(crate) async fn aead_decrypted_read<T: Read + Unpin>(
+/// Fill `buf` like `read_exact`, but return false if `src` ends before the first byte, the
+/// clean end of a stream between two chunks.
+pub(crate) async fn read_exact_or_eof<T: Read + Unpin>(
+    src: &mut T,
+    buf: &mut [u8],
+) -> Result<bool> {
+    let size = src.read(buf).await?;
+    if size == 0 {
+        return Ok(false);
+    }
+    src.read_exact(&mut buf[size..]).await?;
+    Ok(true)
+}
+
+/// Read and decrypt the length of the next chunk, `None` if the stream ends before it
+pub(crate) async fn aead_decrypted_read_len<T: Read + Unpin>(
     cipher: &mut BoxAeadDecryptor,
-    mut src: T,
+    src: &mut T,
     tmp_buf: &mut [u8],
-    output: &mut [u8],
     t: CipherType,
-) -> Result<usize> {
+) -> Result<Option<usize>> {
     let tag_size = t.tag_size();
-    src.read_exact(&mut tmp_buf[..2 + tag_size]).await?;
+    if !read_exact_or_eof(src, &mut tmp_buf[..2 + tag_size]).await? {
+        return Ok(None);
+    }
     let mut len_buf = [0u8; 2];
     cipher.decrypt(&tmp_buf[..2 + tag_size], &mut len_buf)?;
     let len = BigEndian::read_u16(&len_buf) as usize;
     if len > MAX_PACKET_SIZE {
         return Err(ErrorKind::InvalidData.into());
     }
+    Ok(Some(len))
+}
 
+/// Read and decrypt a chunk of `len` bytes, the length returned by `aead_decrypted_read_len`
+pub(crate) async fn aead_decrypted_read_data<T: Read + Unpin>(
+    cipher: &mut BoxAeadDecryptor,
+    src: &mut T,
+    tmp_buf: &mut [u8],
+    output: &mut [u8],
+    len: usize,
+    t: CipherType,
+) -> Result<usize> {
+    let tag_size = t.tag_size();
     src.read_exact(&mut tmp_buf[..len + tag_size]).await?;
     cipher.decrypt(&tmp_buf[..len + tag_size], &mut output[..len])?;
     Ok(len)
@@ -78,16 +106,37 @@ mod tests {
             aead_encrypted_write(&mut encrypter_cipher, &buf[..], &mut dst, cipher_type).unwrap();
 
         task::block_on(async {
-            let s = aead_decrypted_read(
+            let mut src = &dst[..size];
+            let len =
+                aead_decrypted_read_len(&mut decrypter_cipher, &mut src, &mut tmp_buf, cipher_type)
+                    .await
+                    .unwrap()
+                    .expect("chunk length");
+            let s = aead_decrypted_read_data(
                 &mut decrypter_cipher,
-                &dst[..size],
+                &mut src,
                 &mut tmp_buf,
                 &mut output,
+                len,
                 cipher_type,
             )
             .await
             .unwrap();
             assert_eq!(&output[..s], buf);
+
+            // A clean end between chunks, and a stream cut inside one.
+            let mut src = &dst[..0];
+            let len =
+                aead_decrypted_read_len(&mut decrypter_cipher, &mut src, &mut tmp_buf, cipher_type)
+                    .await
+                    .unwrap();
+            assert_eq!(len, None);
+            let mut src = &dst[..1];
+            let err =
+                aead_decrypted_read_len(&mut decrypter_cipher, &mut src, &mut tmp_buf, cipher_type)
+                    .await
+                    .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
         })
     }
 }
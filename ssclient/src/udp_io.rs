@@ -19,22 +19,101 @@
 //! | Fixed  | Variable  |   Fixed   |
 //! +--------+-----------+-----------+
 //! ```
+//!
+//! Payload with AEAD 2022 cipher (SIP022). The separate header holds the session id and the
+//! packet id, and is encrypted with AES using the pre-shared key. Its last 12 bytes are the nonce
+//! of the body, which is sealed with the subkey of the session.
+//!
+//! ```plain
+//! +------------------+-----------------------------------------------------+-----------+
+//! | Separate header  |  *Body*                                             |  Body_TAG |
+//! +------------------+-----------------------------------------------------+-----------+
+//! | 16               | type, timestamp, [client session id], padding, Data |   Fixed   |
+//! +------------------+-----------------------------------------------------+-----------+
+//! ```
+//!
+//! `2022-blake3-chacha20-poly1305` seals the separate header and the body together with
+//! XChaCha20-Poly1305 and the pre-shared key, behind a random 24 bytes nonce.
 
 use std::io::{Error, ErrorKind, Result};
 
+use byteorder::{BigEndian, ByteOrder};
 use bytes::{BufMut, BytesMut};
+use crypto::aead::{aes_decrypt_block, aes_encrypt_block, make_skey};
 use crypto::{CipherCategory, CipherType, CryptoMode};
+use rand::Rng;
+
+use crate::aead_2022::{check_header, timestamp, HEADER_TYPE_CLIENT};
+use crate::replay_filter::{self, PacketIdFilter};
+
+const SEPARATE_HEADER_SIZE: usize = 16;
+const XCHACHA20_NONCE_SIZE: usize = 24;
+/// Server sessions whose packets are accepted, the server starts a new one when it restarts
+const MAX_SERVER_SESSIONS: usize = 2;
+
+/// Client side state of one UDP session, only used by AEAD 2022 ciphers
+#[derive(Clone, Debug)]
+pub struct UdpSession {
+    client_session_id: u64,
+    packet_id: u64,
+    /// Packet ids received in the latest server sessions, the newest one last
+    server_sessions: Vec<(u64, PacketIdFilter)>,
+}
+
+impl UdpSession {
+    pub fn new() -> UdpSession {
+        UdpSession {
+            client_session_id: rand::random(),
+            packet_id: 0,
+            server_sessions: Vec::with_capacity(MAX_SERVER_SESSIONS),
+        }
+    }
+
+    /// Record a packet received from the server, fails if it is replayed or too old.
+    fn check_server_packet(&mut self, server_session_id: u64, packet_id: u64) -> Result<()> {
+        let index = match self
+            .server_sessions
+            .iter()
+            .position(|(id, _)| *id == server_session_id)
+        {
+            Some(index) => index,
+            None => {
+                if self.server_sessions.len() == MAX_SERVER_SESSIONS {
+                    self.server_sessions.remove(0);
+                }
+                self.server_sessions
+                    .push((server_session_id, PacketIdFilter::default()));
+                self.server_sessions.len() - 1
+            }
+        };
+        if self.server_sessions[index].1.check_and_set(packet_id) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "repeated packet id, the udp packet may be replayed",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for UdpSession {
+    fn default() -> Self {
+        UdpSession::new()
+    }
+}
 
 /// Encrypt payload into ShadowSocks UDP encrypted packet
 pub fn encrypt_payload(
     t: CipherType,
     key: &[u8],
+    session: &mut UdpSession,
     payload: &[u8],
     output: &mut BytesMut,
 ) -> Result<usize> {
     match t.category() {
         CipherCategory::Stream => encrypt_payload_stream(t, key, payload, output),
         CipherCategory::Aead => encrypt_payload_aead(t, key, payload, output),
+        CipherCategory::Aead2022 => encrypt_payload_aead_2022(t, key, session, payload, output),
     }
 }
 
@@ -42,12 +121,14 @@ pub fn encrypt_payload(
 pub fn decrypt_payload(
     t: CipherType,
     key: &[u8],
+    session: &mut UdpSession,
     payload: &[u8],
     output: &mut BytesMut,
 ) -> Result<usize> {
     match t.category() {
         CipherCategory::Stream => decrypt_payload_stream(t, key, payload, output),
         CipherCategory::Aead => decrypt_payload_aead(t, key, payload, output),
+        CipherCategory::Aead2022 => decrypt_payload_aead_2022(t, key, session, payload, output),
     }
 }
 
@@ -95,10 +176,117 @@ fn decrypt_payload_aead(
 
     output.resize(data_length, 0);
     cipher.decrypt(data, &mut output[..data_length])?;
+    // Checked after decryption, so garbage packets can't fill the filter.
+    replay_filter::check_salt(salt)?;
 
     Ok(data_length)
 }
 
+fn encrypt_payload_aead_2022(
+    t: CipherType,
+    key: &[u8],
+    session: &mut UdpSession,
+    payload: &[u8],
+    output: &mut BytesMut,
+) -> Result<usize> {
+    let mut header = [0; SEPARATE_HEADER_SIZE];
+    BigEndian::write_u64(&mut header[..8], session.client_session_id);
+    BigEndian::write_u64(&mut header[8..], session.packet_id);
+    session.packet_id += 1;
+
+    let mut body = BytesMut::with_capacity(SEPARATE_HEADER_SIZE + 11 + payload.len());
+    if t == CipherType::Aead2022Blake3ChaCha20Poly1305 {
+        body.put_slice(&header);
+    }
+    body.put_u8(HEADER_TYPE_CLIENT);
+    body.put_u64_be(timestamp());
+    // no padding
+    body.put_u16_be(0);
+    body.put_slice(payload);
+
+    let tag_size = t.tag_size();
+    let mut cipher = if t == CipherType::Aead2022Blake3ChaCha20Poly1305 {
+        let mut nonce = [0; XCHACHA20_NONCE_SIZE];
+        rand::thread_rng().fill(&mut nonce);
+        output.put_slice(&nonce);
+        crypto::new_aead_encryptor_with_nonce(t, key, &nonce)?
+    } else {
+        let skey = make_skey(t, key, &header[..8]);
+        let cipher = crypto::new_aead_encryptor_with_nonce(t, &skey, &header[4..])?;
+        aes_encrypt_block(key, &mut header);
+        output.put_slice(&header);
+        cipher
+    };
+
+    let prefix_len = output.len();
+    output.resize(prefix_len + body.len() + tag_size, 0);
+    cipher.encrypt(&body, &mut output[prefix_len..]);
+
+    Ok(output.len())
+}
+
+fn decrypt_payload_aead_2022(
+    t: CipherType,
+    key: &[u8],
+    session: &mut UdpSession,
+    payload: &[u8],
+    output: &mut BytesMut,
+) -> Result<usize> {
+    let too_short = || Error::new(ErrorKind::UnexpectedEof, "udp packet too short");
+    let tag_size = t.tag_size();
+
+    let mut header = [0; SEPARATE_HEADER_SIZE];
+    let mut body = if t == CipherType::Aead2022Blake3ChaCha20Poly1305 {
+        if payload.len() < XCHACHA20_NONCE_SIZE + SEPARATE_HEADER_SIZE + tag_size {
+            return Err(too_short());
+        }
+        let (nonce, data) = payload.split_at(XCHACHA20_NONCE_SIZE);
+        let mut cipher = crypto::new_aead_decryptor_with_nonce(t, key, nonce)?;
+        let mut body = vec![0; data.len() - tag_size];
+        cipher.decrypt(data, &mut body)?;
+        header.copy_from_slice(&body[..SEPARATE_HEADER_SIZE]);
+        body.split_off(SEPARATE_HEADER_SIZE)
+    } else {
+        if payload.len() < SEPARATE_HEADER_SIZE + tag_size {
+            return Err(too_short());
+        }
+        header.copy_from_slice(&payload[..SEPARATE_HEADER_SIZE]);
+        aes_decrypt_block(key, &mut header);
+        let skey = make_skey(t, key, &header[..8]);
+        let mut cipher = crypto::new_aead_decryptor_with_nonce(t, &skey, &header[4..])?;
+        let data = &payload[SEPARATE_HEADER_SIZE..];
+        let mut body = vec![0; data.len() - tag_size];
+        cipher.decrypt(data, &mut body)?;
+        body
+    };
+
+    // type, timestamp, client session id and padding length
+    if body.len() < 1 + 8 + 8 + 2 {
+        return Err(too_short());
+    }
+    check_header(body[0], BigEndian::read_u64(&body[1..9]))?;
+    if BigEndian::read_u64(&body[9..17]) != session.client_session_id {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "client session id in udp packet mismatch",
+        ));
+    }
+    let data_start = 19 + BigEndian::read_u16(&body[17..19]) as usize;
+    if body.len() < data_start {
+        return Err(too_short());
+    }
+    // Checked once the packet is known to come from the server, so garbage packets can't move
+    // the window.
+    session.check_server_packet(
+        BigEndian::read_u64(&header[..8]),
+        BigEndian::read_u64(&header[8..]),
+    )?;
+    let data = body.split_off(data_start);
+    output.put_slice(&data);
+
+    Ok(data.len())
+}
+
 fn encrypt_payload_stream(
     t: CipherType,
     key: &[u8],
@@ -150,6 +338,129 @@ mod tests {
         assert_eq!(&output2[..size2], payload);
     }
 
+    #[test]
+    fn test_decrypt_replayed_payload_aead() {
+        let cipher_type = CipherType::Aes256Gcm;
+        let key = cipher_type.bytes_to_key(b"key");
+        let mut output = BytesMut::with_capacity(MAX_PACKET_SIZE);
+        let mut output2 = BytesMut::with_capacity(MAX_PACKET_SIZE);
+        let size = encrypt_payload_aead(cipher_type, &key, b"payload", &mut output).unwrap();
+
+        // A packet failing to decrypt doesn't record its salt.
+        let mut corrupted = output[..size].to_vec();
+        corrupted[size - 1] ^= 1;
+        assert!(decrypt_payload_aead(cipher_type, &key, &corrupted, &mut output2).is_err());
+        assert!(decrypt_payload_aead(cipher_type, &key, &output[..size], &mut output2).is_ok());
+        // A replayed packet is rejected.
+        assert!(decrypt_payload_aead(cipher_type, &key, &output[..size], &mut output2).is_err());
+    }
+
+    /// Turn a request packet into the response a server would send back
+    fn server_response(t: CipherType, key: &[u8], request: &[u8]) -> Vec<u8> {
+        let is_chacha = t == CipherType::Aead2022Blake3ChaCha20Poly1305;
+        let tag_size = t.tag_size();
+        let (prefix_size, header) = if is_chacha {
+            (XCHACHA20_NONCE_SIZE, vec![0; SEPARATE_HEADER_SIZE])
+        } else {
+            let mut header = request[..SEPARATE_HEADER_SIZE].to_vec();
+            aes_decrypt_block(key, &mut header);
+            (SEPARATE_HEADER_SIZE, header)
+        };
+        let data = &request[prefix_size..];
+        let mut body = vec![0; data.len() - tag_size];
+        let mut decryptor = if is_chacha {
+            crypto::new_aead_decryptor_with_nonce(t, key, &request[..prefix_size]).unwrap()
+        } else {
+            let skey = make_skey(t, key, &header[..8]);
+            crypto::new_aead_decryptor_with_nonce(t, &skey, &header[4..]).unwrap()
+        };
+        decryptor.decrypt(data, &mut body).unwrap();
+        let (client_header, body) = if is_chacha {
+            let (header, body) = body.split_at(SEPARATE_HEADER_SIZE);
+            (header.to_vec(), body.to_vec())
+        } else {
+            (header, body)
+        };
+        assert_eq!(body[0], HEADER_TYPE_CLIENT);
+        let address_and_payload = &body[11..];
+
+        let server_header = [9; SEPARATE_HEADER_SIZE];
+        let mut response = Vec::new();
+        if is_chacha {
+            response.extend_from_slice(&server_header);
+        }
+        response.push(crate::aead_2022::HEADER_TYPE_SERVER);
+        response.extend_from_slice(&timestamp().to_be_bytes());
+        response.extend_from_slice(&client_header[..8]);
+        response.extend_from_slice(&3u16.to_be_bytes());
+        response.extend_from_slice(&[0; 3]);
+        response.extend_from_slice(address_and_payload);
+
+        let (prefix, mut encryptor) = if is_chacha {
+            let nonce = [5; XCHACHA20_NONCE_SIZE];
+            let encryptor = crypto::new_aead_encryptor_with_nonce(t, key, &nonce).unwrap();
+            (nonce.to_vec(), encryptor)
+        } else {
+            let skey = make_skey(t, key, &server_header[..8]);
+            let encryptor =
+                crypto::new_aead_encryptor_with_nonce(t, &skey, &server_header[4..]).unwrap();
+            let mut header = server_header;
+            aes_encrypt_block(key, &mut header);
+            (header.to_vec(), encryptor)
+        };
+        let mut packet = prefix;
+        let prefix_len = packet.len();
+        packet.resize(prefix_len + response.len() + tag_size, 0);
+        encryptor.encrypt(&response, &mut packet[prefix_len..]);
+        packet
+    }
+
+    #[test]
+    fn test_encrypt_and_decrypt_payload_aead_2022() {
+        for &t in &[
+            CipherType::Aead2022Blake3Aes128Gcm,
+            CipherType::Aead2022Blake3Aes256Gcm,
+            CipherType::Aead2022Blake3ChaCha20Poly1305,
+        ] {
+            let key = t.gen_salt();
+            let payload = b"address and payload";
+            let mut session = UdpSession::new();
+            let mut request = BytesMut::with_capacity(MAX_PACKET_SIZE);
+            let size = encrypt_payload(t, &key, &mut session, payload, &mut request).unwrap();
+            assert_eq!(session.packet_id, 1);
+
+            let response = server_response(t, &key, &request[..size]);
+            let mut output = BytesMut::with_capacity(MAX_PACKET_SIZE);
+            let size = decrypt_payload(t, &key, &mut session, &response, &mut output).unwrap();
+            assert_eq!(&output[..size], payload);
+
+            let mut other_session = UdpSession::new();
+            output.clear();
+            assert!(decrypt_payload(t, &key, &mut other_session, &response, &mut output).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decrypt_replayed_payload_aead_2022() {
+        for &t in &[
+            CipherType::Aead2022Blake3Aes128Gcm,
+            CipherType::Aead2022Blake3Aes256Gcm,
+            CipherType::Aead2022Blake3ChaCha20Poly1305,
+        ] {
+            let key = t.gen_salt();
+            let mut session = UdpSession::new();
+            let mut request = BytesMut::with_capacity(MAX_PACKET_SIZE);
+            let size = encrypt_payload(t, &key, &mut session, b"payload", &mut request).unwrap();
+            let response = server_response(t, &key, &request[..size]);
+
+            let mut output = BytesMut::with_capacity(MAX_PACKET_SIZE);
+            assert!(decrypt_payload(t, &key, &mut session, &response, &mut output).is_ok());
+            output.clear();
+            let err = decrypt_payload(t, &key, &mut session, &response, &mut output).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
     #[test]
     fn test_encrypt_and_decrypt_payload_stream() {
         let cipher_type = CipherType::ChaCha20Ietf;
@@ -1,7 +1,7 @@
 mod net;
 mod proc;
 
-pub use net::{setup_ip, DNSSetup, IpForward};
+pub use net::{restore_system_state, setup_ip, setup_ipv6, DNSSetup, IpForward};
 
-pub use proc::sys::{list_system_proc_socks, list_user_proc_socks};
-pub use proc::SocketInfo;
+pub use proc::sys::{find_socket_owner, list_system_proc_socks, list_user_proc_socks};
+pub use proc::{ProcessInfo, SocketInfo};
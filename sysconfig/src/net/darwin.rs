@@ -1,11 +1,16 @@
-use crate::net::run_cmd;
-use tracing::info;
+use crate::net::{recovery, run_cmd};
+use std::io;
+use std::net::IpAddr;
+use tracing::{error, info};
 
 pub struct DNSSetup;
 
 impl DNSSetup {
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
+        let output = run_cmd("networksetup", &["-getdnsservers", "Wi-Fi"]);
+        recovery::backup(recovery::DNS, dns_servers(&output).as_bytes())
+            .expect("back up dns servers");
         let _ = run_cmd("networksetup", &["-setdnsservers", "Wi-Fi", "127.0.0.1"]);
         DNSSetup
     }
@@ -14,11 +19,64 @@ impl DNSSetup {
 impl Drop for DNSSetup {
     fn drop(&mut self) {
         info!("clear dns");
-        let _ = run_cmd("networksetup", &["-setdnsservers", "Wi-Fi", "empty"]);
+        if let Err(e) = restore_dns() {
+            error!(error = ?e, "restore dns servers");
+        }
     }
 }
 
+/// Servers listed by `networksetup -getdnsservers`, or `empty` to clear them if none is set, as
+/// taken by `networksetup -setdnsservers`.
+fn dns_servers(output: &str) -> String {
+    let servers: Vec<&str> = output
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.parse::<IpAddr>().is_ok())
+        .collect();
+    if servers.is_empty() {
+        "empty".to_string()
+    } else {
+        servers.join(" ")
+    }
+}
+
+pub(crate) fn restore_dns() -> io::Result<()> {
+    recovery::restore(recovery::DNS, |original| {
+        let servers = String::from_utf8_lossy(original);
+        let mut args = vec!["-setdnsservers", "Wi-Fi"];
+        args.extend(servers.split_whitespace());
+        let _ = run_cmd("networksetup", &args);
+        Ok(())
+    })
+}
+
 pub fn setup_ip(tun_name: &str, ip: &str, cidr: &str) {
     let _ = run_cmd("ifconfig", &[tun_name, ip, ip]);
     let _ = run_cmd("route", &["add", cidr, ip]);
 }
+
+pub fn setup_ipv6(tun_name: &str, ip: &str, cidr: &str) {
+    let prefix_len = cidr.rsplit('/').next().unwrap_or("128");
+    let _ = run_cmd(
+        "ifconfig",
+        &[tun_name, "inet6", ip, "prefixlen", prefix_len],
+    );
+    let _ = run_cmd("route", &["add", "-inet6", cidr, "-interface", tun_name]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dns_servers() {
+        assert_eq!(
+            dns_servers("192.168.1.1\n2001:db8::1\n"),
+            "192.168.1.1 2001:db8::1"
+        );
+        assert_eq!(
+            dns_servers("There aren't any DNS Servers set on Wi-Fi.\n"),
+            "empty"
+        );
+    }
+}
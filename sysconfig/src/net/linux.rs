@@ -1,14 +1,14 @@
-use crate::net::run_cmd;
+use crate::net::{recovery, run_cmd};
 use std::fs::OpenOptions;
+use std::io;
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::process::Command;
-use tracing::info;
+use tracing::{error, info};
 
-pub struct DNSSetup {
-    original: Vec<u8>,
-}
+pub struct DNSSetup;
 
 impl DNSSetup {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         info!("setup dns");
         let mut resolv = OpenOptions::new()
@@ -23,26 +23,42 @@ impl DNSSetup {
             "original resolve.conf: {}",
             std::str::from_utf8(&buf).unwrap()
         );
+        recovery::backup(recovery::DNS, &buf).expect("back up /etc/resolv.conf");
         resolv.set_len(0).unwrap();
         resolv.seek(SeekFrom::Start(0)).unwrap();
         resolv.write_all(b"nameserver 127.0.0.1").unwrap();
 
-        DNSSetup { original: buf }
+        DNSSetup
     }
 }
 
 impl Drop for DNSSetup {
     fn drop(&mut self) {
         info!("clear dns");
+        if let Err(e) = restore_dns() {
+            error!(error = ?e, "restore /etc/resolv.conf");
+        }
+    }
+}
+
+pub(crate) fn restore_dns() -> io::Result<()> {
+    recovery::restore(recovery::DNS, |original| {
         let mut resolv = OpenOptions::new()
             .write(true)
-            .open("/etc/resolv.conf")
-            .unwrap();
-        resolv.write_all(&self.original).unwrap();
-    }
+            .truncate(true)
+            .open("/etc/resolv.conf")?;
+        resolv.write_all(original)
+    })
 }
 
 pub fn setup_ip(tun_name: &str, ip: &str, _cidr: &str) {
     let _ = run_cmd("ip", &["addr", "add", ip, "dev", tun_name]);
     let _ = run_cmd("ip", &["link", "set", tun_name, "up"]);
 }
+
+pub fn setup_ipv6(tun_name: &str, ip: &str, cidr: &str) {
+    let prefix_len = cidr.rsplit('/').next().unwrap_or("128");
+    let ip = format!("{}/{}", ip, prefix_len);
+    let _ = run_cmd("ip", &["-6", "addr", "add", &ip, "dev", tun_name]);
+    let _ = run_cmd("ip", &["link", "set", tun_name, "up"]);
+}
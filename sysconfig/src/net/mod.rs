@@ -1,5 +1,8 @@
+use std::io;
 use std::process::Command;
-use tracing::debug;
+use tracing::{debug, error};
+
+mod recovery;
 
 fn run_cmd(cmd: &str, args: &[&str]) -> String {
     let output = Command::new(cmd)
@@ -30,32 +33,44 @@ const IP_FORWARDING_KEY: &str = "net.inet.ip.forwarding";
 #[cfg(target_os = "linux")]
 const IP_FORWARDING_KEY: &str = "net.ipv4.ip_forward";
 
-pub struct IpForward {
-    original_option: usize,
-}
+pub struct IpForward;
 
 impl IpForward {
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         let output = run_cmd("sysctl", &["-n", IP_FORWARDING_KEY]);
         let option = output.trim().parse::<usize>().unwrap();
+        recovery::backup(recovery::IP_FORWARD, option.to_string().as_bytes())
+            .expect("back up ip forwarding");
         let _ = run_cmd("sysctl", &["-w", &format!("{}={}", IP_FORWARDING_KEY, 1)]);
-        IpForward {
-            original_option: option,
-        }
+        IpForward
     }
 }
 
 impl Drop for IpForward {
     fn drop(&mut self) {
+        if let Err(e) = restore_ip_forward() {
+            error!(error = ?e, "restore ip forwarding");
+        }
+    }
+}
+
+fn restore_ip_forward() -> io::Result<()> {
+    recovery::restore(recovery::IP_FORWARD, |original| {
+        let option = String::from_utf8_lossy(original);
         let _ = run_cmd(
             "sysctl",
-            &[
-                "-w",
-                &format!("{}={}", IP_FORWARDING_KEY, self.original_option),
-            ],
+            &["-w", &format!("{}={}", IP_FORWARDING_KEY, option.trim())],
         );
-    }
+        Ok(())
+    })
+}
+
+/// Restore dns and ip forwarding changed by a run that did not restore them, eg. one that
+/// crashed. Does nothing if they are not changed.
+pub fn restore_system_state() -> io::Result<()> {
+    sys::restore_dns()?;
+    restore_ip_forward()
 }
 
 #[cfg(any(target_os = "macos", target_os = "ios"))]
@@ -66,4 +81,4 @@ pub mod sys;
 #[path = "linux.rs"]
 pub mod sys;
 
-pub use sys::{setup_ip, DNSSetup};
+pub use sys::{setup_ip, setup_ipv6, DNSSetup};
@@ -0,0 +1,77 @@
+//! Backups of the system settings changed by seeker.
+//!
+//! A setting is backed up before it is changed and the backup is removed once the setting is
+//! restored. Backups left behind mean the last run crashed, they are restored on the next start.
+use std::fs;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// Kept across reboots, as `/etc/resolv.conf` and the dns of network services are.
+const RECOVERY_DIR: &str = "/var/lib/seeker/recovery";
+
+pub(crate) const DNS: &str = "dns";
+pub(crate) const IP_FORWARD: &str = "ip_forward";
+
+fn backup_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(name)
+}
+
+fn backup_in(dir: &Path, name: &str, original: &[u8]) -> Result<()> {
+    let path = backup_path(dir, name);
+    // An existing backup is from a run that was not restored, it holds the real original.
+    if path.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(dir)?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, original)?;
+    fs::rename(tmp, path)
+}
+
+fn restore_in(dir: &Path, name: &str, restore: impl FnOnce(&[u8]) -> Result<()>) -> Result<()> {
+    let path = backup_path(dir, name);
+    let original = match fs::read(&path) {
+        Ok(original) => original,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    restore(&original)?;
+    fs::remove_file(path)
+}
+
+/// Back up the `original` value of setting `name` before changing it.
+pub(crate) fn backup(name: &str, original: &[u8]) -> Result<()> {
+    backup_in(Path::new(RECOVERY_DIR), name, original)
+}
+
+/// Restore setting `name` with `restore` if it has a backup, and remove the backup.
+pub(crate) fn restore(name: &str, restore: impl FnOnce(&[u8]) -> Result<()>) -> Result<()> {
+    restore_in(Path::new(RECOVERY_DIR), name, restore)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backup_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().join("recovery");
+
+        backup_in(&dir, DNS, b"original").unwrap();
+        // Changed again by a run after a crash, the first backup is kept.
+        backup_in(&dir, DNS, b"changed").unwrap();
+
+        let mut restored = Vec::new();
+        restore_in(&dir, DNS, |original| {
+            restored.extend_from_slice(original);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(restored, b"original");
+
+        // Nothing to restore once restored.
+        restore_in(&dir, DNS, |_| panic!("restored twice")).unwrap();
+        restore_in(&dir, IP_FORWARD, |_| panic!("never backed up")).unwrap();
+    }
+}
@@ -1,11 +1,12 @@
 #![allow(dead_code)]
-use super::SocketInfo;
+use super::{ProcessInfo, SocketInfo};
+use libproc::libproc::bsd_info::BSDInfo;
 use libproc::libproc::proc_pid::{
-    listpidinfo, listpids, pidfdinfo, InSockInfo, ListFDs, ProcFDType, ProcType, SocketFDInfo,
-    SocketInfoKind,
+    listpidinfo, listpids, name, pidfdinfo, pidinfo, InSockInfo, ListFDs, ProcFDType, ProcType,
+    SocketFDInfo, SocketInfoKind,
 };
 use std::collections::HashMap;
-use std::io::Result;
+use std::io::{Error, ErrorKind, Result};
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 
 pub fn list_system_proc_socks() -> Result<HashMap<i32, Vec<SocketInfo>>> {
@@ -33,6 +34,25 @@ pub fn list_user_proc_socks(uid: u32) -> Result<HashMap<i32, Vec<SocketInfo>>> {
     Ok(pid_sockaddr_map)
 }
 
+/// Find the process owning the tcp socket bound to `addr`.
+pub fn find_socket_owner(addr: SocketAddr) -> Result<Option<ProcessInfo>> {
+    for pid in listpids(ProcType::ProcAllPIDS, 0)? {
+        let pid = pid as i32;
+        // Sockets of processes we are not allowed to inspect are skipped.
+        let socket_infos = list_sockaddr(pid).unwrap_or_default();
+        if socket_infos.iter().any(|s| s.local == addr) {
+            let name = name(pid).map_err(|e| Error::new(ErrorKind::Other, e))?;
+            let info = pidinfo::<BSDInfo>(pid, 0).map_err(|e| Error::new(ErrorKind::Other, e))?;
+            return Ok(Some(ProcessInfo {
+                pid,
+                uid: info.pbi_uid,
+                name,
+            }));
+        }
+    }
+    Ok(None)
+}
+
 fn list_sockaddr(pid: i32) -> Result<Vec<SocketInfo>> {
     let mut addrs = vec![];
     for fd in listpidinfo::<ListFDs>(pid, 4000)? {
@@ -1,7 +1,9 @@
-use crate::SocketInfo;
+use crate::{ProcessInfo, SocketInfo};
 use procfs::FDTarget;
 use std::collections::HashMap;
-use std::io::Result;
+use std::fmt::Debug;
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
 
 pub fn list_system_proc_socks() -> Result<HashMap<i32, Vec<SocketInfo>>> {
     let all_procs = procfs::all_processes();
@@ -68,6 +70,49 @@ pub fn list_user_proc_socks(uid: u32) -> Result<HashMap<i32, Vec<SocketInfo>>> {
     Ok(socks_map)
 }
 
+/// Find the process owning the tcp or udp socket bound to `addr`.
+pub fn find_socket_owner(addr: SocketAddr) -> Result<Option<ProcessInfo>> {
+    let tcp = procfs::tcp().map_err(proc_error)?;
+    let tcp6 = procfs::tcp6().map_err(proc_error)?;
+    let udp = procfs::udp().map_err(proc_error)?;
+    let udp6 = procfs::udp6().map_err(proc_error)?;
+    let inode = tcp
+        .into_iter()
+        .chain(tcp6)
+        .map(|entry| (entry.local_address, entry.inode))
+        .chain(
+            udp.into_iter()
+                .chain(udp6)
+                .map(|entry| (entry.local_address, entry.inode)),
+        )
+        .find(|(local, _)| *local == addr)
+        .map(|(_, inode)| inode);
+    let inode = match inode {
+        Some(inode) => inode,
+        None => return Ok(None),
+    };
+
+    for process in procfs::all_processes() {
+        if let Ok(fds) = process.fd() {
+            if fds.iter().any(|fd| match fd.target {
+                FDTarget::Socket(i) => i == inode,
+                _ => false,
+            }) {
+                return Ok(Some(ProcessInfo {
+                    pid: process.pid(),
+                    uid: process.owner,
+                    name: process.stat.comm.clone(),
+                }));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn proc_error<E: Debug>(e: E) -> Error {
+    Error::new(ErrorKind::Other, format!("{:?}", e))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -87,4 +132,14 @@ mod test {
             .values()
             .any(|sockets| sockets.iter().any(|s| s.local.port() == 65532)));
     }
+
+    #[test]
+    fn test_find_socket_owner() {
+        let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let owner = find_socket_owner(socket.local_addr().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(owner.pid, std::process::id() as i32);
+        assert_eq!(owner.uid, unsafe { libc::getuid() });
+    }
 }
@@ -6,6 +6,14 @@ pub struct SocketInfo {
     pub remote: SocketAddr,
 }
 
+/// Process owning a socket
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ProcessInfo {
+    pub pid: i32,
+    pub uid: u32,
+    pub name: String,
+}
+
 #[cfg(target_os = "macos")]
 #[path = "darwin.rs"]
 pub mod sys;
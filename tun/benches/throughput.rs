@@ -0,0 +1,105 @@
+//! Throughput of many parallel tcp flows through the tun device, with the 1500 bytes socket
+//! buffers smoltcp used to be given and with the default `BufferSizes`.
+//!
+//! Creating the tun devices requires root: `sudo cargo bench -p tun`.
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use async_std::task;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+use tun::socket::TunSocket;
+use tun::{BufferSizes, Tun};
+
+const FLOWS: usize = 64;
+const BYTES_PER_FLOW: usize = 256 * 1024;
+
+/// Accept connections from the tun device and count the bytes read from them.
+fn spawn_sink(tun: &Tun, received: Arc<AtomicUsize>) {
+    task::spawn(tun.bg_send());
+    let mut stream = tun.listen();
+    task::spawn(async move {
+        while let Some(socket) = stream.next().await {
+            let mut socket = match socket.unwrap() {
+                TunSocket::Tcp(s) => s,
+                TunSocket::Udp(_) => continue,
+            };
+            let received = received.clone();
+            task::spawn(async move {
+                let mut buf = vec![0; 16 * 1024];
+                loop {
+                    let size = socket.read(&mut buf).await.unwrap();
+                    if size == 0 {
+                        break;
+                    }
+                    received.fetch_add(size, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+}
+
+/// Open `flows` connections to `10.0.<subnet>.2` and send `BYTES_PER_FLOW` on each.
+async fn run_flows(subnet: u8, flows: usize, received: &AtomicUsize) {
+    received.store(0, Ordering::Relaxed);
+    let data = Arc::new(vec![0x5a; BYTES_PER_FLOW]);
+    let mut handles = Vec::with_capacity(flows);
+    for i in 0..flows {
+        let data = data.clone();
+        handles.push(task::spawn(async move {
+            let addr: SocketAddr = (Ipv4Addr::new(10, 0, subnet, 2), 10000 + i as u16).into();
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(&data).await.unwrap();
+        }));
+    }
+    for handle in handles {
+        handle.await;
+    }
+    while received.load(Ordering::Relaxed) < flows * BYTES_PER_FLOW {
+        task::sleep(Duration::from_millis(1)).await;
+    }
+}
+
+fn bench_throughput(c: &mut Criterion) {
+    let configs = [
+        (
+            "1500 bytes buffers",
+            7,
+            BufferSizes {
+                tcp_rx: 1500,
+                tcp_tx: 1500,
+            },
+        ),
+        ("default buffers", 8, BufferSizes::default()),
+    ];
+
+    let mut group = c.benchmark_group("tun");
+    group.sample_size(10);
+    for &(name, subnet, buffer_sizes) in &configs {
+        let tun = Tun::setup(
+            format!("utun{}", subnet),
+            Ipv4Addr::new(10, 0, subnet, 1),
+            Ipv4Cidr::new(Ipv4Address::new(10, 0, subnet, 0), 24),
+            None,
+            buffer_sizes,
+            Arc::new(AtomicBool::new(false)),
+        );
+        let received = Arc::new(AtomicUsize::new(0));
+        spawn_sink(&tun, received.clone());
+
+        for &flows in &[1, 8, FLOWS] {
+            group.throughput(Throughput::Bytes((flows * BYTES_PER_FLOW) as u64));
+            group.bench_function(format!("{}, {} parallel tcp flows", name, flows), |b| {
+                b.iter(|| task::block_on(run_flows(subnet, flows, &received)))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_throughput);
+criterion_main!(benches);
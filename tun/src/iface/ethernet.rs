@@ -1,6 +1,8 @@
 // Copyright (C) 2016 whitequark@whitequark.org
 // SPDX-License-Identifier: 0BSD
 #![allow(dead_code)]
+use std::collections::HashSet;
+
 use managed::ManagedSlice;
 use smoltcp::phy::RxToken;
 use smoltcp::phy::{Device, DeviceCapabilities, TxToken};
@@ -9,8 +11,9 @@ use smoltcp::socket::{PollAt, Socket, SocketSet, UdpSocket};
 use smoltcp::socket::{SocketHandle, TcpSocket};
 use smoltcp::time::{Duration, Instant};
 use smoltcp::wire::{
-    EthernetFrame, IpAddress, IpCidr, IpEndpoint, IpProtocol, IpRepr, Ipv4Address, Ipv4Packet,
-    Ipv4Repr, PrettyPrinter, TcpControl, TcpPacket, TcpRepr, UdpPacket, UdpRepr,
+    EthernetFrame, IpAddress, IpCidr, IpEndpoint, IpProtocol, IpRepr, IpVersion, Ipv4Address,
+    Ipv4Packet, Ipv4Repr, Ipv6Packet, Ipv6Repr, PrettyPrinter, TcpControl, TcpPacket, TcpRepr,
+    UdpPacket, UdpRepr,
 };
 use smoltcp::{Error, Result};
 use tracing::debug;
@@ -36,12 +39,19 @@ struct InterfaceInner<'a> {
     device_capabilities: DeviceCapabilities,
     ip_addrs: ManagedSlice<'a, IpCidr>,
     any_ip: bool,
+    tcp_rx_buffer_size: usize,
+    tcp_tx_buffer_size: usize,
+    /// Sockets that received or sent packets, or changed state, since the last
+    /// `drain_changed`.
+    changed: HashSet<SocketHandle>,
 }
 
 pub struct InterfaceBuilder<'a, DeviceT: for<'d> Device<'d>> {
     device: DeviceT,
     ip_addrs: ManagedSlice<'a, IpCidr>,
     any_ip: bool,
+    tcp_rx_buffer_size: usize,
+    tcp_tx_buffer_size: usize,
 }
 
 impl<'a, DeviceT> InterfaceBuilder<'a, DeviceT>
@@ -53,6 +63,8 @@ where
             device,
             ip_addrs: ManagedSlice::Borrowed(&mut []),
             any_ip: false,
+            tcp_rx_buffer_size: 1500,
+            tcp_tx_buffer_size: 1500,
         }
     }
 
@@ -70,6 +82,13 @@ where
         self
     }
 
+    /// Sizes of the receive and send buffers of each tcp socket created by the interface.
+    pub fn tcp_buffer_sizes(mut self, rx: usize, tx: usize) -> Self {
+        self.tcp_rx_buffer_size = rx;
+        self.tcp_tx_buffer_size = tx;
+        self
+    }
+
     pub fn finalize(self) -> Interface<'a, DeviceT> {
         let cap = self.device.capabilities();
         Interface {
@@ -78,6 +97,9 @@ where
                 ip_addrs: self.ip_addrs,
                 device_capabilities: cap,
                 any_ip: self.any_ip,
+                tcp_rx_buffer_size: self.tcp_rx_buffer_size,
+                tcp_tx_buffer_size: self.tcp_tx_buffer_size,
+                changed: HashSet::new(),
             },
         }
     }
@@ -103,6 +125,12 @@ where
         &mut self.device
     }
 
+    /// Take the sockets whose buffers or state may have changed while polling, only these need
+    /// to be looked at again.
+    pub fn drain_changed(&mut self) -> impl Iterator<Item = SocketHandle> + '_ {
+        self.inner.changed.drain()
+    }
+
     /// Transmit packets queued in the given sockets, and receive packets queued
     /// in the device.
     ///
@@ -227,13 +255,19 @@ where
             };
             rx_token.consume(timestamp, |frame| {
                 inner
-                    .process_ipv4(sockets, timestamp, &frame)
+                    .process_ip(sockets, timestamp, &frame)
                     .map_err(|err| {
                         debug!("cannot process ingress packet: {}", err);
-                        debug!(
-                            "packet dump follows:\n{}",
-                            PrettyPrinter::<Ipv4Packet<&[u8]>>::new("", &frame)
-                        );
+                        match IpVersion::of_packet(&frame) {
+                            Ok(IpVersion::Ipv6) => debug!(
+                                "packet dump follows:\n{}",
+                                PrettyPrinter::<Ipv6Packet<&[u8]>>::new("", &frame)
+                            ),
+                            _ => debug!(
+                                "packet dump follows:\n{}",
+                                PrettyPrinter::<Ipv4Packet<&[u8]>>::new("", &frame)
+                            ),
+                        }
                         err
                     })
                     .and_then(|response| {
@@ -270,14 +304,23 @@ where
                 }};
             }
 
+            let handle = socket.meta().handle;
             let socket_result = match *socket {
                 Socket::Udp(ref mut socket) => {
                     socket.dispatch(|response| respond!(Packet::Udp(response)))
                 }
-                Socket::Tcp(ref mut socket) => socket.dispatch(timestamp, &caps, |response| {
-                    let resp = Packet::Tcp(response);
-                    respond!(resp)
-                }),
+                Socket::Tcp(ref mut socket) => {
+                    let state = socket.state();
+                    let result = socket.dispatch(timestamp, &caps, |response| {
+                        let resp = Packet::Tcp(response);
+                        respond!(resp)
+                    });
+                    // Timers close sockets without any packet from the peer.
+                    if socket.state() != state {
+                        inner.changed.insert(handle);
+                    }
+                    result
+                }
                 _ => unreachable!(),
             };
 
@@ -292,7 +335,10 @@ where
                     );
                     return Err(err);
                 }
-                (Ok(()), Ok(())) => emitted_any = true,
+                (Ok(()), Ok(())) => {
+                    emitted_any = true;
+                    inner.changed.insert(handle);
+                }
             }
         }
         Ok(emitted_any)
@@ -317,6 +363,56 @@ impl<'a> InterfaceInner<'a> {
             .next()
     }
 
+    fn process_ip<'frame, T: AsRef<[u8]>>(
+        &mut self,
+        sockets: &mut SocketSet<'static, 'static, 'static>,
+        timestamp: Instant,
+        frame: &'frame T,
+    ) -> Result<Packet<'frame>> {
+        match IpVersion::of_packet(frame.as_ref())? {
+            IpVersion::Ipv4 => self.process_ipv4(sockets, timestamp, frame),
+            IpVersion::Ipv6 => self.process_ipv6(sockets, timestamp, frame),
+            _ => Err(Error::Unrecognized),
+        }
+    }
+
+    fn process_ipv6<'frame, T: AsRef<[u8]>>(
+        &mut self,
+        sockets: &mut SocketSet<'static, 'static, 'static>,
+        timestamp: Instant,
+        frame: &'frame T,
+    ) -> Result<Packet<'frame>> {
+        let ipv6_packet = Ipv6Packet::new_checked(frame)?;
+        let ipv6_repr = Ipv6Repr::parse(&ipv6_packet)?;
+
+        if !ipv6_repr.src_addr.is_unicast() {
+            // Discard packets with non-unicast source addresses.
+            debug!("non-unicast source address");
+            return Err(Error::Malformed);
+        }
+
+        let ip_repr = IpRepr::Ipv6(ipv6_repr);
+        let ip_payload = ipv6_packet.payload();
+
+        if !self.has_ip_addr(ipv6_repr.dst_addr) && !self.any_ip {
+            // Ignore IP packets not directed at us.
+            return Ok(Packet::None);
+        }
+
+        // Extension headers are not supported, packets carrying them are ignored.
+        match ipv6_repr.next_header {
+            IpProtocol::Udp => self.process_udp(sockets, ip_repr, ip_payload),
+
+            IpProtocol::Tcp => {
+                let packet = self.process_tcp(sockets, timestamp, ip_repr, ip_payload)?;
+                debug!("send tcp packet: {:?}", packet);
+                Ok(packet)
+            }
+
+            _ => Ok(Packet::None),
+        }
+    }
+
     fn process_ipv4<'frame, T: AsRef<[u8]>>(
         &mut self,
         sockets: &mut SocketSet<'static, 'static, 'static>,
@@ -394,7 +490,7 @@ impl<'a> InterfaceInner<'a> {
     }
 
     fn process_udp<'frame>(
-        &self,
+        &mut self,
         sockets: &mut SocketSet,
         ip_repr: IpRepr,
         ip_payload: &'frame [u8],
@@ -410,6 +506,7 @@ impl<'a> InterfaceInner<'a> {
             if !udp_socket.accepts(&ip_repr, &udp_repr) {
                 continue;
             }
+            self.changed.insert(udp_socket.handle());
 
             match udp_socket.process(&ip_repr, &udp_repr) {
                 // The packet is valid and handled by socket.
@@ -421,6 +518,7 @@ impl<'a> InterfaceInner<'a> {
 
         // Auto create a new  udp socket.
         let handle = self.new_udp_socket(sockets, &ip_repr, &udp_repr)?;
+        self.changed.insert(handle);
         let mut udp_socket = sockets.get::<UdpSocket>(handle);
         match udp_socket.process(&ip_repr, &udp_repr) {
             // The packet is valid and handled by socket.
@@ -443,8 +541,8 @@ impl<'a> InterfaceInner<'a> {
             "new tcp socket: dest_addr: {}, dst_port: {}",
             dst_addr, dst_port
         );
-        let tcp_rx_buffer = TcpSocketBuffer::new(vec![0; 1500]);
-        let tcp_tx_buffer = TcpSocketBuffer::new(vec![0; 1500]);
+        let tcp_rx_buffer = TcpSocketBuffer::new(vec![0; self.tcp_rx_buffer_size]);
+        let tcp_tx_buffer = TcpSocketBuffer::new(vec![0; self.tcp_tx_buffer_size]);
         let mut tcp_socket = TcpSocket::new(tcp_rx_buffer, tcp_tx_buffer);
         tcp_socket
             .listen(IpEndpoint::new(dst_addr, dst_port))
@@ -466,7 +564,7 @@ impl<'a> InterfaceInner<'a> {
     }
 
     fn process_tcp<'frame>(
-        &self,
+        &mut self,
         sockets: &mut SocketSet<'static, 'static, 'static>,
         timestamp: Instant,
         ip_repr: IpRepr,
@@ -483,6 +581,7 @@ impl<'a> InterfaceInner<'a> {
             if !tcp_socket.accepts(&ip_repr, &tcp_repr) {
                 continue;
             }
+            self.changed.insert(tcp_socket.handle());
 
             match tcp_socket.process(timestamp, &ip_repr, &tcp_repr) {
                 // The packet is valid and handled by socket.
@@ -509,6 +608,7 @@ impl<'a> InterfaceInner<'a> {
             }
             Err(e) => return Err(e),
         };
+        self.changed.insert(handle);
         let mut tcp_socket = sockets.get::<TcpSocket>(handle);
         match tcp_socket.process(timestamp, &ip_repr, &tcp_repr) {
             // The packet is valid and handled by socket.
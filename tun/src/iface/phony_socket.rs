@@ -7,23 +7,67 @@ use tracing::debug;
 
 const MAX_PACKETS: usize = 1024;
 
+/// A slot of the packet rings. The buffer is allocated once and never shrinks, so reusing the
+/// slot neither allocates nor zeroes memory.
+#[derive(Debug)]
+pub struct Packet {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+impl Packet {
+    fn new(mtu: usize) -> Self {
+        Packet {
+            buf: vec![0; mtu],
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.buf[..self.len]
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
 #[derive(Debug)]
 pub struct PhonySocket {
     mtu: usize,
-    rx: RingBuffer<'static, Vec<u8>>,
-    tx: RingBuffer<'static, Vec<u8>>,
+    rx: RingBuffer<'static, Packet>,
+    tx: RingBuffer<'static, Packet>,
 }
 
 fn enqueue<'a>(
-    ring_buffer: &'a mut RingBuffer<'static, Vec<u8>>,
-    mtu: usize,
-) -> Result<&'a mut Vec<u8>> {
-    let buf = ring_buffer.enqueue_one()?;
-    buf.resize(mtu, 0);
-    Ok(buf)
+    ring_buffer: &'a mut RingBuffer<'static, Packet>,
+    len: usize,
+) -> Result<&'a mut Packet> {
+    let packet = ring_buffer.enqueue_one()?;
+    if packet.buf.len() < len {
+        packet.buf.resize(len, 0);
+    }
+    packet.len = len;
+    Ok(packet)
 }
 
-fn deque<'a>(ring_buffer: &'a mut RingBuffer<'static, Vec<u8>>) -> Option<&'a mut Vec<u8>> {
+fn deque<'a>(ring_buffer: &'a mut RingBuffer<'static, Packet>) -> Option<&'a mut Packet> {
     //    NLL 目前没法支持这种类型的代码，只能写出这样来绕过 borrow checker
     //    loop {
     //        let buf = ring_buffer.dequeue_one()?;
@@ -56,8 +100,8 @@ fn deque<'a>(ring_buffer: &'a mut RingBuffer<'static, Vec<u8>>) -> Option<&'a mu
 
 impl PhonySocket {
     pub fn new(mtu: usize) -> Self {
-        let rx: Vec<_> = (0..MAX_PACKETS).map(|_| vec![0; mtu]).collect();
-        let tx: Vec<_> = (0..MAX_PACKETS).map(|_| vec![0; mtu]).collect();
+        let rx: Vec<_> = (0..MAX_PACKETS).map(|_| Packet::new(mtu)).collect();
+        let tx: Vec<_> = (0..MAX_PACKETS).map(|_| Packet::new(mtu)).collect();
         PhonySocket {
             mtu,
             rx: RingBuffer::new(rx),
@@ -65,11 +109,12 @@ impl PhonySocket {
         }
     }
 
-    pub fn populate_rx(&mut self) -> Option<&mut Vec<u8>> {
+    /// Slot to read the next packet from the device into, `truncate` it to the size read.
+    pub fn populate_rx(&mut self) -> Option<&mut Packet> {
         enqueue(&mut self.rx, self.mtu).ok()
     }
 
-    pub fn vacate_tx(&mut self) -> Option<&mut Vec<u8>> {
+    pub fn vacate_tx(&mut self) -> Option<&mut Packet> {
         deque(&mut self.tx)
     }
 }
@@ -79,7 +124,7 @@ impl<'a> Device<'a> for PhonySocket {
     type TxToken = TxToken<'a>;
 
     fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
-        let buf = deque(&mut self.rx)?;
+        let buf = deque(&mut self.rx)?.as_mut_slice();
         let rx = RxToken { buf };
         let tx = TxToken {
             ring_buffer: &mut self.tx,
@@ -103,7 +148,7 @@ impl<'a> Device<'a> for PhonySocket {
 
 #[doc(hidden)]
 pub struct TxToken<'a> {
-    ring_buffer: &'a mut RingBuffer<'static, Vec<u8>>,
+    ring_buffer: &'a mut RingBuffer<'static, Packet>,
 }
 
 #[doc(hidden)]
@@ -116,9 +161,8 @@ impl<'a> phy::TxToken for TxToken<'a> {
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
     {
-        let buf = enqueue(self.ring_buffer, len)?;
-        buf.resize(len, 0);
-        let ret = f(buf);
+        let packet = enqueue(self.ring_buffer, len)?;
+        let ret = f(packet.as_mut_slice());
         debug!("TxToken.consume {} bytes", len);
         ret
     }
@@ -1,27 +1,26 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::io::Result;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll, Waker};
-use std::time::Duration;
 
 use async_std::prelude::*;
 use async_std::task::ready;
 use parking_lot::Mutex;
-use smoltcp::socket::{Socket, SocketHandle, SocketSet};
+use smoltcp::socket::{Socket, SocketHandle, SocketSet, TcpSocket, UdpSocket};
 use smoltcp::time::Instant;
-use smoltcp::wire::Ipv4Cidr;
+use smoltcp::wire::{Ipv4Cidr, Ipv6Cidr};
 use tracing::{debug, error, trace};
 
 use iface::ethernet::{Interface, InterfaceBuilder};
 use iface::phony_socket::PhonySocket;
-use lazy_static::lazy_static;
-use sysconfig::setup_ip;
+use sysconfig::{setup_ip, setup_ipv6};
 
-use crate::socket::TunSocket;
+use crate::socket::buffer_pool::BufferPool;
+use crate::socket::{to_socket_addr, TcpShard, TunSocket, UdpShard};
 
 pub mod iface;
 pub mod phy;
@@ -29,28 +28,75 @@ pub mod phy;
 #[macro_use]
 pub mod socket;
 
-lazy_static! {
-    static ref TUN: Mutex<Option<Tun>> = Mutex::new(None);
+/// Handle of a tun device and the netstack on top of it, clones share the same device.
+///
+/// `TunListen` and `TunWrite` drive the netstack, and move data between smoltcp and the
+/// buffers of each socket. Sockets only lock their own buffers, so connections never wait for
+/// each other or for the netstack.
+#[derive(Clone)]
+pub struct Tun {
+    inner: Arc<Inner>,
 }
 
-pub struct Tun {
+/// Buffers of udp packets kept for reuse, on top of the packets in flight.
+const UDP_POOL_BUFFERS: usize = 1024;
+
+/// Sizes of the smoltcp buffers of each tcp socket, in bytes.
+///
+/// Larger buffers let a connection have more data in flight, at the cost of memory for every
+/// connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BufferSizes {
+    pub tcp_rx: usize,
+    pub tcp_tx: usize,
+}
+
+impl Default for BufferSizes {
+    fn default() -> Self {
+        BufferSizes {
+            tcp_rx: 64 * 1024,
+            tcp_tx: 64 * 1024,
+        }
+    }
+}
+
+struct Inner {
+    stack: Mutex<Stack>,
+    udp_buffers: Arc<BufferPool>,
+    /// Task of `TunWrite`, woken when sockets have data for the netstack.
+    write_task: Mutex<Option<Waker>>,
+    /// Sockets that changed their buffers since the last sync, marked by the sockets.
+    dirty: Mutex<HashSet<SocketHandle>>,
+    /// Set by `stop_accepting`, new connections are reset instead of accepted.
+    stop_accepting: AtomicBool,
+    to_terminate: Arc<AtomicBool>,
+}
+
+struct Stack {
     iface: Interface<'static, PhonySocket>,
-    tun: phy::TunSocket,
+    tun: Box<dyn phy::Device>,
     sockets: SocketSet<'static, 'static, 'static>,
+    shards: HashMap<SocketHandle, Shard>,
+    /// Sockets synced by the running `sync_shards`, kept to reuse its allocation.
+    syncing: HashSet<SocketHandle>,
     new_sockets: Vec<TunSocket>,
-    socket_read_tasks: HashMap<SocketHandle, Option<Waker>>,
-    socket_write_tasks: HashMap<SocketHandle, Option<Waker>>,
-    tun_write_task: Option<Waker>,
-    to_terminate: Arc<AtomicBool>,
+}
+
+enum Shard {
+    Tcp(Arc<Mutex<TcpShard>>),
+    Udp(Arc<Mutex<UdpShard>>),
 }
 
 impl Tun {
+    /// Create the tun device and assign `tun_ip` to it, and `tun_ipv6` if IPv6 is enabled.
     pub fn setup(
         tun_name: String,
         tun_ip: Ipv4Addr,
         tun_cidr: Ipv4Cidr,
+        tun_ipv6: Option<(Ipv6Addr, Ipv6Cidr)>,
+        buffer_sizes: BufferSizes,
         to_terminate: Arc<AtomicBool>,
-    ) {
+    ) -> Tun {
         let tun = phy::TunSocket::new(tun_name.as_str());
         let tun_name = tun.name();
         if cfg!(target_os = "macos") {
@@ -68,41 +114,128 @@ impl Tun {
             );
         }
 
-        let device = PhonySocket::new(tun.mtu());
-        let ip_addrs = vec![tun_cidr.into()];
-        let iface = InterfaceBuilder::new(device)
+        if let Some((ip, cidr)) = tun_ipv6 {
+            setup_ipv6(tun_name, &ip.to_string(), &cidr.to_string());
+        }
+
+        Tun::with_device(
+            tun,
+            tun_cidr,
+            tun_ipv6.map(|(_, cidr)| cidr),
+            buffer_sizes,
+            to_terminate,
+        )
+    }
+
+    /// Run the netstack on `device`, accepting connections to any address in `tun_cidr` and
+    /// `tun_ipv6_cidr`.
+    pub fn with_device<D: phy::Device + 'static>(
+        device: D,
+        tun_cidr: Ipv4Cidr,
+        tun_ipv6_cidr: Option<Ipv6Cidr>,
+        buffer_sizes: BufferSizes,
+        to_terminate: Arc<AtomicBool>,
+    ) -> Tun {
+        let mtu = device.mtu();
+        let phony = PhonySocket::new(mtu);
+        let mut ip_addrs = vec![tun_cidr.into()];
+        if let Some(cidr) = tun_ipv6_cidr {
+            ip_addrs.push(cidr.into());
+        }
+        let iface = InterfaceBuilder::new(phony)
             .ip_addrs(ip_addrs)
             .any_ip(true)
+            .tcp_buffer_sizes(buffer_sizes.tcp_rx, buffer_sizes.tcp_tx)
             .finalize();
 
-        let sockets = SocketSet::new(vec![]);
-        let new_sockets = Vec::new();
-        let tun = Tun {
+        let stack = Stack {
             iface,
-            tun,
-            sockets,
-            new_sockets,
-            socket_read_tasks: HashMap::new(),
-            socket_write_tasks: HashMap::new(),
-            tun_write_task: None,
-            to_terminate,
+            tun: Box::new(device),
+            sockets: SocketSet::new(vec![]),
+            shards: HashMap::new(),
+            syncing: HashSet::new(),
+            new_sockets: Vec::new(),
         };
-        let _ = TUN
-            .try_lock_for(Duration::from_secs(1))
-            .unwrap()
-            .replace(tun);
+        Tun {
+            inner: Arc::new(Inner {
+                stack: Mutex::new(stack),
+                udp_buffers: Arc::new(BufferPool::new(mtu, UDP_POOL_BUFFERS)),
+                write_task: Mutex::new(None),
+                dirty: Mutex::new(HashSet::new()),
+                stop_accepting: AtomicBool::new(false),
+                to_terminate,
+            }),
+        }
+    }
+
+    pub fn listen(&self) -> TunListen {
+        TunListen { tun: self.clone() }
+    }
+
+    pub fn bg_send(&self) -> TunWrite {
+        TunWrite { tun: self.clone() }
+    }
+
+    /// Stop handing out new sockets, tcp connections are reset and udp packets dropped once
+    /// opened. `TunListen` and `TunWrite` keep moving packets of the sockets already accepted
+    /// until `to_terminate` is set, so they can finish.
+    pub fn stop_accepting(&self) {
+        self.inner.stop_accepting.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn udp_buffers(&self) -> Arc<BufferPool> {
+        self.inner.udp_buffers.clone()
     }
 
-    pub fn listen() -> TunListen {
-        TunListen
+    pub(crate) fn wake_writer(&self) {
+        if let Some(waker) = self.inner.write_task.lock().take() {
+            waker.wake();
+        }
     }
 
-    pub fn bg_send() -> TunWrite {
-        TunWrite
+    /// Mark the socket of `handle` to be synced with smoltcp, and wake `TunWrite` to do it.
+    pub(crate) fn notify(&self, handle: SocketHandle) {
+        self.inner.dirty.lock().insert(handle);
+        self.wake_writer();
     }
 }
 
-pub struct TunListen;
+impl Stack {
+    /// Move data between smoltcp and the buffers of sockets, and release sockets that are
+    /// dropped.
+    ///
+    /// Only sockets marked in `dirty` by their owners, or changed by smoltcp while polling, are
+    /// synced, idle connections cost nothing.
+    fn sync_shards(&mut self, dirty: &Mutex<HashSet<SocketHandle>>) {
+        self.syncing.extend(self.iface.drain_changed());
+        self.syncing.extend(dirty.lock().drain());
+        let mut released = Vec::new();
+        for handle in self.syncing.drain() {
+            // Sockets not accepted yet have no shard, released ones have none any more.
+            let keep = match self.shards.get(&handle) {
+                Some(Shard::Tcp(shard)) => shard
+                    .lock()
+                    .sync(&mut *self.sockets.get::<TcpSocket>(handle)),
+                Some(Shard::Udp(shard)) => shard
+                    .lock()
+                    .sync(&mut *self.sockets.get::<UdpSocket>(handle)),
+                None => continue,
+            };
+            if !keep {
+                released.push(handle);
+            }
+        }
+        for handle in released {
+            debug!("release socket {}", handle);
+            self.shards.remove(&handle);
+            self.sockets.release(handle);
+        }
+    }
+}
+
+pub struct TunListen {
+    tun: Tun,
+}
 
 #[derive(Debug, PartialEq)]
 enum Handle {
@@ -111,8 +244,8 @@ enum Handle {
 }
 
 impl TunListen {
-    fn may_recv_tun_handles(mut_tun: &mut Tun, handles: &mut Vec<Handle>) {
-        for s in mut_tun.sockets.iter() {
+    fn may_recv_tun_handles(stack: &Stack, handles: &mut Vec<Handle>) {
+        for s in stack.sockets.iter() {
             match s {
                 Socket::Tcp(s) if s.may_recv() => {
                     handles.push(Handle::Tcp(s.handle()));
@@ -124,35 +257,76 @@ impl TunListen {
             }
         }
     }
+
+    fn accept(tun: &Tun, stack: &mut Stack, handle: &Handle) {
+        let (sock, shard) = match *handle {
+            Handle::Tcp(h) => {
+                let (local, remote) = {
+                    let s = stack.sockets.get::<TcpSocket>(h);
+                    (s.local_endpoint(), s.remote_endpoint())
+                };
+                let sock = TunSocket::new_tcp_socket(
+                    tun.clone(),
+                    h,
+                    to_socket_addr(local),
+                    to_socket_addr(remote),
+                );
+                let shard = match &sock {
+                    TunSocket::Tcp(s) => Shard::Tcp(s.shard()),
+                    TunSocket::Udp(_) => unreachable!(),
+                };
+                (sock, shard)
+            }
+            Handle::Udp(h) => {
+                let local = stack.sockets.get::<UdpSocket>(h).endpoint();
+                let sock = TunSocket::new_udp_socket(tun.clone(), h, to_socket_addr(local));
+                let shard = match &sock {
+                    TunSocket::Udp(s) => Shard::Udp(s.shard()),
+                    TunSocket::Tcp(_) => unreachable!(),
+                };
+                (sock, shard)
+            }
+        };
+        stack.shards.insert(sock.handle(), shard);
+        stack.new_sockets.push(sock);
+    }
 }
 
 impl Stream for TunListen {
     type Item = Result<TunSocket>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut guard = TUN.try_lock_for(Duration::from_secs(1)).unwrap();
-        let mut_tun = guard.as_mut().expect("no tun setup");
-        let size = mut_tun.sockets.iter().count();
+        let tun = &self.tun;
+        let mut guard = tun.inner.stack.lock();
+        let stack = &mut *guard;
+        let size = stack.sockets.iter().count();
         let mut before_handle = Vec::with_capacity(size);
         let mut after_handle = Vec::with_capacity(size);
 
         loop {
-            if mut_tun.to_terminate.load(Ordering::Relaxed) {
+            if tun.inner.to_terminate.load(Ordering::Relaxed) {
                 return Poll::Ready(None);
             }
 
-            if let Some(s) = mut_tun.new_sockets.pop() {
+            if let Some(s) = stack.new_sockets.pop() {
+                if tun.inner.stop_accepting.load(Ordering::Relaxed) {
+                    debug!("reject socket while stopping: {}", s);
+                    if let TunSocket::Tcp(s) = &s {
+                        s.abort();
+                    }
+                    continue;
+                }
                 trace!("new socket accepted: {}", s);
                 return Poll::Ready(Some(Ok(s)));
             }
 
-            TunListen::may_recv_tun_handles(mut_tun, &mut before_handle);
+            TunListen::may_recv_tun_handles(stack, &mut before_handle);
 
             {
-                let phony_socket = mut_tun.iface.device_mut();
+                let phony_socket = stack.iface.device_mut();
                 let mut total_size = 0;
                 while let Some(buf) = phony_socket.populate_rx() {
-                    let size = match Pin::new(&mut mut_tun.tun).poll_read(cx, buf) {
+                    let size = match Pin::new(&mut stack.tun).poll_read(cx, buf.as_mut_slice()) {
                         Poll::Ready(Ok(size)) => {
                             buf.truncate(size);
                             trace!("tun.poll_read size {}", size);
@@ -173,10 +347,7 @@ impl Stream for TunListen {
                 }
             }
 
-            match mut_tun
-                .iface
-                .poll_read(&mut mut_tun.sockets, Instant::now())
-            {
+            match stack.iface.poll_read(&mut stack.sockets, Instant::now()) {
                 Ok(_) => {}
                 Err(smoltcp::Error::Malformed) | Err(smoltcp::Error::Dropped) => {}
                 Err(e) => {
@@ -184,61 +355,32 @@ impl Stream for TunListen {
                 }
             };
 
-            if let Some(waker) = mut_tun.tun_write_task.take() {
-                debug!("notify tun for write");
-                waker.wake();
-            }
-
-            for mut socket in mut_tun.sockets.iter_mut() {
-                match &mut *socket {
-                    Socket::Tcp(ref mut s) => {
-                        if s.is_open() && (s.can_recv() || !s.may_recv()) {
-                            if let Some(t) = mut_tun.socket_read_tasks.get_mut(&s.handle()) {
-                                if let Some(waker) = t.take() {
-                                    debug!("notify tcp socket {} for read", s.handle());
-                                    waker.wake();
-                                }
-                            }
-                        }
-                    }
-
-                    Socket::Udp(s) => {
-                        if s.is_open() && s.can_recv() {
-                            if let Some(t) = mut_tun.socket_read_tasks.get_mut(&s.handle()) {
-                                if let Some(waker) = t.take() {
-                                    debug!("notify udp socket {} for read", s.handle());
-                                    waker.wake();
-                                }
-                            }
-                        }
-                    }
-                    _ => unreachable!(),
-                }
-            }
-
-            mut_tun.sockets.prune();
+            stack.sockets.prune();
 
-            TunListen::may_recv_tun_handles(mut_tun, &mut after_handle);
+            TunListen::may_recv_tun_handles(stack, &mut after_handle);
             for handle in &after_handle {
                 if !before_handle.contains(handle) {
-                    let sock = match handle {
-                        Handle::Tcp(h) => unsafe { TunSocket::new_tcp_socket(*h) },
-                        Handle::Udp(h) => unsafe { TunSocket::new_udp_socket(*h) },
-                    };
-                    mut_tun.new_sockets.push(sock);
+                    TunListen::accept(tun, stack, handle);
                 }
             }
             before_handle.clear();
             after_handle.clear();
+
+            stack.sync_shards(&tun.inner.dirty);
+
+            debug!("notify tun for write");
+            tun.wake_writer();
         }
     }
 }
 
-pub struct TunWrite;
+pub struct TunWrite {
+    tun: Tun,
+}
 
 impl TunWrite {
-    fn poll_write_sockets_to_phoney_socket(cx: &mut Context<'_>, tun: &mut Tun) -> Result<bool> {
-        let processed_any = match tun.iface.poll_write(&mut tun.sockets, Instant::now()) {
+    fn poll_write_sockets_to_phoney_socket(stack: &mut Stack) -> Result<bool> {
+        let processed_any = match stack.iface.poll_write(&mut stack.sockets, Instant::now()) {
             Ok(any) => any,
             Err(smoltcp::Error::Malformed) | Err(smoltcp::Error::Dropped) => true,
             Err(e) => {
@@ -247,36 +389,8 @@ impl TunWrite {
             }
         };
 
-        for socket in tun.sockets.iter_mut() {
-            match &*socket {
-                Socket::Tcp(s) => {
-                    if s.can_send() {
-                        if let Some(t) = tun.socket_write_tasks.get_mut(&s.handle()) {
-                            if let Some(waker) = t.take() {
-                                debug!("notify tcp socket {} for write", s.handle());
-                                waker.wake();
-                            }
-                        }
-                    }
-                }
-                Socket::Udp(s) => {
-                    if s.can_send() {
-                        //                        debug!("udp socket {} can send.", s.handle());
-                        if let Some(t) = tun.socket_read_tasks.get_mut(&s.handle()) {
-                            if let Some(waker) = t.take() {
-                                debug!("notify udp socket {} for write", s.handle());
-                                waker.wake();
-                            }
-                        }
-                    }
-                }
-                _ => unreachable!(),
-            }
-        }
-
         if !processed_any {
             debug!("TunWrite NotReady");
-            tun.tun_write_task = Some(cx.waker().clone());
         }
         Ok(processed_any)
     }
@@ -286,21 +400,28 @@ impl Future for TunWrite {
     type Output = Result<()>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        loop {
-            let mut guard = TUN.try_lock_for(Duration::from_secs(5)).unwrap();
-            let mut_tun = guard.as_mut().expect("no tun setup");
+        let tun = &self.tun;
+        // Registered before looking at the sockets, so wakes from sockets during this poll
+        // are not lost.
+        *tun.inner.write_task.lock() = Some(cx.waker().clone());
+        let mut guard = tun.inner.stack.lock();
+        let stack = &mut *guard;
 
-            if mut_tun.to_terminate.load(Ordering::Relaxed) {
+        loop {
+            if tun.inner.to_terminate.load(Ordering::Relaxed) {
                 return Poll::Ready(Ok(()));
             }
 
+            stack.sync_shards(&tun.inner.dirty);
+
             loop {
-                let phony_socket = mut_tun.iface.device_mut();
+                let phony_socket = stack.iface.device_mut();
                 let buf = phony_socket.vacate_tx();
                 match buf {
                     Some(buf) if buf.is_empty() => {}
                     Some(buf) => {
-                        let size = ready!(Pin::new(&mut mut_tun.tun).poll_write(cx, &buf)).unwrap();
+                        let size = ready!(Pin::new(&mut stack.tun).poll_write(cx, buf.as_slice()))
+                            .unwrap();
                         assert_eq!(size, buf.len());
                         debug!("write {} bytes to tun.", size);
                     }
@@ -308,7 +429,7 @@ impl Future for TunWrite {
                 }
             }
 
-            if !TunWrite::poll_write_sockets_to_phoney_socket(cx, mut_tun)? {
+            if !TunWrite::poll_write_sockets_to_phoney_socket(stack)? {
                 return Poll::Pending;
             }
         }
@@ -317,31 +438,486 @@ impl Future for TunWrite {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::VecDeque;
     use std::net::SocketAddr;
+    use std::time::Duration;
 
+    use async_std::future;
     use async_std::io;
     use async_std::net::TcpStream;
     use async_std::prelude::*;
     use async_std::task;
-    use smoltcp::wire::Ipv4Address;
+    use smoltcp::phy::ChecksumCapabilities;
+    use smoltcp::wire::{
+        IpProtocol, Ipv4Address, Ipv4Packet, Ipv4Repr, TcpControl, TcpPacket, TcpRepr,
+        TcpSeqNumber, UdpPacket, UdpRepr,
+    };
 
     use super::*;
+    use crate::phy::{MemoryDevice, MemoryPeer};
+    use crate::socket::TunTcpSocket;
+
+    const PEER_ADDR: Ipv4Address = Ipv4Address([10, 0, 0, 1]);
+    const STACK_ADDR: Ipv4Address = Ipv4Address([10, 0, 0, 2]);
+    const PEER_PORT: u16 = 1234;
+    const STACK_PORT: u16 = 80;
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    type Accepted = Arc<Mutex<VecDeque<TunSocket>>>;
+
+    /// Run a netstack on a `MemoryDevice`, collecting accepted sockets.
+    fn memory_tun() -> (Tun, MemoryPeer, Accepted) {
+        let (device, peer) = MemoryDevice::new(1500);
+        let tun = Tun::with_device(
+            device,
+            Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 0), 24),
+            None,
+            BufferSizes::default(),
+            Arc::new(AtomicBool::new(false)),
+        );
+        task::spawn(tun.bg_send());
+        let accepted = Arc::new(Mutex::new(VecDeque::new()));
+        let mut stream = tun.listen();
+        let sockets = accepted.clone();
+        task::spawn(async move {
+            while let Some(socket) = stream.next().await {
+                sockets.lock().push_back(socket.unwrap());
+            }
+        });
+        (tun, peer, accepted)
+    }
+
+    async fn accept(accepted: &Accepted) -> TunSocket {
+        future::timeout(TIMEOUT, async {
+            loop {
+                if let Some(socket) = accepted.lock().pop_front() {
+                    return socket;
+                }
+                task::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("accept")
+    }
+
+    async fn recv(peer: &MemoryPeer) -> Vec<u8> {
+        future::timeout(TIMEOUT, peer.recv()).await.expect("recv")
+    }
+
+    fn ipv4_packet(
+        src_addr: Ipv4Address,
+        dst_addr: Ipv4Address,
+        protocol: IpProtocol,
+        payload_len: usize,
+        emit: impl FnOnce(&mut [u8]),
+    ) -> Vec<u8> {
+        let ip_repr = Ipv4Repr {
+            src_addr,
+            dst_addr,
+            protocol,
+            payload_len,
+            hop_limit: 64,
+        };
+        let mut buf = vec![0; ip_repr.buffer_len() + payload_len];
+        ip_repr.emit(
+            &mut Ipv4Packet::new_unchecked(&mut buf),
+            &ChecksumCapabilities::default(),
+        );
+        emit(&mut buf[ip_repr.buffer_len()..]);
+        buf
+    }
+
+    fn parse_ipv4(packet: &[u8]) -> (Ipv4Repr, &[u8]) {
+        let ipv4_packet = Ipv4Packet::new_checked(packet).unwrap();
+        let ipv4_repr = Ipv4Repr::parse(&ipv4_packet, &ChecksumCapabilities::default()).unwrap();
+        let payload = &packet[ipv4_repr.buffer_len()..];
+        (ipv4_repr, payload)
+    }
+
+    fn tcp_repr(
+        control: TcpControl,
+        seq: TcpSeqNumber,
+        ack: Option<TcpSeqNumber>,
+    ) -> TcpRepr<'static> {
+        TcpRepr {
+            src_port: PEER_PORT,
+            dst_port: STACK_PORT,
+            control,
+            seq_number: seq,
+            ack_number: ack,
+            window_len: 64240,
+            window_scale: None,
+            max_seg_size: None,
+            sack_permitted: false,
+            sack_ranges: [None, None, None],
+            payload: &[],
+        }
+    }
+
+    fn tcp_packet(repr: &TcpRepr) -> Vec<u8> {
+        ipv4_packet(
+            PEER_ADDR,
+            STACK_ADDR,
+            IpProtocol::Tcp,
+            repr.buffer_len(),
+            |buf| {
+                repr.emit(
+                    &mut TcpPacket::new_unchecked(buf),
+                    &PEER_ADDR.into(),
+                    &STACK_ADDR.into(),
+                    &ChecksumCapabilities::default(),
+                )
+            },
+        )
+    }
+
+    fn parse_tcp(packet: &[u8]) -> TcpRepr {
+        let (ipv4_repr, payload) = parse_ipv4(packet);
+        assert_eq!(ipv4_repr.protocol, IpProtocol::Tcp);
+        assert_eq!(ipv4_repr.src_addr, STACK_ADDR);
+        assert_eq!(ipv4_repr.dst_addr, PEER_ADDR);
+        TcpRepr::parse(
+            &TcpPacket::new_checked(payload).unwrap(),
+            &STACK_ADDR.into(),
+            &PEER_ADDR.into(),
+            &ChecksumCapabilities::default(),
+        )
+        .unwrap()
+    }
+
+    fn udp_packet(src_port: u16, dst_addr: Ipv4Address, payload: &[u8]) -> Vec<u8> {
+        let repr = UdpRepr {
+            src_port,
+            dst_port: 53,
+            payload,
+        };
+        ipv4_packet(
+            PEER_ADDR,
+            dst_addr,
+            IpProtocol::Udp,
+            repr.buffer_len(),
+            |buf| {
+                repr.emit(
+                    &mut UdpPacket::new_unchecked(buf),
+                    &PEER_ADDR.into(),
+                    &dst_addr.into(),
+                    &ChecksumCapabilities::default(),
+                )
+            },
+        )
+    }
+
+    /// Complete a handshake from the peer, returns the accepted socket and the next sequence
+    /// numbers of the peer and the netstack.
+    async fn connect(
+        peer: &MemoryPeer,
+        accepted: &Accepted,
+    ) -> (TunTcpSocket, TcpSeqNumber, TcpSeqNumber) {
+        let peer_seq = TcpSeqNumber(100);
+        peer.send(&tcp_packet(&tcp_repr(TcpControl::Syn, peer_seq, None)));
+
+        let packet = recv(peer).await;
+        let syn_ack = parse_tcp(&packet);
+        assert_eq!(syn_ack.control, TcpControl::Syn);
+        assert_eq!(syn_ack.src_port, STACK_PORT);
+        assert_eq!(syn_ack.dst_port, PEER_PORT);
+        assert_eq!(syn_ack.ack_number, Some(peer_seq + 1));
+
+        let (peer_seq, stack_seq) = (peer_seq + 1, syn_ack.seq_number + 1);
+        peer.send(&tcp_packet(&tcp_repr(
+            TcpControl::None,
+            peer_seq,
+            Some(stack_seq),
+        )));
+        let socket = match accept(accepted).await {
+            TunSocket::Tcp(s) => s,
+            TunSocket::Udp(s) => panic!("unexpected udp socket {:?}", s),
+        };
+        (socket, peer_seq, stack_seq)
+    }
+
+    #[test]
+    fn test_tcp_handshake_and_data() {
+        let (_tun, peer, accepted) = memory_tun();
+        task::block_on(async move {
+            let (mut socket, peer_seq, stack_seq) = connect(&peer, &accepted).await;
+            assert_eq!(
+                socket.local_addr(),
+                "10.0.0.2:80".parse::<SocketAddr>().unwrap()
+            );
+            assert_eq!(
+                socket.remote_addr(),
+                "10.0.0.1:1234".parse::<SocketAddr>().unwrap()
+            );
+
+            let mut data = tcp_repr(TcpControl::Psh, peer_seq, Some(stack_seq));
+            data.payload = b"hello";
+            peer.send(&tcp_packet(&data));
+            let mut buf = vec![0; 1024];
+            let size = socket.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..size], b"hello");
+
+            socket.write_all(b"world").await.unwrap();
+            loop {
+                let packet = recv(&peer).await;
+                let repr = parse_tcp(&packet);
+                // Skip the ack of "hello".
+                if repr.payload.is_empty() {
+                    continue;
+                }
+                assert_eq!(repr.seq_number, stack_seq);
+                assert_eq!(repr.payload, b"world");
+                break;
+            }
+        });
+    }
+
+    #[test]
+    fn test_tcp_fin() {
+        let (_tun, peer, accepted) = memory_tun();
+        task::block_on(async move {
+            let (mut socket, peer_seq, stack_seq) = connect(&peer, &accepted).await;
+            peer.send(&tcp_packet(&tcp_repr(
+                TcpControl::Fin,
+                peer_seq,
+                Some(stack_seq),
+            )));
+
+            let mut buf = vec![0; 1024];
+            assert_eq!(socket.read(&mut buf).await.unwrap(), 0);
+            let ack = parse_tcp(&recv(&peer).await);
+            assert_eq!(ack.ack_number, Some(peer_seq + 1));
+        });
+    }
+
+    #[test]
+    fn test_tcp_rst() {
+        let (_tun, peer, accepted) = memory_tun();
+        task::block_on(async move {
+            let (mut socket, peer_seq, stack_seq) = connect(&peer, &accepted).await;
+            peer.send(&tcp_packet(&tcp_repr(
+                TcpControl::Rst,
+                peer_seq,
+                Some(stack_seq),
+            )));
+
+            let mut buf = vec![0; 1024];
+            let err = socket.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+            let err = socket.write(b"closed").await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+            // Never reply to a RST.
+            task::sleep(Duration::from_millis(100)).await;
+            assert_eq!(peer.try_recv(), None);
+        });
+    }
+
+    #[test]
+    fn test_tcp_half_close() {
+        let (_tun, peer, accepted) = memory_tun();
+        task::block_on(async move {
+            let (mut socket, peer_seq, stack_seq) = connect(&peer, &accepted).await;
+            socket.close().await.unwrap();
+            let fin = parse_tcp(&recv(&peer).await);
+            assert_eq!(fin.control, TcpControl::Fin);
+            assert_eq!(fin.seq_number, stack_seq);
+            let err = socket.write(b"closed").await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+
+            // The peer keeps sending until its own FIN.
+            let mut data = tcp_repr(TcpControl::Psh, peer_seq, Some(stack_seq + 1));
+            data.payload = b"reply";
+            peer.send(&tcp_packet(&data));
+            let mut buf = vec![0; 1024];
+            let size = socket.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..size], b"reply");
+
+            peer.send(&tcp_packet(&tcp_repr(
+                TcpControl::Fin,
+                peer_seq + 5,
+                Some(stack_seq + 1),
+            )));
+            assert_eq!(socket.read(&mut buf).await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn test_tcp_abort() {
+        let (_tun, peer, accepted) = memory_tun();
+        task::block_on(async move {
+            let (mut socket, _peer_seq, _stack_seq) = connect(&peer, &accepted).await;
+            socket.abort();
+            let rst = parse_tcp(&recv(&peer).await);
+            assert_eq!(rst.control, TcpControl::Rst);
+
+            let mut buf = vec![0; 1024];
+            let err = socket.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        });
+    }
+
+    #[test]
+    fn test_udp_demux() {
+        let (_tun, peer, accepted) = memory_tun();
+        let other_addr = Ipv4Address::new(10, 0, 0, 3);
+        task::block_on(async move {
+            peer.send(&udp_packet(5353, STACK_ADDR, b"first"));
+            peer.send(&udp_packet(5354, other_addr, b"second"));
+
+            let mut sockets = Vec::new();
+            for _ in 0..2 {
+                match accept(&accepted).await {
+                    TunSocket::Udp(s) => sockets.push(s),
+                    TunSocket::Tcp(s) => panic!("unexpected tcp socket {:?}", s),
+                }
+            }
+            sockets.sort_by_key(|s| s.local_addr().ip());
+            assert_eq!(
+                sockets[0].local_addr(),
+                "10.0.0.2:53".parse::<SocketAddr>().unwrap()
+            );
+            assert_eq!(
+                sockets[1].local_addr(),
+                "10.0.0.3:53".parse::<SocketAddr>().unwrap()
+            );
+
+            let mut buf = vec![0; 1024];
+            let (size, src) = sockets[0].recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..size], b"first");
+            assert_eq!(src, "10.0.0.1:5353".parse::<SocketAddr>().unwrap());
+            let (size, src) = sockets[1].recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..size], b"second");
+            assert_eq!(src, "10.0.0.1:5354".parse::<SocketAddr>().unwrap());
+
+            sockets[1].send_to(b"reply", &src).await.unwrap();
+            let packet = recv(&peer).await;
+            let (ipv4_repr, payload) = parse_ipv4(&packet);
+            assert_eq!(ipv4_repr.src_addr, other_addr);
+            assert_eq!(ipv4_repr.dst_addr, PEER_ADDR);
+            let udp_repr = UdpRepr::parse(
+                &UdpPacket::new_checked(payload).unwrap(),
+                &other_addr.into(),
+                &PEER_ADDR.into(),
+                &ChecksumCapabilities::default(),
+            )
+            .unwrap();
+            assert_eq!(udp_repr.src_port, 53);
+            assert_eq!(udp_repr.dst_port, 5354);
+            assert_eq!(udp_repr.payload, b"reply");
+        });
+    }
+
+    #[test]
+    fn test_udp_buffer_too_small() {
+        let (_tun, peer, accepted) = memory_tun();
+        task::block_on(async move {
+            peer.send(&udp_packet(5353, STACK_ADDR, b"too large"));
+            peer.send(&udp_packet(5353, STACK_ADDR, b"small"));
+            let socket = match accept(&accepted).await {
+                TunSocket::Udp(s) => s,
+                TunSocket::Tcp(s) => panic!("unexpected tcp socket {:?}", s),
+            };
+
+            let mut buf = vec![0; 5];
+            let err = socket.recv_from(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            // The large packet is dropped, the next one is received whole.
+            let (size, _) = socket.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..size], b"small");
+        });
+    }
+
+    #[test]
+    fn test_sync_dirty_shards_only() {
+        let (tun, peer, accepted) = memory_tun();
+        task::block_on(async move {
+            let (mut socket, peer_seq, stack_seq) = connect(&peer, &accepted).await;
+            let mut data = tcp_repr(TcpControl::Psh, peer_seq, Some(stack_seq));
+            data.payload = b"hello";
+            peer.send(&tcp_packet(&data));
+            let mut buf = vec![0; 1024];
+            let size = socket.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..size], b"hello");
+
+            // Once the netstack is idle, nothing is left to sync.
+            let ack = parse_tcp(&recv(&peer).await);
+            assert_eq!(ack.ack_number, Some(peer_seq + 5));
+            task::sleep(Duration::from_millis(100)).await;
+            assert!(tun.inner.dirty.lock().is_empty());
+            let mut stack = tun.inner.stack.lock();
+            assert!(stack.syncing.is_empty());
+            assert_eq!(stack.iface.drain_changed().count(), 0);
+        });
+    }
+
+    #[test]
+    fn test_stop_accepting() {
+        let (tun, peer, accepted) = memory_tun();
+        task::block_on(async move {
+            let (mut socket, peer_seq, stack_seq) = connect(&peer, &accepted).await;
+            tun.stop_accepting();
+
+            // The connection in flight still moves data both ways.
+            let mut data = tcp_repr(TcpControl::Psh, peer_seq, Some(stack_seq));
+            data.payload = b"hello";
+            peer.send(&tcp_packet(&data));
+            let mut buf = vec![0; 1024];
+            let size = socket.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..size], b"hello");
+            socket.write_all(b"world").await.unwrap();
+            loop {
+                let repr = parse_tcp(&recv(&peer).await);
+                // Skip the ack of "hello".
+                if repr.payload.is_empty() {
+                    continue;
+                }
+                assert_eq!(repr.payload, b"world");
+                break;
+            }
+            peer.send(&tcp_packet(&tcp_repr(
+                TcpControl::None,
+                peer_seq + 5,
+                Some(stack_seq + 5),
+            )));
+
+            // A new connection is reset once its handshake completes.
+            let mut syn = tcp_repr(TcpControl::Syn, TcpSeqNumber(500), None);
+            syn.src_port = PEER_PORT + 1;
+            peer.send(&tcp_packet(&syn));
+            let syn_ack = parse_tcp(&recv(&peer).await);
+            assert_eq!(syn_ack.control, TcpControl::Syn);
+            assert_eq!(syn_ack.dst_port, PEER_PORT + 1);
+            let mut ack = tcp_repr(
+                TcpControl::None,
+                syn.seq_number + 1,
+                Some(syn_ack.seq_number + 1),
+            );
+            ack.src_port = PEER_PORT + 1;
+            peer.send(&tcp_packet(&ack));
+            let rst = parse_tcp(&recv(&peer).await);
+            assert_eq!(rst.control, TcpControl::Rst);
+            assert_eq!(rst.dst_port, PEER_PORT + 1);
+            assert!(accepted.lock().is_empty());
+        });
+    }
 
     #[test]
     fn test_accept_tcp() {
         let to_terminate = Arc::new(AtomicBool::new(false));
-        Tun::setup(
+        let tun = Tun::setup(
             "utun4".to_string(),
             Ipv4Addr::new(10, 0, 0, 1),
             Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 0), 24),
+            None,
+            BufferSizes::default(),
             to_terminate.clone(),
         );
 
         task::block_on(async move {
-            task::spawn(Tun::bg_send());
+            task::spawn(tun.bg_send());
 
             task::spawn(async move {
-                let mut stream = Tun::listen();
+                let mut stream = tun.listen();
                 loop {
                     match stream.next().await {
                         Some(Ok(TunSocket::Tcp(mut s))) => {
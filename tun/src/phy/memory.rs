@@ -0,0 +1,151 @@
+//! In-memory device, to run the netstack in tests without a kernel tun device.
+use super::Device;
+use async_std::future::poll_fn;
+use async_std::io::{Read, Write};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+#[derive(Default)]
+struct Queues {
+    /// Packets injected by the peer, read by the netstack.
+    to_stack: VecDeque<Vec<u8>>,
+    /// Packets written by the netstack, received by the peer.
+    from_stack: VecDeque<Vec<u8>>,
+    stack_task: Option<Waker>,
+    peer_task: Option<Waker>,
+}
+
+/// Device end of an in-memory link, passed to `Tun::with_device`.
+pub struct MemoryDevice {
+    mtu: usize,
+    queues: Arc<Mutex<Queues>>,
+}
+
+/// The other end of a `MemoryDevice`, standing in for the kernel.
+#[derive(Clone)]
+pub struct MemoryPeer {
+    queues: Arc<Mutex<Queues>>,
+}
+
+impl MemoryDevice {
+    pub fn new(mtu: usize) -> (MemoryDevice, MemoryPeer) {
+        let queues = Arc::new(Mutex::new(Queues::default()));
+        let device = MemoryDevice {
+            mtu,
+            queues: queues.clone(),
+        };
+        (device, MemoryPeer { queues })
+    }
+}
+
+impl Device for MemoryDevice {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+}
+
+impl Read for MemoryDevice {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut queues = self.queues.lock();
+        match queues.to_stack.pop_front() {
+            Some(packet) => {
+                let size = packet.len().min(buf.len());
+                buf[..size].copy_from_slice(&packet[..size]);
+                Poll::Ready(Ok(size))
+            }
+            None => {
+                queues.stack_task = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Write for MemoryDevice {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut queues = self.queues.lock();
+        queues.from_stack.push_back(buf.to_vec());
+        if let Some(waker) = queues.peer_task.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl MemoryPeer {
+    /// Inject a raw ip packet, as if the kernel routed it to the tun device.
+    pub fn send(&self, packet: &[u8]) {
+        let mut queues = self.queues.lock();
+        queues.to_stack.push_back(packet.to_vec());
+        if let Some(waker) = queues.stack_task.take() {
+            waker.wake();
+        }
+    }
+
+    /// Take a packet written by the netstack if there is one.
+    pub fn try_recv(&self) -> Option<Vec<u8>> {
+        self.queues.lock().from_stack.pop_front()
+    }
+
+    pub fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Vec<u8>> {
+        let mut queues = self.queues.lock();
+        match queues.from_stack.pop_front() {
+            Some(packet) => Poll::Ready(packet),
+            None => {
+                queues.peer_task = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Wait for a packet written by the netstack.
+    pub async fn recv(&self) -> Vec<u8> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::prelude::*;
+    use async_std::task::block_on;
+
+    #[test]
+    fn test_memory_device() {
+        let (mut device, peer) = MemoryDevice::new(1500);
+        block_on(async move {
+            peer.send(b"to stack");
+            let mut buf = vec![0; 1500];
+            let size = device.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..size], b"to stack");
+
+            assert_eq!(peer.try_recv(), None);
+            device.write_all(b"from stack").await.unwrap();
+            assert_eq!(peer.recv().await, b"from stack".to_vec());
+        })
+    }
+}
@@ -4,8 +4,19 @@ use std::io;
 use std::io::{Read as _, Write as _};
 use std::pin::Pin;
 use std::task::{Context, Poll};
+
+mod memory;
 mod sys;
 
+pub use memory::{MemoryDevice, MemoryPeer};
+
+/// A device the netstack reads packets from and writes packets to, one ip packet for each read
+/// or write.
+pub trait Device: Read + Write + Unpin + Send {
+    fn name(&self) -> &str;
+    fn mtu(&self) -> usize;
+}
+
 pub(crate) struct TunSocket {
     mtu: usize,
     name: String,
@@ -31,6 +42,16 @@ impl TunSocket {
     }
 }
 
+impl Device for TunSocket {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+}
+
 impl Read for TunSocket {
     fn poll_read(
         self: Pin<&mut Self>,
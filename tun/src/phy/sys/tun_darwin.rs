@@ -212,11 +212,6 @@ impl TunSocket {
             n => Ok((n - 4) as usize),
         }
     }
-
-    #[allow(dead_code)]
-    pub fn write6(&self, src: &[u8]) -> Result<usize> {
-        self.af_write(src, AF_INET6 as u8)
-    }
 }
 
 impl Read for TunSocket {
@@ -274,7 +269,11 @@ impl Read for &TunSocket {
 
 impl Write for &TunSocket {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        self.af_write(buf, AF_INET as u8)
+        // utun needs the address family of each packet, read it from the IP version.
+        match buf.first().map(|b| b >> 4) {
+            Some(6) => self.af_write(buf, AF_INET6 as u8),
+            _ => self.af_write(buf, AF_INET as u8),
+        }
     }
 
     fn flush(&mut self) -> Result<()> {
@@ -0,0 +1,61 @@
+use bytes::BytesMut;
+use parking_lot::Mutex;
+
+/// Buffers reused across packets, instead of allocating one for each packet.
+#[derive(Debug)]
+pub struct BufferPool {
+    buffers: Mutex<Vec<BytesMut>>,
+    buffer_size: usize,
+    max_buffers: usize,
+}
+
+impl BufferPool {
+    pub fn new(buffer_size: usize, max_buffers: usize) -> Self {
+        BufferPool {
+            buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+            buffer_size,
+            max_buffers,
+        }
+    }
+
+    /// An empty buffer with room for at least `buffer_size` bytes.
+    pub fn get(&self) -> BytesMut {
+        match self.buffers.lock().pop() {
+            Some(buf) => buf,
+            None => BytesMut::with_capacity(self.buffer_size),
+        }
+    }
+
+    /// Return `buf` to the pool, it is dropped if the pool is full.
+    pub fn put(&self, mut buf: BytesMut) {
+        buf.clear();
+        let mut buffers = self.buffers.lock();
+        if buffers.len() < self.max_buffers {
+            buffers.push(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse_buffers() {
+        let pool = BufferPool::new(1500, 1);
+        let mut buf = pool.get();
+        assert!(buf.capacity() >= 1500);
+        buf.extend_from_slice(b"packet");
+        let ptr = buf.as_ptr();
+        pool.put(buf);
+
+        let buf = pool.get();
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+
+        // Only one buffer is kept.
+        pool.put(buf);
+        pool.put(BytesMut::with_capacity(1500));
+        assert_eq!(pool.buffers.lock().len(), 1);
+    }
+}
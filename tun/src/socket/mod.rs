@@ -1,24 +1,46 @@
 use smoltcp::wire::{IpAddress, IpEndpoint};
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::task::Waker;
 
-fn to_socket_addr(endpoint: IpEndpoint) -> SocketAddr {
+pub(crate) fn to_socket_addr(endpoint: IpEndpoint) -> SocketAddr {
     match endpoint.addr {
         IpAddress::Ipv4(addr) => {
             let a: Ipv4Addr = addr.into();
             (a, endpoint.port).into()
         }
+        IpAddress::Ipv6(addr) => {
+            let a: Ipv6Addr = addr.into();
+            (a, endpoint.port).into()
+        }
         _ => unreachable!(),
     }
 }
 
+fn to_endpoint(addr: SocketAddr) -> IpEndpoint {
+    match addr {
+        SocketAddr::V4(addr) => addr.into(),
+        SocketAddr::V6(addr) => addr.into(),
+    }
+}
+
+fn wake(task: &mut Option<Waker>) {
+    if let Some(waker) = task.take() {
+        waker.wake();
+    }
+}
+
+pub mod buffer_pool;
 mod tcp_socket;
 mod udp_socket;
 
+use crate::Tun;
 use smoltcp::socket::SocketHandle;
 use std::fmt;
 use std::fmt::Display;
+pub(crate) use tcp_socket::TcpShard;
 pub use tcp_socket::TunTcpSocket;
 pub use udp_socket::TunUdpSocket;
+pub(crate) use udp_socket::UdpShard;
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum TunSocket {
@@ -27,18 +49,21 @@ pub enum TunSocket {
 }
 
 impl TunSocket {
-    /// # Safety
-    ///
-    /// You need to make sure handle have at lease one reference.
-    pub unsafe fn new_tcp_socket(handle: SocketHandle) -> TunSocket {
-        TunSocket::Tcp(TunTcpSocket::new(handle))
-    }
-
-    /// # Safety
-    ///
-    /// You need to make sure handle have at lease one reference.
-    pub unsafe fn new_udp_socket(handle: SocketHandle) -> TunSocket {
-        TunSocket::Udp(TunUdpSocket::new(handle))
+    pub(crate) fn new_tcp_socket(
+        tun: Tun,
+        handle: SocketHandle,
+        local_addr: SocketAddr,
+        remote_addr: SocketAddr,
+    ) -> TunSocket {
+        TunSocket::Tcp(TunTcpSocket::new(tun, handle, local_addr, remote_addr))
+    }
+
+    pub(crate) fn new_udp_socket(
+        tun: Tun,
+        handle: SocketHandle,
+        local_addr: SocketAddr,
+    ) -> TunSocket {
+        TunSocket::Udp(TunUdpSocket::new(tun, handle, local_addr))
     }
 
     pub fn handle(&self) -> SocketHandle {
@@ -1,59 +1,216 @@
-use crate::socket::to_socket_addr;
-use crate::TUN;
+use crate::socket::wake;
+use crate::Tun;
 use async_std::io::{Read, Write};
-use smoltcp::socket::{SocketHandle, TcpSocket};
-use std::io;
-use std::io::Error;
+use parking_lot::Mutex;
+use smoltcp::socket::{SocketHandle, TcpSocket, TcpState};
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{Error, ErrorKind};
 use std::net::SocketAddr;
 use std::pin::Pin;
-use std::task::{Context, Poll};
-use std::time::Duration;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
 use tracing::{debug, info};
 
-#[derive(Debug, Eq, PartialEq, Hash)]
+/// Bytes buffered in each direction of a connection, on top of the buffers of smoltcp.
+const SHARD_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Data of one connection, moved between the socket and smoltcp by the tun tasks.
+///
+/// Reads and writes only lock the shard of their own connection, so copying data for one
+/// connection never waits for the netstack or for other connections.
+#[derive(Debug)]
+pub(crate) struct TcpShard {
+    rx: VecDeque<u8>,
+    tx: VecDeque<u8>,
+    rx_closed: bool,
+    tx_closed: bool,
+    /// The connection is reset by the peer or aborted.
+    reset: bool,
+    /// `poll_close` is called, a FIN is sent once `tx` is sent.
+    write_shutdown: bool,
+    /// `abort` is called, a RST is sent on the next sync.
+    abort: bool,
+    read_task: Option<Waker>,
+    write_task: Option<Waker>,
+    /// Number of `TunTcpSocket`s of this connection.
+    refs: usize,
+}
+
+impl TcpShard {
+    fn new() -> Self {
+        TcpShard {
+            rx: VecDeque::with_capacity(SHARD_BUFFER_SIZE),
+            tx: VecDeque::with_capacity(SHARD_BUFFER_SIZE),
+            rx_closed: false,
+            tx_closed: false,
+            reset: false,
+            write_shutdown: false,
+            abort: false,
+            read_task: None,
+            write_task: None,
+            refs: 1,
+        }
+    }
+
+    /// Move received data out of and data to send into `socket`, waking the tasks waiting for
+    /// them. Returns false once the shard is dropped and all its data sent, so the socket can
+    /// be released.
+    pub(crate) fn sync(&mut self, socket: &mut TcpSocket) -> bool {
+        if self.abort {
+            self.abort = false;
+            socket.abort();
+        }
+
+        let mut readable = false;
+        while socket.can_recv() && self.rx.len() < SHARD_BUFFER_SIZE {
+            let room = SHARD_BUFFER_SIZE - self.rx.len();
+            let rx = &mut self.rx;
+            match socket.recv(|data| {
+                let size = data.len().min(room);
+                rx.extend(&data[..size]);
+                (size, size)
+            }) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => readable = true,
+            }
+        }
+        if !socket.may_recv() && !self.rx_closed {
+            // A FIN leaves the socket in CLOSE-WAIT or TIME-WAIT, only a RST closes it at once.
+            self.reset = socket.state() == TcpState::Closed;
+            self.rx_closed = true;
+            readable = true;
+        }
+        if readable {
+            wake(&mut self.read_task);
+        }
+
+        let mut writable = false;
+        while socket.can_send() && !self.tx.is_empty() {
+            let tx = &mut self.tx;
+            match socket.send(|buf| {
+                let size = buf.len().min(tx.len());
+                for (b, d) in buf.iter_mut().zip(tx.drain(..size)) {
+                    *b = d;
+                }
+                (size, size)
+            }) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => writable = true,
+            }
+        }
+        if self.write_shutdown && self.tx.is_empty() {
+            socket.close();
+        }
+        if !socket.may_send() && !self.tx_closed {
+            self.tx_closed = true;
+            self.tx.clear();
+            writable = true;
+        }
+        if writable {
+            wake(&mut self.write_task);
+        }
+
+        self.refs > 0 || !self.tx.is_empty()
+    }
+}
+
 pub struct TunTcpSocket {
+    tun: Tun,
     handle: SocketHandle,
+    shard: Arc<Mutex<TcpShard>>,
+    local_addr: SocketAddr,
+    remote_addr: SocketAddr,
 }
 
 impl TunTcpSocket {
-    /// # Safety
-    ///
-    /// You need to make sure handle have at lease one reference.
-    pub unsafe fn new(handle: SocketHandle) -> Self {
+    pub(crate) fn new(
+        tun: Tun,
+        handle: SocketHandle,
+        local_addr: SocketAddr,
+        remote_addr: SocketAddr,
+    ) -> Self {
         debug!("TunTcpSocket.new: {}", handle);
 
-        TunTcpSocket { handle }
+        TunTcpSocket {
+            tun,
+            handle,
+            shard: Arc::new(Mutex::new(TcpShard::new())),
+            local_addr,
+            remote_addr,
+        }
+    }
+
+    pub(crate) fn shard(&self) -> Arc<Mutex<TcpShard>> {
+        self.shard.clone()
     }
 
     pub fn local_addr(&self) -> SocketAddr {
-        let mut guard = TUN.try_lock_for(Duration::from_secs(1)).unwrap();
-        let mut_tun = guard.as_mut().expect("no tun setup");
-        let socket = mut_tun.sockets.get::<TcpSocket>(self.handle);
-        to_socket_addr(socket.local_endpoint())
+        self.local_addr
     }
 
     pub fn remote_addr(&self) -> SocketAddr {
-        let mut guard = TUN.try_lock_for(Duration::from_secs(1)).unwrap();
-        let mut_tun = guard.as_mut().expect("no tun setup");
-        let socket = mut_tun.sockets.get::<TcpSocket>(self.handle);
-        to_socket_addr(socket.remote_endpoint())
+        self.remote_addr
     }
 
     pub fn handle(&self) -> SocketHandle {
         self.handle
     }
+
+    /// Reset the connection, data not sent yet is dropped.
+    pub fn abort(&self) {
+        debug!("TunTcpSocket.abort: {}", self.handle);
+        let mut shard = self.shard.lock();
+        shard.abort = true;
+        shard.reset = true;
+        shard.rx_closed = true;
+        shard.tx_closed = true;
+        shard.rx.clear();
+        shard.tx.clear();
+        wake(&mut shard.read_task);
+        wake(&mut shard.write_task);
+        drop(shard);
+        self.tun.notify(self.handle);
+    }
+}
+
+impl fmt::Debug for TunTcpSocket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TunTcpSocket")
+            .field("handle", &self.handle)
+            .field("local_addr", &self.local_addr)
+            .field("remote_addr", &self.remote_addr)
+            .finish()
+    }
+}
+
+impl PartialEq for TunTcpSocket {
+    fn eq(&self, other: &Self) -> bool {
+        self.handle == other.handle
+    }
+}
+
+impl Eq for TunTcpSocket {}
+
+impl Hash for TunTcpSocket {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.handle.hash(state)
+    }
 }
 
 impl Clone for TunTcpSocket {
     fn clone(&self) -> Self {
         debug!("TunTcpSocket.clone: {}", self.handle);
 
-        let mut guard = TUN.try_lock_for(Duration::from_secs(1)).unwrap();
-        let mut_tun = guard.as_mut().expect("no tun setup");
-        mut_tun.sockets.retain(self.handle);
+        self.shard.lock().refs += 1;
 
         TunTcpSocket {
+            tun: self.tun.clone(),
             handle: self.handle,
+            shard: self.shard.clone(),
+            local_addr: self.local_addr,
+            remote_addr: self.remote_addr,
         }
     }
 }
@@ -61,10 +218,12 @@ impl Clone for TunTcpSocket {
 impl Drop for TunTcpSocket {
     fn drop(&mut self) {
         debug!("TunTcpSocket.drop: {}", self.handle);
-        let mut guard = TUN.try_lock_for(Duration::from_secs(1)).unwrap();
-        let mut_tun = guard.as_mut().expect("no tun setup");
-
-        mut_tun.sockets.release(self.handle);
+        let mut shard = self.shard.lock();
+        shard.refs -= 1;
+        if shard.refs == 0 {
+            drop(shard);
+            self.tun.notify(self.handle);
+        }
     }
 }
 
@@ -74,33 +233,31 @@ impl Read for TunTcpSocket {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<Result<usize, Error>> {
-        let mut guard = TUN.try_lock_for(Duration::from_secs(1)).unwrap();
-        let mut_tun = guard.as_mut().expect("no tun setup");
-        let mut socket = mut_tun.sockets.get::<TcpSocket>(self.handle);
-        debug!("TunTcpSocket.read socket state: {}", socket.state());
-        if socket.may_recv() {
-            if socket.can_recv() {
-                let size = socket
-                    .recv_slice(buf)
-                    .map_err(|_| -> io::Error { io::ErrorKind::Other.into() })?;
-                debug!(
-                    "TunTcpSocket.read recv {} bytes",
-                    size,
-                    //                        std::str::from_utf8(buf).unwrap()
-                );
-                assert!(size > 0);
-                Poll::Ready(Ok(size))
-            } else {
-                debug!("TunTcpSocket.read will block");
-                let h = socket.handle();
-                mut_tun
-                    .socket_read_tasks
-                    .insert(h, Some(cx.waker().clone()));
-                Poll::Pending
+        let mut shard = self.shard.lock();
+        if !shard.rx.is_empty() {
+            // A full shard leaves data in smoltcp, the tun tasks need to move it over now
+            // there is room.
+            let was_full = shard.rx.len() >= SHARD_BUFFER_SIZE;
+            let size = buf.len().min(shard.rx.len());
+            for (b, d) in buf.iter_mut().zip(shard.rx.drain(..size)) {
+                *b = d;
             }
-        } else {
+            drop(shard);
+            debug!("TunTcpSocket.read recv {} bytes", size);
+            if was_full {
+                self.tun.notify(self.handle);
+            }
+            Poll::Ready(Ok(size))
+        } else if shard.reset {
+            info!("tcp socket {} is reset", self.handle);
+            Poll::Ready(Err(ErrorKind::ConnectionReset.into()))
+        } else if shard.rx_closed {
             info!("read eof for tcp socket: {}", self.handle);
             Poll::Ready(Ok(0))
+        } else {
+            debug!("TunTcpSocket.read will block");
+            shard.read_task = Some(cx.waker().clone());
+            Poll::Pending
         }
     }
 }
@@ -112,36 +269,29 @@ impl Write for TunTcpSocket {
         buf: &[u8],
     ) -> Poll<Result<usize, Error>> {
         debug!("TunTcpSocket.write");
-        let mut guard = TUN.try_lock_for(Duration::from_secs(1)).unwrap();
-        let mut_tun = guard.as_mut().expect("no tun setup");
-        let mut socket = mut_tun.sockets.get::<TcpSocket>(self.handle);
-        if socket.may_send() {
-            if socket.can_send() {
-                let size = socket
-                    .send_slice(buf)
-                    .map_err(|_| -> io::Error { io::ErrorKind::Other.into() })?;
-                debug!(
-                    "TunTcpSocket.write send {} bytes",
-                    size,
-                    // std::str::from_utf8(buf).unwrap()
-                );
-
-                if let Some(waker) = mut_tun.tun_write_task.take() {
-                    waker.wake();
-                }
-                Poll::Ready(Ok(size))
-            } else {
-                debug!("TunTcpSocket.write will block");
-                let h = socket.handle();
-                mut_tun
-                    .socket_write_tasks
-                    .insert(h, Some(cx.waker().clone()));
-                Poll::Pending
-            }
-        } else {
+        let mut shard = self.shard.lock();
+        if shard.reset {
+            return Poll::Ready(Err(ErrorKind::ConnectionReset.into()));
+        }
+        if shard.write_shutdown {
+            return Poll::Ready(Err(ErrorKind::BrokenPipe.into()));
+        }
+        if shard.tx_closed {
             info!("write eof for tcp socket: {}", self.handle);
-            Poll::Ready(Ok(0))
+            return Poll::Ready(Ok(0));
+        }
+        let room = SHARD_BUFFER_SIZE - shard.tx.len();
+        if room == 0 {
+            debug!("TunTcpSocket.write will block");
+            shard.write_task = Some(cx.waker().clone());
+            return Poll::Pending;
         }
+        let size = buf.len().min(room);
+        shard.tx.extend(&buf[..size]);
+        drop(shard);
+        debug!("TunTcpSocket.write send {} bytes", size);
+        self.tun.notify(self.handle);
+        Poll::Ready(Ok(size))
     }
 
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
@@ -149,8 +299,11 @@ impl Write for TunTcpSocket {
         Poll::Ready(Ok(()))
     }
 
+    /// Shut down the write side, the connection is still readable until the peer closes it.
     fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
-        debug!("close");
+        debug!("TunTcpSocket.close: {}", self.handle);
+        self.shard.lock().write_shutdown = true;
+        self.tun.notify(self.handle);
         Poll::Ready(Ok(()))
     }
 }
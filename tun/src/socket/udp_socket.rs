@@ -1,26 +1,114 @@
-use crate::socket::to_socket_addr;
-use crate::TUN;
+use crate::socket::buffer_pool::BufferPool;
+use crate::socket::{to_endpoint, to_socket_addr, wake};
+use crate::Tun;
 use async_std::future::poll_fn;
+use bytes::BytesMut;
+use parking_lot::Mutex;
 use smoltcp::socket::{SocketHandle, UdpSocket};
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io;
 use std::io::Result;
 use std::net::SocketAddr;
-use std::task::{Context, Poll};
-use std::time::Duration;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
 use tracing::debug;
 
-#[derive(Debug, Eq, PartialEq, Hash)]
+/// Packets buffered in each direction, on top of the buffers of smoltcp.
+const SHARD_PACKETS: usize = 64;
+
+/// Packets of one udp socket, moved between the socket and smoltcp by the tun tasks.
+#[derive(Debug)]
+pub(crate) struct UdpShard {
+    rx: VecDeque<(BytesMut, SocketAddr)>,
+    tx: VecDeque<(BytesMut, SocketAddr)>,
+    /// Packets are copied into buffers of the pool, returned once the packet is consumed.
+    pool: Arc<BufferPool>,
+    read_task: Option<Waker>,
+    write_task: Option<Waker>,
+    /// Number of `TunUdpSocket`s of this socket.
+    refs: usize,
+}
+
+impl UdpShard {
+    fn new(pool: Arc<BufferPool>) -> Self {
+        UdpShard {
+            rx: VecDeque::with_capacity(SHARD_PACKETS),
+            tx: VecDeque::with_capacity(SHARD_PACKETS),
+            pool,
+            read_task: None,
+            write_task: None,
+            refs: 1,
+        }
+    }
+
+    /// Move received packets out of and packets to send into `socket`, waking the tasks waiting
+    /// for them. Returns false once the shard is dropped and all its packets sent, so the socket
+    /// can be released.
+    pub(crate) fn sync(&mut self, socket: &mut UdpSocket) -> bool {
+        let mut readable = false;
+        while socket.can_recv() && self.rx.len() < SHARD_PACKETS {
+            match socket.recv() {
+                Ok((data, endpoint)) => {
+                    let mut buf = self.pool.get();
+                    buf.extend_from_slice(data);
+                    self.rx.push_back((buf, to_socket_addr(endpoint)));
+                    readable = true;
+                }
+                Err(_) => break,
+            }
+        }
+        if readable {
+            wake(&mut self.read_task);
+        }
+
+        let mut writable = false;
+        while socket.can_send() {
+            let (data, addr) = match self.tx.pop_front() {
+                Some(packet) => packet,
+                None => break,
+            };
+            match socket.send_slice(&data, to_endpoint(addr)) {
+                Ok(()) => {}
+                Err(smoltcp::Error::Exhausted) => {
+                    self.tx.push_front((data, addr));
+                    break;
+                }
+                Err(e) => debug!("TunUdpSocket drops packet to {}: {}", addr, e),
+            }
+            self.pool.put(data);
+            writable = true;
+        }
+        if writable {
+            wake(&mut self.write_task);
+        }
+
+        self.refs > 0 || !self.tx.is_empty()
+    }
+}
+
 pub struct TunUdpSocket {
+    tun: Tun,
     handle: SocketHandle,
+    shard: Arc<Mutex<UdpShard>>,
+    local_addr: SocketAddr,
 }
 
 impl TunUdpSocket {
-    /// # Safety
-    ///
-    /// You need to make sure handle have at lease one reference.
-    pub unsafe fn new(handle: SocketHandle) -> Self {
+    pub(crate) fn new(tun: Tun, handle: SocketHandle, local_addr: SocketAddr) -> Self {
         debug!("TunUdpSocket.new: {}", handle);
-        TunUdpSocket { handle }
+        let shard = Arc::new(Mutex::new(UdpShard::new(tun.udp_buffers())));
+        TunUdpSocket {
+            tun,
+            handle,
+            shard,
+            local_addr,
+        }
+    }
+
+    pub(crate) fn shard(&self) -> Arc<Mutex<UdpShard>> {
+        self.shard.clone()
     }
 
     pub fn handle(&self) -> SocketHandle {
@@ -28,34 +116,51 @@ impl TunUdpSocket {
     }
 
     pub fn local_addr(&self) -> SocketAddr {
-        let mut guard = TUN.try_lock_for(Duration::from_secs(1)).unwrap();
-        let mut_tun = guard.as_mut().expect("no tun setup");
-        let socket = mut_tun.sockets.get::<UdpSocket>(self.handle);
-        to_socket_addr(socket.endpoint())
+        self.local_addr
     }
 
+    /// Receive a packet into `buf`. A packet larger than `buf` is dropped with an `InvalidInput`
+    /// error instead of being truncated.
     pub fn poll_recv_from(
         &self,
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<Result<(usize, SocketAddr)>> {
         debug!("TunUdpSocket.read");
-        let mut guard = TUN.try_lock_for(Duration::from_secs(1)).unwrap();
-        let mut_tun = guard.as_mut().expect("no tun setup");
-        let mut socket = mut_tun.sockets.get::<UdpSocket>(self.handle);
-        if socket.can_recv() {
-            let (size, endpoint) = socket
-                .recv_slice(buf)
-                .map_err(|_| -> io::Error { io::ErrorKind::Other.into() })?;
-            debug!("TunUdpSocket.read {} bytes", size);
-            Poll::Ready(Ok((size, to_socket_addr(endpoint))))
-        } else {
-            let h = socket.handle();
-            mut_tun
-                .socket_read_tasks
-                .insert(h, Some(cx.waker().clone()));
-            debug!("TunUdpSocket.read blocks: {:?}", h);
-            Poll::Pending
+        let mut shard = self.shard.lock();
+        let was_full = shard.rx.len() >= SHARD_PACKETS;
+        let packet = shard.rx.pop_front();
+        match packet {
+            Some((data, addr)) => {
+                let size = data.len();
+                let fits = size <= buf.len();
+                if fits {
+                    buf[..size].copy_from_slice(&data);
+                }
+                shard.pool.put(data);
+                drop(shard);
+                if was_full {
+                    self.tun.notify(self.handle);
+                }
+                if !fits {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "udp packet of {} bytes from {} exceeds the buffer of {} bytes",
+                            size,
+                            addr,
+                            buf.len()
+                        ),
+                    )));
+                }
+                debug!("TunUdpSocket.read {} bytes", size);
+                Poll::Ready(Ok((size, addr)))
+            }
+            None => {
+                shard.read_task = Some(cx.waker().clone());
+                debug!("TunUdpSocket.read blocks: {:?}", self.handle);
+                Poll::Pending
+            }
         }
     }
 
@@ -66,28 +171,17 @@ impl TunUdpSocket {
         target: &SocketAddr,
     ) -> Poll<Result<usize>> {
         debug!("TunUdpSocket.write");
-        let mut guard = TUN.try_lock_for(Duration::from_secs(1)).unwrap();
-        let mut_tun = guard.as_mut().expect("no tun setup");
-        let mut socket = mut_tun.sockets.get::<UdpSocket>(self.handle);
-        if socket.can_send() {
-            let endpoint = match target {
-                SocketAddr::V4(addr) => addr.clone().into(),
-                SocketAddr::V6(_) => unreachable!(),
-            };
-            socket
-                .send_slice(buf, endpoint)
-                .map_err(|_e| -> io::Error { io::ErrorKind::Other.into() })?;
-            if let Some(waker) = mut_tun.tun_write_task.take() {
-                waker.wake();
-            }
-            Poll::Ready(Ok(buf.len()))
-        } else {
-            let h = socket.handle();
-            mut_tun
-                .socket_write_tasks
-                .insert(h, Some(cx.waker().clone()));
-            Poll::Pending
+        let mut shard = self.shard.lock();
+        if shard.tx.len() >= SHARD_PACKETS {
+            shard.write_task = Some(cx.waker().clone());
+            return Poll::Pending;
         }
+        let mut data = shard.pool.get();
+        data.extend_from_slice(buf);
+        shard.tx.push_back((data, *target));
+        drop(shard);
+        self.tun.notify(self.handle);
+        Poll::Ready(Ok(buf.len()))
     }
 
     pub async fn send_to(&self, buf: &[u8], addr: &SocketAddr) -> io::Result<usize> {
@@ -99,13 +193,37 @@ impl TunUdpSocket {
     }
 }
 
+impl fmt::Debug for TunUdpSocket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TunUdpSocket")
+            .field("handle", &self.handle)
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+impl PartialEq for TunUdpSocket {
+    fn eq(&self, other: &Self) -> bool {
+        self.handle == other.handle
+    }
+}
+
+impl Eq for TunUdpSocket {}
+
+impl Hash for TunUdpSocket {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.handle.hash(state)
+    }
+}
+
 impl Clone for TunUdpSocket {
     fn clone(&self) -> Self {
-        let mut guard = TUN.try_lock_for(Duration::from_secs(1)).unwrap();
-        let mut_tun = guard.as_mut().expect("no tun setup");
-        mut_tun.sockets.retain(self.handle);
+        self.shard.lock().refs += 1;
         TunUdpSocket {
+            tun: self.tun.clone(),
             handle: self.handle,
+            shard: self.shard.clone(),
+            local_addr: self.local_addr,
         }
     }
 }
@@ -113,8 +231,11 @@ impl Clone for TunUdpSocket {
 impl Drop for TunUdpSocket {
     fn drop(&mut self) {
         debug!("TunUdpSocket.drop: {}", self.handle);
-        let mut guard = TUN.try_lock_for(Duration::from_secs(1)).unwrap();
-        let mut_tun = guard.as_mut().expect("no tun setup");
-        mut_tun.sockets.release(self.handle)
+        let mut shard = self.shard.lock();
+        shard.refs -= 1;
+        if shard.refs == 0 {
+            drop(shard);
+            self.tun.notify(self.handle);
+        }
     }
 }